@@ -6,16 +6,16 @@ use chai::objectives::目标函数;
 use chai::operators::default::默认操作;
 use chai::{命令行, 错误};
 use criterion::{criterion_group, criterion_main, Criterion};
-use rustc_hash::FxHashMap;
+use std::sync::Arc;
 
 fn 计时(数据: 数据, 名称: &str, b: &mut Criterion) -> Result<(), 错误> {
     let mut 编码器 = 默认编码器::新建(&数据)?;
     let mut 目标函数 = 默认目标函数::新建(&数据)?;
-    let 操作 = 默认操作::新建(&数据)?;
+    let mut 操作 = 默认操作::新建(&数据)?;
     b.bench_function(名称, |b| {
         b.iter(|| {
-            let mut 映射 = 数据.初始映射.clone();
-            let 模拟移动的元素 = 操作.有约束的随机移动(&mut 映射, &FxHashMap::default(), &FxHashMap::default(), 0.0);
+            let mut 映射 = 数据.初始映射.to_vec();
+            let 模拟移动的元素 = 操作.有约束的随机移动(&mut 映射, None, 0.0, &mut vec![]);
             let mut 编码结果 = 编码器.编码(&映射, &Some(模拟移动的元素));
             目标函数.计算(&mut 编码结果, &映射, 0.0);
         })
@@ -30,13 +30,19 @@ fn 四码定长字词(b: &mut Criterion) {
 
 fn 四码定长单字(b: &mut Criterion) {
     let mut 数据 = 命令行::读取("米十五笔");
-    数据.词列表 = 数据
-        .词列表
-        .into_iter()
-        .filter(|x| x.名称.chars().count() == 1)
-        .collect();
-    数据
-        .配置
+    数据.词列表 = Arc::new(
+        数据
+            .词列表
+            .iter()
+            .filter(|x| x.名称.chars().count() == 1)
+            .cloned()
+            .collect(),
+    );
+    数据.包含元素的词 = Arc::new(数据::预处理包含元素的词(
+        &数据.词列表,
+        数据.元素转数字.len() + 1,
+    ));
+    Arc::make_mut(&mut 数据.配置)
         .optimization
         .as_mut()
         .unwrap()
@@ -0,0 +1,188 @@
+//! 方案对比：在同一份拆分表上比较两个方案的元素布局、编码和评测指标
+//!
+//! 两个方案分别编码和评测，然后按元素名称比较布局，按拆分表中的顺序逐项比较全码和简码，按全码统计新增和消除的重码，最后逐项比较评测指标。
+
+use crate::data::{码表项, 数据};
+use crate::encoders::新建编码器;
+use crate::objectives::default::默认目标函数;
+use crate::objectives::metric::{指标差异, 比较指标, 默认指标};
+use crate::objectives::目标函数;
+use crate::错误;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Display;
+
+/// 报告中每一类变化最多显示的条数，完整的结果见输出的 YAML 文件
+const 最大显示条数: usize = 100;
+
+/// 一个元素在两个方案中所在的键，只在其中一个方案中出现的元素另一侧为空
+#[derive(Debug, Clone, Serialize)]
+pub struct 元素变化 {
+    pub element: String,
+    pub old: Option<char>,
+    pub new: Option<char>,
+}
+
+/// 一个字词在两个方案中的全码和简码
+#[derive(Debug, Clone, Serialize)]
+pub struct 编码变化 {
+    pub name: String,
+    pub old_full: String,
+    pub new_full: String,
+    pub old_short: String,
+    pub new_short: String,
+}
+
+/// 两个全码相同的字词
+#[derive(Debug, Clone, Serialize)]
+pub struct 重码变化 {
+    pub words: [String; 2],
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct 方案差异 {
+    pub elements: Vec<元素变化>,
+    pub codes: Vec<编码变化>,
+    pub new_collisions: Vec<重码变化>,
+    pub resolved_collisions: Vec<重码变化>,
+    pub metrics: Vec<指标差异>,
+}
+
+fn 编码并评测(数据: &数据) -> Result<(Vec<码表项>, 默认指标), 错误> {
+    let mut 编码器 = 新建编码器(数据)?;
+    let mut 目标函数 = 默认目标函数::新建(数据)?;
+    let mut 编码结果 = 编码器.编码(&数据.初始映射, &None).clone();
+    let 码表 = 数据.生成码表(&编码结果);
+    let (指标, _) = 目标函数.计算(&mut 编码结果, &数据.初始映射, 0.0);
+    Ok((码表, 指标))
+}
+
+/// 元素名称到所在键的映射，不包含代表按键本身的元素
+fn 布局(数据: &数据) -> BTreeMap<String, char> {
+    (数据.进制 as usize..数据.初始映射.len())
+        .map(|x| (数据.数字转元素[&x].clone(), 数据.数字转键[&数据.初始映射[x]]))
+        .collect()
+}
+
+/// 所有全码相同的字词对，以全码区分；同名的字词（如多音字）不算作重码
+fn 重码集合(码表: &[码表项]) -> BTreeMap<(String, String), String> {
+    let mut 全码分组: HashMap<&str, Vec<&str>> = HashMap::new();
+    for 码表项 { name, full, .. } in 码表 {
+        全码分组.entry(full).or_default().push(name);
+    }
+    let mut 集合 = BTreeMap::new();
+    for (全码, 字词列表) in 全码分组 {
+        for (i, 甲) in 字词列表.iter().enumerate() {
+            for 乙 in &字词列表[i + 1..] {
+                if 甲 != 乙 {
+                    let 键 = if 甲 < 乙 { (甲, 乙) } else { (乙, 甲) };
+                    集合.insert((键.0.to_string(), 键.1.to_string()), 全码.to_string());
+                }
+            }
+        }
+    }
+    集合
+}
+
+fn 重码差集(
+    甲: &BTreeMap<(String, String), String>,
+    乙: &BTreeMap<(String, String), String>,
+) -> Vec<重码变化> {
+    甲.iter()
+        .filter(|(字词, _)| !乙.contains_key(*字词))
+        .map(|((a, b), code)| 重码变化 {
+            words: [a.clone(), b.clone()],
+            code: code.clone(),
+        })
+        .collect()
+}
+
+/// 分别编码和评测两个方案，然后比较它们的差异；两个方案必须使用同一份拆分表
+pub fn 比较方案(旧数据: &数据, 新数据: &数据) -> Result<方案差异, 错误> {
+    let (旧码表, 旧指标) = 编码并评测(旧数据)?;
+    let (新码表, 新指标) = 编码并评测(新数据)?;
+    if 旧码表.len() != 新码表.len()
+        || 旧码表.iter().zip(&新码表).any(|(x, y)| x.name != y.name)
+    {
+        return Err("两个方案的拆分表不一致，无法比较".into());
+    }
+    let 旧布局 = 布局(旧数据);
+    let 新布局 = 布局(新数据);
+    let 元素列表: BTreeSet<&String> = 旧布局.keys().chain(新布局.keys()).collect();
+    let elements = 元素列表
+        .into_iter()
+        .filter_map(|x| {
+            let (old, new) = (旧布局.get(x).copied(), 新布局.get(x).copied());
+            (old != new).then(|| 元素变化 {
+                element: x.clone(),
+                old,
+                new,
+            })
+        })
+        .collect();
+    let codes = 旧码表
+        .iter()
+        .zip(&新码表)
+        .filter(|(x, y)| x.full != y.full || x.short != y.short)
+        .map(|(x, y)| 编码变化 {
+            name: x.name.clone(),
+            old_full: x.full.clone(),
+            new_full: y.full.clone(),
+            old_short: x.short.clone(),
+            new_short: y.short.clone(),
+        })
+        .collect();
+    let 旧重码 = 重码集合(&旧码表);
+    let 新重码 = 重码集合(&新码表);
+    Ok(方案差异 {
+        elements,
+        codes,
+        new_collisions: 重码差集(&新重码, &旧重码),
+        resolved_collisions: 重码差集(&旧重码, &新重码),
+        metrics: 比较指标(&旧指标, &新指标, 1e-12),
+    })
+}
+
+fn 输出列表<T>(
+    f: &mut std::fmt::Formatter<'_>,
+    标题: &str,
+    列表: &[T],
+    格式化: impl Fn(&T) -> String,
+) -> std::fmt::Result {
+    writeln!(f, "{}（{}）：", 标题, 列表.len())?;
+    for 项 in 列表.iter().take(最大显示条数) {
+        writeln!(f, "  {}", 格式化(项))?;
+    }
+    if 列表.len() > 最大显示条数 {
+        writeln!(f, "  …… 另有 {} 项", 列表.len() - 最大显示条数)?;
+    }
+    Ok(())
+}
+
+impl Display for 方案差异 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let 键 = |x: &Option<char>| x.map_or("无".to_string(), |x| x.to_string());
+        输出列表(f, "元素变化", &self.elements, |x| {
+            format!("{}：{} → {}", x.element, 键(&x.old), 键(&x.new))
+        })?;
+        输出列表(f, "编码变化", &self.codes, |x| {
+            format!(
+                "{}：全码 {} → {}；简码 {} → {}",
+                x.name, x.old_full, x.new_full, x.old_short, x.new_short
+            )
+        })?;
+        输出列表(f, "新增重码", &self.new_collisions, |x| {
+            format!("{} {}：{}", x.words[0], x.words[1], x.code)
+        })?;
+        输出列表(f, "消除重码", &self.resolved_collisions, |x| {
+            format!("{} {}：{}", x.words[0], x.words[1], x.code)
+        })?;
+        输出列表(f, "指标变化", &self.metrics, |x| {
+            match (x.old.as_f64(), x.new.as_f64()) {
+                (Some(old), Some(new)) => format!("{}（{:+.6}）", x, new - old),
+                _ => x.to_string(),
+            }
+        })
+    }
+}
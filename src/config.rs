@@ -3,10 +3,15 @@
 //! 这部分内容太多，就不一一注释了。后期会写一个「`config.yaml` 详解」来统一解释各种配置文件的字段。
 //!
 
-use crate::optimizers::simulated_annealing::退火方法;
+use crate::operators::default::{变异配置, DEFAULT_MUTATE};
+use crate::optimizers::{
+    great_deluge::大洪水方法, large_neighborhood::大邻域搜索方法, late_acceptance::延迟接受方法,
+    simulated_annealing::退火方法, threshold_accepting::门槛接受方法,
+};
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use std::collections::HashMap;
+use std::num::NonZeroUsize;
 
 // config.info begin
 #[skip_serializing_none]
@@ -191,6 +196,14 @@ pub struct EncoderConfig {
     // 简码
     pub short_code: Option<Vec<ShortCodeConfig>>,
     pub priority_short_codes: Option<Vec<(String, String, usize)>>,
+    // 顶功
+    pub top_up: Option<TopUpConfig>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopUpConfig {
+    pub leading_keys: Vec<char>,
 }
 
 #[skip_serializing_none]
@@ -275,6 +288,8 @@ pub struct TierWeights {
     pub duplication: Option<f64>,
     pub levels: Option<Vec<LevelWeights>>,
     pub fingering: Option<FingeringWeights>,
+    pub key_distribution: Option<f64>,
+    pub pair_equivalence: Option<f64>,
 }
 
 // let types = ["同手", "大跨", "小跨", "干扰", "错手", "三连", "备用", "备用"];
@@ -290,6 +305,8 @@ pub struct PartialWeights {
     pub extended_pair_equivalence: Option<f64>,
     pub fingering: Option<FingeringWeights>,
     pub levels: Option<Vec<LevelWeights>>,
+    pub selection: Option<f64>,
+    pub selection_costs: Option<Vec<f64>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -332,6 +349,41 @@ pub struct ObjectiveConfig {
     pub characters_short: Option<PartialWeights>,
     pub words_short: Option<PartialWeights>,
     pub regularization: Option<Regularization>,
+    pub soft_constraints: Option<SoftConstraints>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Finger {
+    Thumb,
+    Index,
+    Middle,
+    Ring,
+    Pinky,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreferredKeys {
+    pub element: ElementWithIndex,
+    pub keys: Vec<char>,
+    pub weight: f64,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForbiddenPositions {
+    pub elements: Option<Vec<ElementWithIndex>>,
+    pub rows: Option<Vec<usize>>,
+    pub fingers: Option<Vec<Finger>>,
+    pub weight: f64,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SoftConstraints {
+    pub preferred_keys: Option<Vec<PreferredKeys>>,
+    pub forbidden_positions: Option<Vec<ForbiddenPositions>>,
+    pub keep_initial: Option<f64>,
 }
 
 #[skip_serializing_none]
@@ -348,6 +400,44 @@ pub struct ConstraintsConfig {
     pub elements: Option<Vec<AtomicConstraint>>,
     pub indices: Option<Vec<AtomicConstraint>>,
     pub element_indices: Option<Vec<AtomicConstraint>>,
+    pub relations: Option<Vec<RelationConstraint>>,
+    pub capacities: Option<Vec<CapacityConstraint>>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapacityConstraint {
+    pub keys: Option<Vec<char>>,
+    pub elements: Option<Vec<String>>,
+    pub index: Option<usize>,
+    pub max: usize,
+    pub penalty: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Hand {
+    Left,
+    Right,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RelationConstraint {
+    SameKey {
+        elements: Vec<ElementWithIndex>,
+    },
+    DifferentKey {
+        elements: Vec<ElementWithIndex>,
+    },
+    SameRow {
+        elements: Vec<ElementWithIndex>,
+    },
+    SameHand {
+        elements: Vec<ElementWithIndex>,
+        hand: Option<Hand>,
+    },
 }
 
 #[skip_serializing_none]
@@ -355,7 +445,43 @@ pub struct ConstraintsConfig {
 #[serde(tag = "algorithm")]
 pub enum SolverConfig {
     SimulatedAnnealing(退火方法),
-    // TODO: Add more algorithms
+    LateAcceptance(延迟接受方法),
+    ThresholdAccepting(门槛接受方法),
+    GreatDeluge(大洪水方法),
+    LargeNeighborhood(大邻域搜索方法),
+}
+
+impl SolverConfig {
+    pub fn 变异配置(&self) -> Option<变异配置> {
+        match self {
+            Self::SimulatedAnnealing(x) => x.search_method,
+            Self::LateAcceptance(x) => x.search_method,
+            Self::ThresholdAccepting(x) => x.search_method,
+            Self::GreatDeluge(x) => x.search_method,
+            // 大邻域搜索不做随机变异，只借用变异配置来开启冲突引导
+            Self::LargeNeighborhood(x) => Some(变异配置 {
+                conflict_guidance: Some(x.conflict_guidance.unwrap_or(true)),
+                ..DEFAULT_MUTATE
+            }),
+        }
+    }
+
+    /// 用命令行参数覆盖方案文件中的校验间隔和时间限制
+    pub fn 覆盖(&mut self, 校验间隔: Option<NonZeroUsize>, 时间限制: Option<f64>) {
+        let (verify_interval, time_limit) = match self {
+            Self::SimulatedAnnealing(x) => (&mut x.verify_interval, &mut x.time_limit),
+            Self::LateAcceptance(x) => (&mut x.verify_interval, &mut x.time_limit),
+            Self::ThresholdAccepting(x) => (&mut x.verify_interval, &mut x.time_limit),
+            Self::GreatDeluge(x) => (&mut x.verify_interval, &mut x.time_limit),
+            Self::LargeNeighborhood(x) => (&mut x.verify_interval, &mut x.time_limit),
+        };
+        if 校验间隔.is_some() {
+            *verify_interval = 校验间隔;
+        }
+        if 时间限制.is_some() {
+            *time_limit = 时间限制;
+        }
+    }
 }
 
 #[skip_serializing_none]
@@ -436,6 +562,7 @@ impl Default for 配置 {
                 rules: None,
                 short_code: None,
                 priority_short_codes: None,
+                top_up: None,
             },
             optimization: None,
             diagram: None,
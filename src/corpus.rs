@@ -0,0 +1,587 @@
+//! 语料评测：将真实文本按码表切分，模拟用户的实际输入过程
+//!
+//! 基于频率表的指标把每个可编码对象看作是孤立的，而用户实际输入的是连续的文本。这里将语料切分为码表中的字词，把它们的实际编码（包括空格、选择键）首尾相接得到完整的按键序列，再在这个序列上统计击键数、选择键、当量和差指法等指标。
+
+use crate::data::{原始可编码对象, 数据, 最大词长, 编码信息, 键};
+use crate::objectives::metric::{FingeringMetric, 指法标记, 指法标记名称};
+use clap::ValueEnum;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// 语料的切分方式
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum 分词方式 {
+    /// 正向最大匹配：每次取词典中能匹配的最长的词
+    #[default]
+    ForwardMaximum,
+    /// 最少击键：用动态规划找出总击键数最少的切分
+    MinimumKeystrokes,
+}
+
+/// 切分的结果，要么是词典中的一个词，要么是一个无法识别的字符
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum 片段 {
+    词 { 序号: usize, 长度: usize },
+    未知(char),
+}
+
+/// 字典树的节点，序号为以这个节点结尾的词在词列表中的序号
+#[derive(Default)]
+struct 节点 {
+    子节点: FxHashMap<char, usize>,
+    序号: Option<usize>,
+}
+
+/// 用于切分文本的词典，以字典树的形式存储，将词映射为它在词列表中的序号
+pub struct 词典 {
+    节点列表: Vec<节点>,
+}
+
+impl 词典 {
+    /// 由词列表构造词典，如果有重复的词，只保留第一次出现的
+    pub fn 新建<'a>(词列表: impl Iterator<Item = &'a str>) -> Self {
+        let mut 节点列表 = vec![节点::default()];
+        for (序号, 词) in 词列表.enumerate() {
+            let 长度 = 词.chars().count();
+            if 长度 == 0 || 长度 > 最大词长 {
+                continue;
+            }
+            let mut 当前 = 0;
+            for 字符 in 词.chars() {
+                let 新节点 = 节点列表.len();
+                当前 = *节点列表[当前].子节点.entry(字符).or_insert(新节点);
+                if 当前 == 新节点 {
+                    节点列表.push(节点::default());
+                }
+            }
+            节点列表[当前].序号.get_or_insert(序号);
+        }
+        Self { 节点列表 }
+    }
+
+    /// 词在词列表中的序号，重复的词返回第一次出现的序号
+    pub fn 查找(&self, 词: &str) -> Option<usize> {
+        let mut 当前 = 0;
+        for 字符 in 词.chars() {
+            当前 = *self.节点列表[当前].子节点.get(&字符)?;
+        }
+        self.节点列表[当前].序号
+    }
+
+    /// 词列表中出现在词典里的最大序号加一
+    fn 序号上限(&self) -> usize {
+        self.节点列表.iter().filter_map(|x| x.序号).max().map_or(0, |x| x + 1)
+    }
+
+    /// 沿字典树查找从某个位置开始的所有词，按长度从短到长返回词的序号和长度
+    fn 匹配<'a>(&'a self, 文本: &'a [char], 起点: usize) -> impl Iterator<Item = (usize, usize)> + 'a {
+        文本[起点..]
+            .iter()
+            .scan(0, move |当前, 字符| {
+                *当前 = *self.节点列表[*当前].子节点.get(字符)?;
+                Some(*当前)
+            })
+            .enumerate()
+            .filter_map(move |(i, 当前)| self.节点列表[当前].序号.map(|序号| (序号, i + 1)))
+    }
+
+    pub fn 正向最大匹配(&self, 文本: &[char]) -> Vec<片段> {
+        let mut 结果 = vec![];
+        let mut 起点 = 0;
+        while 起点 < 文本.len() {
+            match self.匹配(文本, 起点).last() {
+                Some((序号, 长度)) => {
+                    结果.push(片段::词 { 序号, 长度 });
+                    起点 += 长度;
+                }
+                None => {
+                    结果.push(片段::未知(文本[起点]));
+                    起点 += 1;
+                }
+            }
+        }
+        结果
+    }
+
+    /// 给定每个词的代价，用动态规划找出总代价最小的切分；无法识别的字符不计代价
+    pub fn 最小代价切分(&self, 文本: &[char], 代价: impl Fn(usize) -> f64) -> Vec<片段> {
+        let n = 文本.len();
+        let mut 最小代价 = vec![f64::INFINITY; n + 1];
+        let mut 来源: Vec<Option<片段>> = vec![None; n + 1];
+        最小代价[0] = 0.0;
+        for 起点 in 0..n {
+            if 最小代价[起点].is_infinite() {
+                continue;
+            }
+            let mut 有匹配 = false;
+            for (序号, 长度) in self.匹配(文本, 起点) {
+                有匹配 = true;
+                let 新代价 = 最小代价[起点] + 代价(序号);
+                if 新代价 < 最小代价[起点 + 长度] {
+                    最小代价[起点 + 长度] = 新代价;
+                    来源[起点 + 长度] = Some(片段::词 { 序号, 长度 });
+                }
+            }
+            if !有匹配 && 最小代价[起点] < 最小代价[起点 + 1] {
+                最小代价[起点 + 1] = 最小代价[起点];
+                来源[起点 + 1] = Some(片段::未知(文本[起点]));
+            }
+        }
+        let mut 结果 = vec![];
+        let mut 终点 = n;
+        while 终点 > 0 {
+            let 片段 = 来源[终点].unwrap(); // 每个位置都至少可以由前一个位置到达
+            终点 -= match 片段 {
+                片段::词 { 长度, .. } => 长度,
+                片段::未知(_) => 1,
+            };
+            结果.push(片段);
+        }
+        结果.reverse();
+        结果
+    }
+}
+
+/// 在语料上统计的各项指标
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct 语料指标 {
+    pub characters: u64,
+    pub unknown_characters: u64,
+    pub segments: u64,
+    pub keystrokes: u64,
+    pub keystrokes_per_character: f64,
+    /// 按下第一个选择键以外的选择键的次数，即选重的次数
+    pub selections: u64,
+    pub selections_per_character: f64,
+    pub pair_equivalence: f64,
+    pub fingering: FingeringMetric,
+}
+
+impl Display for 语料指标 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "字数：{}；未识别：{}；词数：{}；\n",
+            self.characters, self.unknown_characters, self.segments
+        ))?;
+        f.write_str(&format!(
+            "总击键：{}；字均击键：{:.4}；选重：{}；字均选重：{:.4}；\n",
+            self.keystrokes,
+            self.keystrokes_per_character,
+            self.selections,
+            self.selections_per_character
+        ))?;
+        f.write_str(&format!("组合当量：{:.4}；", self.pair_equivalence))?;
+        for (index, percent) in self.fingering.iter().enumerate() {
+            if let Some(percent) = percent {
+                f.write_str(&format!(
+                    "{}：{:.2}%；",
+                    指法标记名称[index],
+                    percent * 100.0
+                ))?;
+            }
+        }
+        f.write_str("\n")
+    }
+}
+
+/// 按键流中的一次击键，记录它是否为一个编码的第一个键
+#[derive(Debug, Clone, Copy)]
+pub struct 击键 {
+    pub 键: 键,
+    pub 首键: bool,
+}
+
+/// 根据编码结果生成每个词的按键序列，用于切分和评测语料
+pub struct 语料评测器<'a> {
+    数据: &'a 数据,
+    词典: 词典,
+    按键序列: Vec<Vec<键>>,
+    指法标记: 指法标记,
+}
+
+impl<'a> 语料评测器<'a> {
+    /// 用户输入的是简码（如果有的话），而简码的实际编码已经包含了全码作为后备
+    ///
+    /// 编码器在计算全码的实际编码时统一视为首选，所以这里要为非首选的全码补上相应的选择键
+    pub fn 新建(数据: &'a 数据, 编码结果: &[编码信息]) -> Self {
+        let 词典 = 词典::新建(数据.词列表.iter().map(|x| x.名称.as_str()));
+        let 按键序列 = 编码结果
+            .iter()
+            .map(|x| {
+                if x.简码.实际编码 != 0 {
+                    return Self::拆分编码(x.简码.实际编码, 数据.进制);
+                }
+                let mut 按键 = Self::拆分编码(x.全码.原始编码, 数据.进制);
+                let 候选位置 = x.全码.原始编码候选位置 as usize;
+                if 候选位置 == 0 {
+                    return Self::拆分编码(x.全码.实际编码, 数据.进制);
+                }
+                按键.push(*数据.选择键.get(候选位置).unwrap_or(&数据.选择键[0]));
+                按键
+            })
+            .collect();
+        Self {
+            数据,
+            词典,
+            按键序列,
+            指法标记: 指法标记::new(),
+        }
+    }
+
+    fn 拆分编码(编码: u64, 进制: u64) -> Vec<键> {
+        let mut 按键 = vec![];
+        let mut 剩余 = 编码;
+        while 剩余 > 0 {
+            let 键 = 剩余 % 进制;
+            if 键 != 0 {
+                按键.push(键);
+            }
+            剩余 /= 进制;
+        }
+        按键
+    }
+
+    pub fn 切分(&self, 文本: &str, 方式: 分词方式) -> Vec<片段> {
+        let 字符: Vec<char> = 文本.chars().collect();
+        match 方式 {
+            分词方式::ForwardMaximum => self.词典.正向最大匹配(&字符),
+            分词方式::MinimumKeystrokes => self
+                .词典
+                .最小代价切分(&字符, |序号| self.按键序列[序号].len() as f64),
+        }
+    }
+
+    /// 把切分结果转换为连续的按键序列，无法识别的字符会打断按键序列
+    pub fn 生成按键流(&self, 切分结果: &[片段]) -> Vec<Vec<击键>> {
+        let mut 按键流 = vec![vec![]];
+        for 片段 in 切分结果 {
+            match 片段 {
+                片段::词 { 序号, .. } => {
+                    let 当前 = 按键流.last_mut().unwrap();
+                    for (i, 键) in self.按键序列[*序号].iter().enumerate() {
+                        当前.push(击键 {
+                            键: *键,
+                            首键: i == 0,
+                        });
+                    }
+                }
+                片段::未知(_) => {
+                    if !按键流.last().unwrap().is_empty() {
+                        按键流.push(vec![]);
+                    }
+                }
+            }
+        }
+        按键流
+    }
+
+    fn 统计字数(切分结果: &[片段], 指标: &mut 语料指标) {
+        for 片段 in 切分结果 {
+            match 片段 {
+                片段::词 { 长度, .. } => {
+                    指标.characters += *长度 as u64;
+                    指标.segments += 1;
+                }
+                片段::未知(字符) => {
+                    if !字符.is_whitespace() {
+                        指标.unknown_characters += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// 两键组合的当量
+    fn 双键当量(&self, 键一: 键, 键二: 键) -> f64 {
+        let 下标 = (键一 + 键二 * self.数据.进制) as usize;
+        *self.数据.组合当量信息.get(下标).unwrap_or(&0.0)
+    }
+
+    /// 三键组合在两个两键组合之外额外的当量
+    ///
+    /// 三键编码的预计算当量包含了其中两个两键组合以及这个三键组合本身，减去前者即得到后者
+    fn 三键当量(&self, 键一: 键, 键二: 键, 键三: 键) -> f64 {
+        let 进制 = self.数据.进制;
+        let 下标 = (键一 + 键二 * 进制 + 键三 * 进制 * 进制) as usize;
+        match self.数据.组合当量信息.get(下标) {
+            Some(当量) => 当量 - self.双键当量(键一, 键二) - self.双键当量(键二, 键三),
+            None => 0.0,
+        }
+    }
+
+    pub fn 评测(&self, 文本: &str, 方式: 分词方式) -> 语料指标 {
+        let 切分结果 = self.切分(文本, 方式);
+        let mut 指标 = 语料指标::default();
+        Self::统计字数(&切分结果, &mut 指标);
+        let mut 组合数 = 0;
+        let mut 总当量 = 0.0;
+        let mut 指法计数 = [0_u64; 8];
+        for 按键流 in self.生成按键流(&切分结果) {
+            let 按键序列: Vec<键> = 按键流.iter().map(|x| x.键).collect();
+            指标.keystrokes += 按键序列.len() as u64;
+            // 第一个选择键只是上屏首选，不算选重
+            let 重选键 = self.数据.选择键.get(1..).unwrap_or_default();
+            指标.selections += 按键序列.iter().filter(|x| 重选键.contains(x)).count() as u64;
+            let 字符序列: Vec<char> = 按键序列.iter().map(|x| self.数据.数字转键[x]).collect();
+            for (i, 组合) in 按键序列.windows(2).enumerate() {
+                组合数 += 1;
+                总当量 += self.双键当量(组合[0], 组合[1]);
+                let 字符组合 = (字符序列[i], 字符序列[i + 1]);
+                let 标记 = &self.指法标记;
+                for (j, 集合) in [
+                    &标记.同手,
+                    &标记.同指大跨排,
+                    &标记.同指小跨排,
+                    &标记.小指干扰,
+                    &标记.错手,
+                ]
+                .iter()
+                .enumerate()
+                {
+                    if 集合.contains(&字符组合) {
+                        指法计数[j] += 1;
+                    }
+                }
+            }
+            for 三键 in 按键序列.windows(3) {
+                if 三键[0] == 三键[1] && 三键[1] == 三键[2] {
+                    指法计数[5] += 1;
+                }
+            }
+        }
+        let 字数 = 指标.characters.max(1) as f64;
+        指标.keystrokes_per_character = 指标.keystrokes as f64 / 字数;
+        指标.selections_per_character = 指标.selections as f64 / 字数;
+        let 组合数 = (组合数 as u64).max(1) as f64;
+        指标.pair_equivalence = 总当量 / 组合数;
+        for (i, 计数) in 指法计数.iter().enumerate().take(6) {
+            指标.fingering[i] = Some(*计数 as f64 / 组合数);
+        }
+        指标
+    }
+
+    /// 模拟在语料上打字的过程，根据当量估算耗时
+    ///
+    /// 按键流中每两个相邻按键之间的转移耗时为它们的两键当量，再加上以它结尾的三键组合的额外当量，然后按照转移的类型分类：涉及空格或选择键的为上屏，跨越编码边界的为词间，其余为码内
+    pub fn 模拟(&self, 文本: &str, 方式: 分词方式, 每当量秒数: f64) -> 模拟结果 {
+        let 切分结果 = self.切分(文本, 方式);
+        let mut 指标 = 语料指标::default();
+        Self::统计字数(&切分结果, &mut 指标);
+        let mut 耗时 = 耗时分解::default();
+        let mut 击键数 = 0;
+        for 按键流 in self.生成按键流(&切分结果) {
+            击键数 += 按键流.len() as u64;
+            for i in 1..按键流.len() {
+                let (前, 后) = (按键流[i - 1].键, 按键流[i].键);
+                let mut 当量 = self.双键当量(前, 后);
+                if i >= 2 {
+                    当量 += self.三键当量(按键流[i - 2].键, 前, 后);
+                }
+                let 秒数 = 当量 * 每当量秒数;
+                if self.数据.选择键.contains(&前) || self.数据.选择键.contains(&后) {
+                    耗时.selection += 秒数;
+                } else if 按键流[i].首键 {
+                    耗时.between_codes += 秒数;
+                } else {
+                    耗时.within_code += 秒数;
+                }
+            }
+        }
+        let 总秒数 = 耗时.within_code + 耗时.between_codes + 耗时.selection;
+        let 千字 = 指标.characters.max(1) as f64 / 1000.0;
+        模拟结果 {
+            characters: 指标.characters,
+            keystrokes: 击键数,
+            total_seconds: 总秒数,
+            seconds_per_1000_characters: 总秒数 / 千字,
+            breakdown: 耗时分解 {
+                within_code: 耗时.within_code / 千字,
+                between_codes: 耗时.between_codes / 千字,
+                selection: 耗时.selection / 千字,
+            },
+        }
+    }
+}
+
+/// 打字耗时按原因的分解
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct 耗时分解 {
+    pub within_code: f64,
+    pub between_codes: f64,
+    pub selection: f64,
+}
+
+/// 打字模拟的结果，分解中的各项均为每千字的秒数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct 模拟结果 {
+    pub characters: u64,
+    pub keystrokes: u64,
+    pub total_seconds: f64,
+    pub seconds_per_1000_characters: f64,
+    pub breakdown: 耗时分解,
+}
+
+impl Display for 模拟结果 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "字数：{}；总击键：{}；总耗时：{:.2} 秒；\n",
+            self.characters, self.keystrokes, self.total_seconds
+        ))?;
+        f.write_str(&format!(
+            "每千字耗时：{:.2} 秒，其中码内 {:.2} 秒，词间 {:.2} 秒，上屏 {:.2} 秒\n",
+            self.seconds_per_1000_characters,
+            self.breakdown.within_code,
+            self.breakdown.between_codes,
+            self.breakdown.selection
+        ))
+    }
+}
+
+/// 用正向最大匹配切分语料，统计词典中每个字词出现的次数
+pub fn 统计频率<'a>(词典: &词典, 语料列表: impl Iterator<Item = &'a str>) -> Vec<u64> {
+    let mut 频率 = vec![0; 词典.序号上限()];
+    for 语料 in 语料列表 {
+        let 字符: Vec<char> = 语料.chars().collect();
+        for 片段 in 词典.正向最大匹配(&字符) {
+            if let 片段::词 { 序号, .. } = 片段 {
+                频率[序号] += 1;
+            }
+        }
+    }
+    频率
+}
+
+/// 按名称为拆分表中的每一行分配统计到的频率，去掉频率低于下限的字词
+///
+/// 同名的多行（如多音字）平分这个名称的频率，余数分给靠前的行
+pub fn 分配频率(
+    拆分表: Vec<原始可编码对象>,
+    词典: &词典,
+    频率: &[u64],
+    最低频率: u64,
+) -> Vec<原始可编码对象> {
+    let mut 同名行数: FxHashMap<String, u64> = FxHashMap::default();
+    for x in &拆分表 {
+        *同名行数.entry(x.name.clone()).or_default() += 1;
+    }
+    let mut 已分配行数: FxHashMap<String, u64> = FxHashMap::default();
+    let mut 结果 = vec![];
+    for x in 拆分表 {
+        let 总频率 = 词典.查找(&x.name).map_or(0, |i| 频率.get(i).copied().unwrap_or(0));
+        if 总频率 < 最低频率 {
+            continue;
+        }
+        let 行数 = 同名行数[&x.name];
+        let 序号 = 已分配行数.entry(x.name.clone()).or_default();
+        let frequency = 总频率 / 行数 + u64::from(*序号 < 总频率 % 行数);
+        *序号 += 1;
+        结果.push(原始可编码对象 { frequency, ..x });
+    }
+    结果
+}
+
+/// 判断一个字符是否为中日韩统一表意文字（包括扩展区和兼容区）
+pub fn 是汉字(字符: char) -> bool {
+    matches!(字符 as u32,
+        0x3007
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xF900..=0xFAFF
+        | 0x20000..=0x323AF
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Mapped, 配置};
+    use crate::data::原始可编码对象;
+    use crate::encoders::新建编码器;
+    use std::collections::HashMap;
+
+    #[test]
+    fn 正向最大匹配与最小代价切分() {
+        let 词典 = 词典::新建(["中", "国", "人", "中国", "国人", "中国人民"].iter().copied());
+        let 文本: Vec<char> = "中国人。".chars().collect();
+        assert_eq!(
+            词典.正向最大匹配(&文本),
+            vec![
+                片段::词 { 序号: 3, 长度: 2 },
+                片段::词 { 序号: 2, 长度: 1 },
+                片段::未知('。'),
+            ]
+        );
+        // 假设「中国」的代价很高，最小代价切分会选择「中」+「国人」
+        let 代价 = |序号: usize| if 序号 == 3 { 10.0 } else { 1.0 };
+        assert_eq!(
+            词典.最小代价切分(&文本, 代价),
+            vec![
+                片段::词 { 序号: 0, 长度: 1 },
+                片段::词 { 序号: 4, 长度: 2 },
+                片段::未知('。'),
+            ]
+        );
+    }
+
+    #[test]
+    fn 码长为二时仍计入三键当量() {
+        let mut 配置 = 配置::default();
+        配置.form.alphabet = "abc".to_string();
+        for (元素, 键) in [("甲", "a"), ("乙", "b"), ("丙", "c")] {
+            配置
+                .form
+                .mapping
+                .insert(元素.to_string(), Mapped::Basic(键.to_string()));
+        }
+        配置.encoder.max_length = 2;
+        let 词列表 = [("甲乙", "甲 乙"), ("丙甲", "丙 甲")]
+            .iter()
+            .map(|(name, sequence)| 原始可编码对象 {
+                name: name.to_string(),
+                sequence: sequence.to_string(),
+                frequency: 1,
+                level: u64::MAX,
+            })
+            .collect();
+        let 当量 = [("ab", 1.0), ("bc", 2.0), ("ca", 4.0), ("abc", 8.0), ("bca", 16.0)];
+        let 原始当量信息 = 当量.iter().map(|(x, y)| (x.to_string(), *y)).collect();
+        let 数据 = 数据::新建(配置, 词列表, HashMap::new(), 原始当量信息).unwrap();
+        let 编码结果 = 新建编码器(&数据).unwrap().编码(&数据.初始映射, &None).clone();
+        let 评测器 = 语料评测器::新建(&数据, &编码结果);
+        // 按键流为 abca
+        let 指标 = 评测器.评测("甲乙丙甲", 分词方式::ForwardMaximum);
+        assert_eq!(指标.keystrokes, 4);
+        assert!((指标.pair_equivalence - 7.0 / 3.0).abs() < 1e-9);
+        let 结果 = 评测器.模拟("甲乙丙甲", 分词方式::ForwardMaximum, 1.0);
+        assert!((结果.total_seconds - 31.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn 同名的行平分频率() {
+        let 拆分表: Vec<_> = [("行", "彳 亍"), ("中", "口 丨"), ("行", "彳 亍"), ("行", "彳 亍")]
+            .iter()
+            .map(|(name, sequence)| 原始可编码对象 {
+                name: name.to_string(),
+                sequence: sequence.to_string(),
+                frequency: 0,
+                level: u64::MAX,
+            })
+            .collect();
+        let 词典 = 词典::新建(拆分表.iter().map(|x| x.name.as_str()));
+        let 频率 = 统计频率(&词典, ["行中行行行行中", "行行"].iter().copied());
+        let 结果: Vec<_> = 分配频率(拆分表.clone(), &词典, &频率, 1)
+            .iter()
+            .map(|x| (x.name.clone(), x.frequency))
+            .collect();
+        let 期望 = [("行", 3), ("中", 2), ("行", 2), ("行", 2)];
+        assert_eq!(结果, 期望.map(|(x, y)| (x.to_string(), y)));
+        let 结果: Vec<_> = 分配频率(拆分表, &词典, &频率, 3)
+            .iter()
+            .map(|x| (x.name.clone(), x.frequency))
+            .collect();
+        // 按名称的总频率筛选，所以平分后低于下限的行仍然保留
+        assert_eq!(结果, [("行", 3), ("行", 2), ("行", 2)].map(|(x, y)| (x.to_string(), y)));
+    }
+}
@@ -1,15 +1,17 @@
 //! 数据结构的定义
 
 use crate::{
-    config::{Mapped, MappedKey, Regularization, Scheme, ShortCodeConfig, 配置},
+    config::{
+        ElementWithIndex, Finger, Mapped, MappedKey, Regularization, Scheme, ShortCodeConfig, 配置,
+    },
     encoders::简码配置,
-    objectives::metric::指法标记,
+    objectives::metric::{手指, 指法标记, 查找键位},
     错误,
 };
 use regex::Regex;
 use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
-use std::{cmp::Reverse, collections::HashMap};
+use std::{cmp::Reverse, collections::HashMap, sync::Arc};
 
 /// 只考虑长度为 1 到 10 的词
 pub const 最大词长: usize = 10;
@@ -75,20 +77,25 @@ pub struct 部分编码信息 {
     pub 选重标记: bool,       // 实际编码是否算作重码
     pub 上一个实际编码: 编码, // 前一个实际编码
     pub 上一个选重标记: bool, // 前一个实际编码是否算作重码
+    pub 候选位置: u8,         // 实际编码的候选位置，用于计算选重代价
+    pub 上一个候选位置: u8,   // 前一个实际编码的候选位置
     pub 有变化: bool,         // 编码是否发生了变化
 }
 
 impl 部分编码信息 {
     #[inline(always)]
-    pub fn 更新(&mut self, 编码: 编码, 选重标记: bool) {
-        if self.实际编码 == 编码 && self.选重标记 == 选重标记 {
+    pub fn 更新(&mut self, 编码: 编码, 选重标记: bool, 候选位置: u8) {
+        if self.实际编码 == 编码 && self.选重标记 == 选重标记 && self.候选位置 == 候选位置
+        {
             return;
         }
         self.有变化 = true;
         self.上一个实际编码 = self.实际编码;
         self.上一个选重标记 = self.选重标记;
+        self.上一个候选位置 = self.候选位置;
         self.实际编码 = 编码;
         self.选重标记 = 选重标记;
+        self.候选位置 = 候选位置;
     }
 }
 
@@ -136,19 +143,118 @@ pub struct 码表项 {
 
 pub type 正则化 = FxHashMap<元素, Vec<(元素, f64)>>;
 
+/// 每个键上最多能放置的元素个数，未提供惩罚系数时是硬约束，否则作为惩罚项计入目标函数
+#[derive(Debug, Clone)]
+pub struct 容量约束 {
+    pub 键列表: Vec<键>,
+    pub 计入元素: Vec<元素>,
+    pub 上限: usize,
+    pub 惩罚: Option<f64>,
+    /// 按键的编号索引，键是否受约束
+    pub 受约束的键: Vec<bool>,
+    /// 按元素的编号索引，元素是否计入
+    pub 计入的元素: Vec<bool>,
+}
+
+/// 编译后的软约束，偏好键和禁用键都是元素、键列表和权重的三元组
+#[derive(Debug, Clone, Default)]
+pub struct 软约束 {
+    pub 偏好键: Vec<(元素, Vec<键>, f64)>,
+    pub 禁用键: Vec<(元素, Vec<键>, f64)>,
+    pub 保持初始映射: Option<f64>,
+}
+
+impl 软约束 {
+    pub fn is_empty(&self) -> bool {
+        self.偏好键.is_empty() && self.禁用键.is_empty() && self.保持初始映射.is_none()
+    }
+}
+
+impl 容量约束 {
+    pub fn 计数(&self, 映射: &元素映射, 键: 键) -> usize {
+        self.计入元素.iter().filter(|x| 映射[**x] == 键).count()
+    }
+
+    pub fn 受约束(&self, 键: 键) -> bool {
+        self.受约束的键[键 as usize]
+    }
+
+    pub fn 计入(&self, 元素: 元素) -> bool {
+        self.计入的元素[元素]
+    }
+}
+
+/// 一组容量约束在某个映射下每个键上计入的元素个数
+///
+/// 换到新的映射时只更新所在的键有变化的元素，不必重新统计全部计入的元素
+#[derive(Debug, Clone)]
+pub struct 容量计数 {
+    映射: 元素映射,
+    计数: Vec<Vec<usize>>,
+}
+
+impl 容量计数 {
+    pub fn 新建(约束列表: &[容量约束], 映射: &元素映射) -> Self {
+        let 计数 = 约束列表
+            .iter()
+            .map(|约束| {
+                let mut 计数 = vec![0; 约束.受约束的键.len()];
+                for 元素 in &约束.计入元素 {
+                    计数[映射[*元素] as usize] += 1;
+                }
+                计数
+            })
+            .collect();
+        Self {
+            映射: 映射.clone(),
+            计数,
+        }
+    }
+
+    /// 把计数更新为给定映射下的计数
+    pub fn 同步(&mut self, 约束列表: &[容量约束], 映射: &元素映射) {
+        for (元素, 键) in 映射.iter().enumerate() {
+            let 原键 = self.映射[元素];
+            if 原键 == *键 {
+                continue;
+            }
+            for (约束, 计数) in 约束列表.iter().zip(&mut self.计数) {
+                if 约束.计入(元素) {
+                    计数[原键 as usize] -= 1;
+                    计数[*键 as usize] += 1;
+                }
+            }
+            self.映射[元素] = *键;
+        }
+    }
+
+    /// 第几条约束在这个键上计入的元素个数
+    pub fn 计数(&self, 序号: usize, 键: 键) -> usize {
+        self.计数[序号][键 as usize]
+    }
+}
+
 /// 将用户提供的输入转换为内部数据结构，并提供了一些实用的方法
+///
+/// 词列表和预先计算好的查找表在创建时构建一次，之后只读，克隆数据时多个线程的编码器和目标函数共享同一份
 #[derive(Debug, Clone)]
 pub struct 数据 {
-    pub 配置: 配置,
-    pub 词列表: Vec<可编码对象>,
+    pub 配置: Arc<配置>,
+    pub 词列表: Arc<Vec<可编码对象>>,
+    /// 按元素的编号索引，每个元素出现在哪些字词中
+    pub 包含元素的词: Arc<Vec<Vec<usize>>>,
     pub 键位分布信息: 键位分布信息,
-    pub 当量信息: 当量信息,
-    pub 初始映射: 元素映射,
+    pub 当量信息: Arc<当量信息>,
+    /// 所有两键和三键组合的当量，不受最大码长的限制，用于计算语料中相邻按键的当量
+    pub 组合当量信息: Arc<当量信息>,
+    pub 指法标记: Arc<Vec<指法向量>>,
+    pub 自动上屏查找表: Arc<自动上屏>,
+    pub 初始映射: Arc<元素映射>,
     pub 正则化: 正则化,
     pub 进制: u64,
     pub 选择键: Vec<键>,
     pub 键转数字: FxHashMap<char, 键>,
-    pub 数字转键: FxHashMap<键, char>,
+    pub 数字转键: Arc<FxHashMap<键, char>>,
     pub 元素转数字: FxHashMap<String, 元素>,
     pub 数字转元素: FxHashMap<元素, String>,
 }
@@ -183,10 +289,13 @@ impl 数据 {
         let (初始映射, 元素转数字, 数字转元素) = Self::预处理映射(&配置, &键转数字, 进制)?;
         let 最大码长 = 配置.encoder.max_length;
         let 词列表 = Self::预处理词列表(原始词列表, 最大码长, &元素转数字)?;
+        let 包含元素的词 = Self::预处理包含元素的词(&词列表, 元素转数字.len() + 1);
         let 组合长度 = 最大码长.min(最大按键组合长度);
         let 编码空间大小 = 进制.pow(组合长度 as u32) as usize;
         let 键位分布信息 = Self::预处理键位分布信息(&原始键位分布信息, 进制, &数字转键);
         let 当量信息 = Self::预处理当量信息(&原始当量信息, 编码空间大小, 进制, &数字转键);
+        let 组合当量信息 =
+            Self::预处理当量信息(&原始当量信息, 进制.pow(3) as usize, 进制, &数字转键);
         let 正则化 = if let Some(正则化配置) = 配置
             .optimization
             .clone()
@@ -198,20 +307,26 @@ impl 数据 {
         } else {
             FxHashMap::default()
         };
-        let repr = Self {
-            配置,
-            词列表,
+        let mut repr = Self {
+            配置: Arc::new(配置),
+            词列表: Arc::new(词列表),
+            包含元素的词: Arc::new(包含元素的词),
             键位分布信息,
-            当量信息,
-            初始映射,
+            当量信息: Arc::new(当量信息),
+            组合当量信息: Arc::new(组合当量信息),
+            指法标记: Arc::default(),
+            自动上屏查找表: Arc::default(),
+            初始映射: Arc::new(初始映射),
             元素转数字,
             数字转元素,
             键转数字,
-            数字转键,
+            数字转键: Arc::new(数字转键),
             进制,
             选择键,
             正则化,
         };
+        repr.指法标记 = Arc::new(repr.预处理指法标记());
+        repr.自动上屏查找表 = Arc::new(repr.预处理自动上屏()?);
         Ok(repr)
     }
 
@@ -284,6 +399,17 @@ impl 数据 {
         Ok((元素映射, 元素转数字, 数字转元素))
     }
 
+    /// 为每个元素列出包含它的字词的序号，增量编码时只需要重新计算这些字词
+    pub fn 预处理包含元素的词(词列表: &[可编码对象], 元素数: usize) -> Vec<Vec<usize>> {
+        let mut 包含元素的词 = vec![vec![]; 元素数];
+        for (词序号, 词) in 词列表.iter().enumerate() {
+            for 元素 in &词.元素序列 {
+                包含元素的词[*元素].push(词序号);
+            }
+        }
+        包含元素的词
+    }
+
     pub fn 预处理正则化(
         正则化: &Regularization,
         元素转数字: &FxHashMap<String, 元素>,
@@ -324,6 +450,133 @@ impl 数据 {
         }
         Ok(result)
     }
+
+    /// 把配置中的容量约束编译为键和元素的编号，未指定键时约束字母表中的所有键，未指定元素和码位时计入所有元素
+    pub fn 预处理容量约束(&self) -> Result<Vec<容量约束>, 错误> {
+        let 列表 = self
+            .配置
+            .optimization
+            .as_ref()
+            .and_then(|x| x.constraints.as_ref())
+            .and_then(|x| x.capacities.clone())
+            .unwrap_or_default();
+        let mut result = Vec::new();
+        for 约束 in 列表 {
+            let 键字符列表: Vec<char> = match &约束.keys {
+                Some(keys) => keys.clone(),
+                None => self.配置.form.alphabet.chars().collect(),
+            };
+            let mut 键列表 = Vec::new();
+            for 键字符 in 键字符列表 {
+                let 键 = self
+                    .键转数字
+                    .get(&键字符)
+                    .ok_or(format!("容量约束中的键 {键字符} 不在字母表中"))?;
+                键列表.push(*键);
+            }
+            if let Some(elements) = &约束.elements {
+                for 元素 in elements {
+                    if !self.配置.form.mapping.contains_key(元素) {
+                        return Err(format!("容量约束中的元素 {元素} 不在键盘映射中").into());
+                    }
+                }
+            }
+            let mut 计入元素 = Vec::new();
+            for (元素, 映射值) in &self.配置.form.mapping {
+                if 约束.elements.as_ref().is_some_and(|x| !x.contains(元素)) {
+                    continue;
+                }
+                for (序号, 映射键) in 映射值.normalize().iter().enumerate() {
+                    if 约束.index.is_some_and(|x| x != 序号) {
+                        continue;
+                    }
+                    if let MappedKey::Ascii(_) = 映射键 {
+                        计入元素.push(self.元素转数字[&Self::序列化(元素, 序号)]);
+                    }
+                }
+            }
+            let mut 受约束的键 = vec![false; self.进制 as usize];
+            for 键 in &键列表 {
+                受约束的键[*键 as usize] = true;
+            }
+            let mut 计入的元素 = vec![false; self.初始映射.len()];
+            for 元素 in &计入元素 {
+                计入的元素[*元素] = true;
+            }
+            result.push(容量约束 {
+                键列表,
+                计入元素,
+                上限: 约束.max,
+                惩罚: 约束.penalty,
+                受约束的键,
+                计入的元素,
+            });
+        }
+        Ok(result)
+    }
+
+    /// 把配置中的软约束编译为元素和键的编号，禁用的行和手指被展开为字母表中对应的键
+    pub fn 预处理软约束(&self) -> Result<软约束, 错误> {
+        let mut result = 软约束::default();
+        let Some(配置) = self
+            .配置
+            .optimization
+            .as_ref()
+            .and_then(|x| x.objective.soft_constraints.as_ref())
+        else {
+            return Ok(result);
+        };
+        let 查找元素 = |x: &ElementWithIndex| {
+            let 元素名称 = Self::序列化(&x.element, x.index);
+            self.元素转数字
+                .get(&元素名称)
+                .copied()
+                .ok_or(format!("软约束中的元素 {元素名称} 不存在"))
+        };
+        for 规则 in 配置.preferred_keys.iter().flatten() {
+            let mut 键列表 = Vec::new();
+            for 键字符 in &规则.keys {
+                let 键 = self
+                    .键转数字
+                    .get(键字符)
+                    .ok_or(format!("软约束中的键 {键字符} 不在字母表中"))?;
+                键列表.push(*键);
+            }
+            result.偏好键.push((查找元素(&规则.element)?, 键列表, 规则.weight));
+        }
+        for 规则 in 配置.forbidden_positions.iter().flatten() {
+            let 键列表: Vec<键> = self
+                .配置
+                .form
+                .alphabet
+                .chars()
+                .filter(|x| {
+                    查找键位(*x).is_some_and(|键位| {
+                        let 手指 = match 键位.手指 {
+                            手指::大拇指 => Finger::Thumb,
+                            手指::食指 => Finger::Index,
+                            手指::中指 => Finger::Middle,
+                            手指::无名指 => Finger::Ring,
+                            手指::小指 => Finger::Pinky,
+                        };
+                        规则.rows.as_ref().is_some_and(|x| x.contains(&键位.行))
+                            || 规则.fingers.as_ref().is_some_and(|x| x.contains(&手指))
+                    })
+                })
+                .map(|x| self.键转数字[&x])
+                .collect();
+            let 元素列表: Vec<元素> = match &规则.elements {
+                Some(elements) => elements.iter().map(查找元素).collect::<Result<_, _>>()?,
+                None => (self.进制 as usize..self.初始映射.len()).collect(),
+            };
+            for 元素 in 元素列表 {
+                result.禁用键.push((元素, 键列表.clone(), 规则.weight));
+            }
+        }
+        result.保持初始映射 = 配置.keep_initial;
+        Ok(result)
+    }
+
     pub fn 序列化(element: &String, index: usize) -> String {
         if index == 0 {
             element.to_string()
@@ -381,29 +634,32 @@ impl 数据 {
 
     pub fn 生成码表(&self, buffer: &[编码信息]) -> Vec<码表项> {
         let mut entries: Vec<(usize, 码表项)> = Vec::new();
-        let encodables = &self.词列表;
+        for (index, encodable) in self.词列表.iter().enumerate() {
+            entries.push((encodable.原始顺序, self.生成码表项(index, &buffer[index])));
+        }
+        entries.sort_by_key(|x| x.0);
+        entries.into_iter().map(|x| x.1).collect()
+    }
+
+    /// 生成词列表中第 index 个词的码表项
+    pub fn 生成码表项(&self, index: usize, 编码信息: &编码信息) -> 码表项 {
         let recover = |code: 编码| {
             Self::数字转编码(code, self.进制, &self.数字转键)
                 .iter()
                 .collect()
         };
-        for (index, encodable) in encodables.iter().enumerate() {
-            let entry = 码表项 {
-                name: encodable.名称.clone(),
-                full: recover(buffer[index].全码.原始编码),
-                full_rank: buffer[index].全码.原始编码候选位置,
-                short: recover(buffer[index].简码.原始编码),
-                short_rank: buffer[index].简码.原始编码候选位置,
-            };
-            entries.push((encodable.原始顺序, entry));
+        码表项 {
+            name: self.词列表[index].名称.clone(),
+            full: recover(编码信息.全码.原始编码),
+            full_rank: 编码信息.全码.原始编码候选位置,
+            short: recover(编码信息.简码.原始编码),
+            short_rank: 编码信息.简码.原始编码候选位置,
         }
-        entries.sort_by_key(|x| x.0);
-        entries.into_iter().map(|x| x.1).collect()
     }
 
     /// 根据一个计算中得到的元素布局来生成一份新的配置文件，其余内容不变直接复制过来
     pub fn 更新配置(&self, candidate: &元素映射) -> 配置 {
-        let mut new_config = self.配置.clone();
+        let mut new_config = (*self.配置).clone();
         let lookup = |element: &String| {
             let number = *self.元素转数字.get(element).unwrap(); // 输入的时候已经检查过一遍，不需要再次检查
             let current_mapped = &candidate[number];
@@ -649,3 +905,31 @@ impl 数据 {
         self.进制.pow(max_length as u32) as usize
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn 容量计数随映射同步() {
+        // 键 0 到 2，元素 3 到 6，只计入元素 3、4、5
+        let 约束 = 容量约束 {
+            键列表: vec![1, 2],
+            计入元素: vec![3, 4, 5],
+            上限: 1,
+            惩罚: None,
+            受约束的键: vec![false, true, true],
+            计入的元素: vec![false, false, false, true, true, true, false],
+        };
+        let mut 映射 = vec![0, 1, 2, 1, 1, 2, 2];
+        let mut 计数 = 容量计数::新建(std::slice::from_ref(&约束), &映射);
+        assert_eq!((计数.计数(0, 1), 计数.计数(0, 2)), (2, 1));
+        映射[3] = 2;
+        映射[6] = 1;
+        计数.同步(std::slice::from_ref(&约束), &映射);
+        assert_eq!((计数.计数(0, 1), 计数.计数(0, 2)), (1, 2));
+        for 键 in 0..3 {
+            assert_eq!(计数.计数(0, 键), 约束.计数(&映射, 键));
+        }
+    }
+}
@@ -1,27 +1,43 @@
-use super::{简码配置, 编码器, 编码空间, 编码配置};
-use crate::data::{元素, 元素映射, 可编码对象, 数据, 编码信息};
-use crate::{命令行, 命令行参数, 错误};
-use clap::Parser;
+use super::{哈希表内存, 截断位置, 简码配置, 编码分组, 编码器, 编码配置};
+use crate::data::{元素, 元素映射, 可编码对象, 数据, 最大词长, 编码, 编码信息};
+use crate::错误;
 use rustc_hash::FxHashMap;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::iter::zip;
+use std::mem::size_of;
+use std::sync::Arc;
 
+/// 默认编码器
+///
+/// 编码器在两次编码之间保留全码和简码在各个编码上的占用情况。增量编码时，先与上一次编码的映射逐个比较，找出真正改变了键位的元素，再只重新计算包含这些元素的字词的全码、受影响的候选位置，以及可能因此改变的简码。被拒绝的移动不需要专门处理，下一次编码时与上一次的映射比较就会被撤销。
 pub struct 默认编码器 {
     编码结果: Vec<编码信息>,
     编码配置: 编码配置,
-    词信息: Vec<可编码对象>,
-    全码空间: 编码空间,
-    简码空间: 编码空间,
-    包含元素的词: Vec<Vec<usize>>,
+    简码配置列表: Option<Arc<[Vec<简码配置>; 最大词长]>>,
+    词信息: Arc<Vec<可编码对象>>,
+    包含元素的词: Arc<Vec<Vec<usize>>>,
+    当前映射: 元素映射,
+    全码分组: 编码分组,
+    优先简码分组: 编码分组,
+    常规简码分组: 编码分组,
+    简码查询索引: 简码查询索引,
+    词标记: Vec<u64>,
+    待处理标记: Vec<u64>,
+    待处理: BinaryHeap<Reverse<usize>>,
+    轮次: u64,
+    /// 开启时记录每次编码中重新计算过全码或简码的字词，供顶功编码器只处理这些字词
+    刷新过的词: Option<Vec<usize>>,
 }
 
-lazy_static::lazy_static! {
-    pub static ref 元素序列: Vec<Vec<usize>> = {
-        let 参数 = 命令行参数::parse();
-        let 命令行 = 命令行::新建(参数, None);
-        let 数据 = 命令行.准备数据();
-        let 编码器 = 默认编码器::新建(&数据).unwrap();
-        编码器.词信息.iter().map(|x| x.元素序列.clone()).collect()
-    };
+/// 常规简码的判断会查询全码的各个前缀以及全码本身在简码中的占用情况，这里记录每个编码被哪些字词查询
+///
+/// 字词的全码改变时只追加新的条目，旧的条目留待过期条目足够多时整体重建，查询到过期条目只会多算一次，不影响结果
+#[derive(Default)]
+struct 简码查询索引 {
+    索引: FxHashMap<编码, Vec<usize>>,
+    有效条目数: usize,
+    过期条目数: usize,
 }
 
 impl 默认编码器 {
@@ -36,162 +52,505 @@ impl 默认编码器 {
         }
         let 词信息 = 数据.词列表.clone();
         let 编码结果 = 词信息.iter().map(编码信息::new).collect();
-        let 线性表长度 = 数据.进制.pow(最大码长 as u32) as usize;
-        let 全码空间 = 编码空间 {
-            线性表: vec![u8::default(); 线性表长度],
-            线性表长度,
-            哈希表: FxHashMap::default(),
-        };
-        let 简码空间 = 全码空间.clone();
-        let mut 包含元素的词 = vec![];
-        for _ in 0..=数据.元素转数字.len() {
-            包含元素的词.push(vec![]);
-        }
-        for (词序号, 词) in 词信息.iter().enumerate() {
-            for 元素 in &词.元素序列 {
-                包含元素的词[*元素].push(词序号);
-            }
-        }
-        let 编码配置 = 编码配置::new(数据)?;
+        let mut 编码配置 = 编码配置::new(数据)?;
+        let 简码配置列表 = 编码配置.简码配置列表.take().map(Arc::new);
+        let 词数 = 词信息.len();
         Ok(Self {
             编码结果,
             编码配置,
+            简码配置列表,
             词信息,
-            全码空间,
-            简码空间,
-            包含元素的词,
+            包含元素的词: 数据.包含元素的词.clone(),
+            当前映射: vec![],
+            全码分组: 编码分组::default(),
+            优先简码分组: 编码分组::default(),
+            常规简码分组: 编码分组::default(),
+            简码查询索引: 简码查询索引::default(),
+            词标记: vec![0; 词数],
+            待处理标记: vec![0; 词数],
+            待处理: BinaryHeap::new(),
+            轮次: 0,
+            刷新过的词: None,
         })
     }
 
-    fn 重置(&mut self) {
-        self.全码空间.线性表.iter_mut().for_each(|x| {
-            *x = 0;
-        });
-        self.全码空间.哈希表.clear();
-        self.简码空间.线性表.iter_mut().for_each(|x| {
-            *x = 0;
-        });
-        self.简码空间.哈希表.clear();
+    fn 计算全码(&self, 词序号: usize, 映射: &元素映射) -> 编码 {
+        let 词 = &self.词信息[词序号];
+        let mut 原始编码 = 0;
+        for (元素, 乘数) in zip(&词.元素序列, &self.编码配置.乘数列表) {
+            原始编码 += 映射[*元素] * 乘数;
+        }
+        原始编码
+    }
+
+    /// 按组内位次重新确定一个全码上所有字词的候选位置
+    fn 刷新全码(&mut self, 原始编码: 编码) {
+        for (位次, 序号) in self.全码分组.成员(原始编码).iter().enumerate() {
+            let 词 = &self.词信息[*序号];
+            let 全码信息 = &mut self.编码结果[*序号].全码;
+            let 原始编码候选位置 = 截断位置(位次);
+            全码信息.原始编码候选位置 = 原始编码候选位置;
+            // 注意：对于全码来说，暂且忽略次选及之后的选择键的影响，统一视为首选进行编码。这可以避免在四码类方案中大量出现五码的编码，影响性能
+            let 乘数 = self.编码配置.乘数列表[词.元素序列.len()];
+            let 编码 = self.编码配置.生成编码(原始编码, 0, 乘数);
+            全码信息.更新(编码, 原始编码候选位置 > 0, 原始编码候选位置);
+            if let Some(列表) = &mut self.刷新过的词 {
+                列表.push(*序号);
+            }
+        }
+    }
+
+    /// 按组内位次重新确定一个编码上所有优先简码的候选位置
+    fn 刷新优先简码(&mut self, 原始编码: 编码) {
+        for (位次, 序号) in self.优先简码分组.成员(原始编码).iter().enumerate() {
+            let 乘数 = self.编码配置.乘数列表[self.词信息[*序号].简码等级 as usize];
+            let 简码信息 = &mut self.编码结果[*序号].简码;
+            let 候选位置 = 截断位置(位次);
+            let 编码 = self.编码配置.生成编码(原始编码, 候选位置, 乘数);
+            简码信息.原始编码候选位置 = 候选位置;
+            简码信息.更新(编码, 候选位置 > 0, 候选位置);
+            if let Some(列表) = &mut self.刷新过的词 {
+                列表.push(*序号);
+            }
+        }
+    }
+
+    /// 常规简码查询的所有编码：每个出简方式对应的全码前缀，以及全码本身
+    fn 查询编码<'a>(
+        简码配置: &'a [简码配置],
+        乘数列表: &'a [u64],
+        全码: 编码,
+    ) -> impl Iterator<Item = 编码> + 'a {
+        简码配置
+            .iter()
+            .map(move |x| 乘数列表[x.prefix])
+            .filter(move |x| 全码 >= *x)
+            .map(move |x| 全码 % x)
+            .chain(std::iter::once(全码))
     }
 
-    fn 输出全码(&mut self, 映射: &元素映射, 移动的元素: &Option<Vec<元素>>) {
+    /// 依次尝试每一种出简方式，如果某个前缀上的候选数量没有达到上限，就出这个简码；否则以全码作为简码
+    ///
+    /// 前缀上的候选数量包括全部以它为全码的字词、全部以它为优先简码的字词，以及序号在前、以它为常规简码的字词
+    fn 生成常规简码(&self, 简码配置: &[简码配置], 序号: usize) -> (编码, u8, 编码, bool) {
         let 编码配置 = &self.编码配置;
-        let 编码结果 = &mut self.编码结果;
-        // 根据移动的元素更新编码结果，如果没有移动的元素则直接全部生成
-        if let Some(移动的元素) = 移动的元素 {
-            for 元素 in 移动的元素 {
-                for 索引 in &self.包含元素的词[*元素] {
-                    let 词 = &self.词信息[*索引];
-                    let 全码信息 = &mut 编码结果[*索引].全码;
-                    let mut 原始编码 = 0;
-                    for (元素, 乘数) in zip(&词.元素序列, &编码配置.乘数列表) {
-                        原始编码 += 映射[*元素] * 乘数;
-                    }
-                    全码信息.原始编码 = 原始编码;
-                }
+        let 全码信息 = &self.编码结果[序号].全码;
+        let 简码占用 = |x: 编码| {
+            self.优先简码分组.数量(x) + self.常规简码分组.位次(x, 序号)
+        };
+        for 出简方式 in 简码配置 {
+            let 简码配置 {
+                prefix,
+                select_keys,
+            } = 出简方式;
+            let 乘数 = 编码配置.乘数列表[*prefix];
+            // 如果根本没有这么多码，就放弃
+            if 全码信息.原始编码 < 乘数 {
+                continue;
             }
-        } else {
-            for (词, 编码信息) in zip(&self.词信息, 编码结果.iter_mut()) {
-                let 全码信息 = &mut 编码信息.全码;
-                let mut 原始编码 = 0;
-                for (元素, 乘数) in zip(&词.元素序列, &编码配置.乘数列表) {
-                    原始编码 += 映射[*元素] * 乘数;
-                }
-                全码信息.原始编码 = 原始编码;
+            // 将全码截取一部分出来，检查当前简码位置上的候选数量是否达到上限
+            let 原始编码 = 全码信息.原始编码 % 乘数;
+            let 全码数量 = 截断位置(self.全码分组.数量(原始编码)) as usize;
+            let 候选位置 = 截断位置(全码数量 + 简码占用(原始编码));
+            if 候选位置 >= select_keys.len() as u8 {
+                continue;
             }
+            // 如果没有达到上限，就可以出这个简码
+            let 编码 = 编码配置.生成编码(原始编码, 候选位置, 乘数);
+            return (原始编码, 候选位置, 编码, false);
         }
+        let 候选位置 = 截断位置(简码占用(全码信息.原始编码));
+        (全码信息.原始编码, 候选位置, 全码信息.实际编码, 候选位置 > 0)
+    }
 
-        for (编码信息, 词) in 编码结果.iter_mut().zip(&self.词信息) {
-            let 全码信息 = &mut 编码信息.全码;
-            // 首先查找编码空间中已经有了多少个同样原始编码的词，确定候选位置
-            let 原始编码候选位置 = self.全码空间.查找数量(全码信息.原始编码);
-            全码信息.原始编码候选位置 = 原始编码候选位置;
-            self.全码空间.添加(全码信息.原始编码);
-            // 然后生成实际编码，并向全码信息中写入实际编码和实际编码是否重码的信息，用于测评
-            // 注意：对于全码来说，暂且忽略次选及之后的选择键的影响，统一视为首选进行编码。这可以避免在四码类方案中大量出现五码的编码，影响性能
-            let 乘数 = 编码配置.乘数列表[词.元素序列.len()];
-            let 编码 = 编码配置.生成编码(全码信息.原始编码, 0, 乘数);
-            let 是否重码 = 原始编码候选位置 > 0;
-            全码信息.更新(编码, 是否重码);
+    fn 写入常规简码(&mut self, 简码配置: &[简码配置], 序号: usize) -> 编码 {
+        let (原始编码, 候选位置, 编码, 选重) = self.生成常规简码(简码配置, 序号);
+        let 简码信息 = &mut self.编码结果[序号].简码;
+        let 旧原始编码 = 简码信息.原始编码;
+        简码信息.原始编码 = 原始编码;
+        简码信息.原始编码候选位置 = 候选位置;
+        简码信息.更新(编码, 选重, 候选位置);
+        if let Some(列表) = &mut self.刷新过的词 {
+            列表.push(序号);
         }
+        旧原始编码
     }
 
-    fn 输出简码(&mut self) {
-        let 编码配置 = &self.编码配置;
-        let 编码结果 = &mut self.编码结果;
-        let 简码配置列表 = 编码配置.简码配置列表.as_ref().unwrap();
+    /// 开启刷新记录，之后每次编码重新计算过的字词都会被记录下来
+    pub(super) fn 开启刷新记录(&mut self) {
+        self.刷新过的词 = Some(vec![]);
+    }
+
+    /// 取出上一次编码以来记录的字词，同时换入一个清空的列表，可能有重复
+    pub(super) fn 交换刷新记录(&mut self, 列表: &mut Vec<usize>) {
+        if let Some(记录) = &mut self.刷新过的词 {
+            列表.clear();
+            std::mem::swap(记录, 列表);
+        }
+    }
+
+    /// 以一个编码为原始全码或原始简码的所有字词，可能有重复
+    pub(super) fn 原始编码为(&self, 编码: 编码) -> impl Iterator<Item = usize> + '_ {
+        let 简码 = self.优先简码分组.成员(编码).iter().chain(self.常规简码分组.成员(编码));
+        self.全码分组.成员(编码).iter().chain(简码).copied()
+    }
+
+    pub(super) fn 编码结果(&self) -> &[编码信息] {
+        &self.编码结果
+    }
+
+    pub(super) fn 有简码(&self) -> bool {
+        self.简码配置列表.is_some()
+    }
+
+    /// 从头计算所有字词的全码和简码，并重建所有的分组和索引
+    fn 完整编码(&mut self, 映射: &元素映射) {
+        self.当前映射.clone_from(映射);
+        self.全码分组.清空();
+        for 序号 in 0..self.词信息.len() {
+            let 原始编码 = self.计算全码(序号, 映射);
+            self.编码结果[序号].全码.原始编码 = 原始编码;
+            self.全码分组.添加(原始编码, 序号);
+        }
+        let mut 全码列表: Vec<_> = self.编码结果.iter().map(|x| x.全码.原始编码).collect();
+        全码列表.sort_unstable();
+        全码列表.dedup();
+        for 原始编码 in 全码列表 {
+            self.刷新全码(原始编码);
+        }
+        if let Some(简码配置列表) = self.简码配置列表.clone() {
+            self.完整编码简码(&简码配置列表[..]);
+        }
+    }
+
+    fn 完整编码简码(&mut self, 简码配置列表: &[Vec<简码配置>]) {
         // 优先简码
-        for (词, 编码结果) in zip(&self.词信息, 编码结果.iter_mut()) {
+        self.优先简码分组.清空();
+        for (序号, 词) in self.词信息.iter().enumerate() {
             if 词.简码等级 == u64::MAX {
                 continue;
             }
-            let 原始编码 = 编码结果.全码.原始编码 % 编码配置.乘数列表[词.简码等级 as usize];
-            编码结果.简码.原始编码 = 原始编码;
-            let 序号 = self.简码空间.查找数量(原始编码);
-            let 乘数 = 编码配置.乘数列表[词.简码等级 as usize];
-            let 编码 = 编码配置.生成编码(原始编码, 序号, 乘数);
-            编码结果.简码.更新(编码, 序号 > 0);
-            self.简码空间.添加(原始编码);
+            let 原始编码 =
+                self.编码结果[序号].全码.原始编码 % self.编码配置.乘数列表[词.简码等级 as usize];
+            self.编码结果[序号].简码.原始编码 = 原始编码;
+            self.优先简码分组.添加(原始编码, 序号);
+        }
+        let mut 优先简码列表: Vec<_> = (0..self.词信息.len())
+            .filter(|x| self.词信息[*x].简码等级 != u64::MAX)
+            .map(|x| self.编码结果[x].简码.原始编码)
+            .collect();
+        优先简码列表.sort_unstable();
+        优先简码列表.dedup();
+        for 原始编码 in 优先简码列表 {
+            self.刷新优先简码(原始编码);
         }
         // 常规简码
-        for (词, 编码结果) in zip(&self.词信息, 编码结果.iter_mut()) {
+        self.常规简码分组.清空();
+        for 序号 in 0..self.词信息.len() {
+            let 词 = &self.词信息[序号];
             if 词.简码等级 != u64::MAX {
                 continue;
             }
             let 简码配置 = &简码配置列表[词.词长 - 1];
-            let mut 有简码 = false;
-            let 全码信息 = &编码结果.全码;
-            let 简码信息 = &mut 编码结果.简码;
-            for 出简方式 in 简码配置 {
-                let 简码配置 {
-                    prefix,
-                    select_keys,
-                } = 出简方式;
-                let 乘数 = 编码配置.乘数列表[*prefix];
-                // 如果根本没有这么多码，就放弃
-                if 全码信息.原始编码 < 乘数 {
-                    continue;
-                }
-                // 将全码截取一部分出来，检查当前简码位置上的候选数量是否达到上限
-                let 原始编码 = 全码信息.原始编码 % 乘数;
-                let 序号 = self.全码空间.查找数量(原始编码) + self.简码空间.查找数量(原始编码);
-                if 序号 >= select_keys.len() as u8 {
-                    continue;
+            self.写入常规简码(简码配置, 序号);
+            self.常规简码分组.添加(self.编码结果[序号].简码.原始编码, 序号);
+        }
+        self.重建简码查询索引(简码配置列表);
+    }
+
+    fn 重建简码查询索引(&mut self, 简码配置列表: &[Vec<简码配置>]) {
+        let 索引 = &mut self.简码查询索引;
+        索引.索引.clear();
+        索引.有效条目数 = 0;
+        索引.过期条目数 = 0;
+        for (序号, 词) in self.词信息.iter().enumerate() {
+            if 词.简码等级 != u64::MAX {
+                continue;
+            }
+            let 全码 = self.编码结果[序号].全码.原始编码;
+            let 简码配置 = &简码配置列表[词.词长 - 1];
+            for 编码 in Self::查询编码(简码配置, &self.编码配置.乘数列表, 全码) {
+                索引.索引.entry(编码).or_default().push(序号);
+                索引.有效条目数 += 1;
+            }
+        }
+    }
+
+    /// 一个编码上的简码占用发生变化之后，序号在后、查询这个编码的字词都需要重新判断简码
+    fn 标记变化(&mut self, 编码: 编码, 起点: Option<usize>) {
+        let Some(列表) = self.简码查询索引.索引.get(&编码) else {
+            return;
+        };
+        for 序号 in 列表 {
+            if 起点.is_some_and(|x| *序号 <= x) || self.待处理标记[*序号] == self.轮次 {
+                continue;
+            }
+            self.待处理标记[*序号] = self.轮次;
+            self.待处理.push(Reverse(*序号));
+        }
+    }
+
+    /// 只重新计算受移动的元素影响的部分
+    fn 增量编码(&mut self, 映射: &元素映射) {
+        self.轮次 += 1;
+        // 1. 找出改变了键位的元素，以及包含它们的字词
+        let mut 受影响的词 = vec![];
+        for (元素, (新键, 旧键)) in 映射.iter().zip(&mut self.当前映射).enumerate() {
+            if 新键 == 旧键 {
+                continue;
+            }
+            *旧键 = *新键;
+            for 序号 in &self.包含元素的词[元素] {
+                if self.词标记[*序号] != self.轮次 {
+                    self.词标记[*序号] = self.轮次;
+                    受影响的词.push(*序号);
                 }
-                // 如果没有达到上限，就可以出这个简码
-                let 编码 = 编码配置.生成编码(原始编码, 序号, 乘数);
-                简码信息.原始编码 = 原始编码;
-                简码信息.原始编码候选位置 = 序号;
-                简码信息.更新(编码, false);
-                self.简码空间.添加(原始编码);
-                有简码 = true;
-                break;
             }
-            if !有简码 {
-                let 序号 = self.简码空间.查找数量(全码信息.原始编码);
-                简码信息.原始编码 = 全码信息.原始编码;
-                简码信息.原始编码候选位置 = 序号;
-                简码信息.更新(全码信息.实际编码, 序号 > 0);
-                self.简码空间.添加(全码信息.原始编码);
+        }
+        // 2. 更新全码，并重新确定变化过的全码上所有字词的候选位置
+        let mut 全码变化的词 = vec![];
+        let mut 变化的编码 = vec![];
+        for 序号 in 受影响的词 {
+            let 原始编码 = self.计算全码(序号, 映射);
+            let 全码信息 = &mut self.编码结果[序号].全码;
+            if 原始编码 == 全码信息.原始编码 {
+                continue;
+            }
+            self.全码分组.移除(全码信息.原始编码, 序号);
+            self.全码分组.添加(原始编码, 序号);
+            变化的编码.push(全码信息.原始编码);
+            变化的编码.push(原始编码);
+            全码信息.原始编码 = 原始编码;
+            全码变化的词.push(序号);
+        }
+        变化的编码.sort_unstable();
+        变化的编码.dedup();
+        for 原始编码 in &变化的编码 {
+            self.刷新全码(*原始编码);
+        }
+        if let Some(简码配置列表) = self.简码配置列表.clone() {
+            self.增量编码简码(&简码配置列表[..], 全码变化的词, 变化的编码);
+        }
+    }
+
+    fn 增量编码简码(
+        &mut self,
+        简码配置列表: &[Vec<简码配置>],
+        全码变化的词: Vec<usize>,
+        mut 变化的编码: Vec<编码>,
+    ) {
+        // 3. 更新优先简码
+        let mut 变化的优先简码 = vec![];
+        for 序号 in &全码变化的词 {
+            let 简码等级 = self.词信息[*序号].简码等级;
+            if 简码等级 == u64::MAX {
+                continue;
+            }
+            let 原始编码 =
+                self.编码结果[*序号].全码.原始编码 % self.编码配置.乘数列表[简码等级 as usize];
+            let 简码信息 = &mut self.编码结果[*序号].简码;
+            if 原始编码 == 简码信息.原始编码 {
+                continue;
+            }
+            self.优先简码分组.移除(简码信息.原始编码, *序号);
+            self.优先简码分组.添加(原始编码, *序号);
+            变化的优先简码.push(简码信息.原始编码);
+            变化的优先简码.push(原始编码);
+            简码信息.原始编码 = 原始编码;
+        }
+        变化的优先简码.sort_unstable();
+        变化的优先简码.dedup();
+        for 原始编码 in &变化的优先简码 {
+            self.刷新优先简码(*原始编码);
+        }
+        // 4. 全码或优先简码的占用变化了的编码会影响所有查询它们的常规简码，全码变化了的字词本身也要重新判断
+        for 序号 in 全码变化的词 {
+            let 词 = &self.词信息[序号];
+            if 词.简码等级 != u64::MAX {
+                continue;
+            }
+            let 全码 = self.编码结果[序号].全码.原始编码;
+            let 简码配置 = &简码配置列表[词.词长 - 1];
+            let 索引 = &mut self.简码查询索引;
+            for 编码 in Self::查询编码(简码配置, &self.编码配置.乘数列表, 全码) {
+                索引.索引.entry(编码).or_default().push(序号);
+                索引.过期条目数 += 1;
+            }
+            if self.待处理标记[序号] != self.轮次 {
+                self.待处理标记[序号] = self.轮次;
+                self.待处理.push(Reverse(序号));
             }
         }
+        变化的编码.extend(变化的优先简码);
+        for 编码 in 变化的编码 {
+            self.标记变化(编码, None);
+        }
+        // 5. 按序号从小到大重新判断常规简码，某个字词的简码改变时，序号在后、查询新旧两个编码的字词也要重新判断
+        while let Some(Reverse(序号)) = self.待处理.pop() {
+            let 简码配置 = &简码配置列表[self.词信息[序号].词长 - 1];
+            let 旧原始编码 = self.写入常规简码(简码配置, 序号);
+            let 原始编码 = self.编码结果[序号].简码.原始编码;
+            if 原始编码 == 旧原始编码 {
+                continue;
+            }
+            self.常规简码分组.移除(旧原始编码, 序号);
+            self.常规简码分组.添加(原始编码, 序号);
+            self.标记变化(旧原始编码, Some(序号));
+            self.标记变化(原始编码, Some(序号));
+        }
+        let 索引 = &self.简码查询索引;
+        if 索引.过期条目数 > 索引.有效条目数 {
+            self.重建简码查询索引(简码配置列表);
+        }
     }
 }
 
 impl 编码器 for 默认编码器 {
+    /// 未提供移动的元素时从头编码；否则增量编码，此时移动的元素只作为提示，实际改变的元素由与上一次映射的比较得出
     fn 编码(
         &mut self,
         映射: &元素映射,
         移动的元素: &Option<Vec<元素>>,
     ) -> &mut Vec<编码信息> {
-        self.重置();
-        self.输出全码(映射, 移动的元素);
-        if self.编码配置.简码配置列表.is_none()
-            || self.编码配置.简码配置列表.as_ref().unwrap().is_empty()
-        {
-            return &mut self.编码结果;
-        }
-        self.输出简码();
+        if 移动的元素.is_none() || self.当前映射.len() != 映射.len() {
+            self.完整编码(映射);
+        } else {
+            self.增量编码(映射);
+        }
         &mut self.编码结果
     }
+
+    fn 估计内存(&self) -> usize {
+        let 分组 = [&self.全码分组, &self.优先简码分组, &self.常规简码分组];
+        let 查询索引 = &self.简码查询索引.索引;
+        let 索引: usize = 查询索引.values().map(|x| x.capacity() * size_of::<usize>()).sum();
+        分组.iter().map(|x| x.估计内存()).sum::<usize>()
+            + 哈希表内存(查询索引)
+            + self.编码结果.capacity() * size_of::<编码信息>()
+            + (self.词标记.capacity() + self.待处理标记.capacity()) * size_of::<u64>()
+            + self.当前映射.capacity() * size_of::<u64>()
+            + 索引
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::config::{Mapped, 配置};
+    use crate::data::{原始可编码对象, 部分编码信息};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::collections::HashMap;
+
+    pub(in crate::encoders) fn 实际值(编码: &部分编码信息) -> (编码, bool, u8) {
+        (编码.实际编码, 编码.选重标记, 编码.候选位置)
+    }
+
+    pub(in crate::encoders) fn 对比(增量: &部分编码信息, 完整: &部分编码信息, 上一步: &部分编码信息, 说明: &str) {
+        assert_eq!(增量.原始编码, 完整.原始编码, "{}", 说明);
+        assert_eq!(增量.原始编码候选位置, 完整.原始编码候选位置, "{}", 说明);
+        assert_eq!(实际值(增量), 实际值(完整), "{}", 说明);
+        // 有变化时，上一个编码必须是上一步的编码，这样目标函数才能正确地撤销旧编码的贡献
+        if 实际值(增量) == 实际值(上一步) {
+            assert!(!增量.有变化, "{}", 说明);
+        } else {
+            assert!(增量.有变化, "{}", 说明);
+            let 上一个 = (增量.上一个实际编码, 增量.上一个选重标记, 增量.上一个候选位置);
+            assert_eq!(上一个, 实际值(上一步), "{}", 说明);
+        }
+    }
+
+    #[test]
+    fn 增量编码与完整编码一致() {
+        let 字母表 = "abcdefgh";
+        let 元素列表: Vec<_> = "甲乙丙丁戊己庚辛壬癸子丑寅卯辰巳午未申酉戌亥天地玄黄宇宙洪荒日月盈昃暑宿列张寒来"
+            .chars()
+            .map(|x| x.to_string())
+            .collect();
+        let mut 配置 = 配置::default();
+        配置.form.alphabet = 字母表.to_string();
+        for (元素, 键) in zip(&元素列表, 字母表.chars().cycle()) {
+            配置
+                .form
+                .mapping
+                .insert(元素.clone(), Mapped::Basic(键.to_string()));
+        }
+        配置.encoder.max_length = 3;
+        配置.encoder.select_keys = Some(vec!['_', ';']);
+        配置.encoder.short_code = Some(
+            serde_yaml::from_str(
+                "[{length_equal: 1, schemes: [{prefix: 1, count: 2}]}, {length_equal: 3, schemes: [{prefix: 1}, {prefix: 2, count: 2}]}]",
+            )
+            .unwrap(),
+        );
+        // 每个键上有多个元素，且频率随机，这样争夺同一个简码的字词不总是包含同一个元素
+        let mut 随机数生成器 = StdRng::seed_from_u64(0);
+        let mut 词列表: Vec<_> = 元素列表[..2].iter().map(|x| vec![x]).collect();
+        for _ in 0..600 {
+            let 词 = (0..3)
+                .map(|_| &元素列表[随机数生成器.gen_range(0..元素列表.len())])
+                .collect();
+            词列表.push(词);
+        }
+        let 词列表: Vec<_> = 词列表
+            .into_iter()
+            .enumerate()
+            .map(|(序号, 词)| 原始可编码对象 {
+                name: 词.iter().map(|x| x.as_str()).collect(),
+                sequence: 词.iter().map(|x| x.as_str()).collect::<Vec<_>>().join(" "),
+                frequency: 随机数生成器.gen_range(1..1000),
+                // 一部分词有优先简码
+                level: match 序号 % 50 {
+                    7 => 1,
+                    8 => 2,
+                    _ => u64::MAX,
+                },
+            })
+            .collect();
+        let 数据 = 数据::新建(配置, 词列表, HashMap::new(), HashMap::new()).unwrap();
+        let 键列表: Vec<_> = 字母表.chars().map(|x| 数据.键转数字[&x]).collect();
+        let 元素序号: Vec<_> = 元素列表.iter().map(|x| 数据.元素转数字[x]).collect();
+        let mut 编码器 = 默认编码器::新建(&数据).unwrap();
+        let mut 当前解 = 数据.初始映射.to_vec();
+        let mut 上一步 = 编码器.编码(&当前解, &None).clone();
+        let mut 用到优先简码 = false;
+        let mut 用到常规简码 = false;
+        let mut 上次变异: Vec<元素> = vec![];
+        for 步数 in 0..500 {
+            // 模拟目标函数，处理完变化之后清除标记
+            for 编码信息 in 编码器.编码结果.iter_mut() {
+                编码信息.全码.有变化 = false;
+                编码信息.简码.有变化 = false;
+            }
+            let mut 新解 = 当前解.clone();
+            let 元素 = 元素序号[随机数生成器.gen_range(0..元素序号.len())];
+            新解[元素] = 键列表[随机数生成器.gen_range(0..键列表.len())];
+            let 变异元素 = vec![元素];
+            let mut 移动的元素 = 变异元素.clone();
+            移动的元素.extend(&上次变异);
+            let 增量 = 编码器.编码(&新解, &Some(移动的元素)).clone();
+            let mut 重新编码器 = 默认编码器::新建(&数据).unwrap();
+            let 完整 = 重新编码器.编码(&新解, &None);
+            for (序号, 词) in 数据.词列表.iter().enumerate() {
+                let 说明 = format!("第 {} 步，词「{}」", 步数, 词.名称);
+                对比(&增量[序号].全码, &完整[序号].全码, &上一步[序号].全码, &说明);
+                对比(&增量[序号].简码, &完整[序号].简码, &上一步[序号].简码, &说明);
+                if 增量[序号].简码.原始编码 != 增量[序号].全码.原始编码 {
+                    if 词.简码等级 == u64::MAX {
+                        用到常规简码 = true;
+                    } else {
+                        用到优先简码 = true;
+                    }
+                }
+            }
+            // 交替接受和拒绝，拒绝时下一次编码需要一并撤销这次变异
+            if 步数 % 2 == 0 {
+                当前解 = 新解;
+                上次变异.clear();
+            } else {
+                上次变异 = 变异元素;
+            }
+            上一步 = 增量;
+        }
+        assert!(用到优先简码 && 用到常规简码);
+    }
 }
@@ -5,8 +5,11 @@ use crate::{
     错误,
 };
 use rustc_hash::FxHashMap;
+use std::mem::size_of;
+use std::sync::Arc;
 
 pub mod default;
+pub mod top_up;
 
 pub trait 编码器 {
     fn 编码(
@@ -14,37 +17,100 @@ pub trait 编码器 {
         keymap: &元素映射,
         moved_elements: &Option<Vec<元素>>,
     ) -> &mut Vec<编码信息>;
+
+    /// 编码器当前占用内存的估计值，以字节为单位，不含与其他线程共享的只读数据
+    fn 估计内存(&self) -> usize {
+        0
+    }
 }
 
-#[derive(Clone)]
-pub struct 编码空间 {
-    pub 线性表: Vec<u8>,
-    pub 线性表长度: usize,
-    pub 哈希表: FxHashMap<编码, u8>,
+impl<T: 编码器 + ?Sized> 编码器 for Box<T> {
+    fn 编码(
+        &mut self,
+        keymap: &元素映射,
+        moved_elements: &Option<Vec<元素>>,
+    ) -> &mut Vec<编码信息> {
+        (**self).编码(keymap, moved_elements)
+    }
+
+    fn 估计内存(&self) -> usize {
+        (**self).估计内存()
+    }
 }
 
-impl 编码空间 {
-    #[inline(always)]
-    pub fn 添加(&mut self, 编码: u64) {
-        if 编码 < self.线性表长度 as u64 {
-            let 编码 = 编码 as usize;
-            self.线性表[编码] = self.线性表[编码].saturating_add(1);
-        } else {
-            self.哈希表
-                .entry(编码)
-                .and_modify(|x| *x = x.saturating_add(1))
-                .or_insert(1);
+/// 根据配置选择编码器：配置了顶功时使用顶功编码器，否则使用默认编码器
+pub fn 新建编码器(数据: &数据) -> Result<Box<dyn 编码器 + Send>, 错误> {
+    if 数据.配置.encoder.top_up.is_some() {
+        Ok(Box::new(top_up::顶功编码器::新建(数据)?))
+    } else {
+        Ok(Box::new(default::默认编码器::新建(数据)?))
+    }
+}
+
+/// 按编码分组的字词序号，每组按序号升序排列
+///
+/// 一个字词在组内的位次就是它在这个编码上的候选位置，字词改变编码时只需调整两个组，便于增量地维护候选位置
+#[derive(Debug, Clone, Default)]
+pub struct 编码分组 {
+    分组: FxHashMap<编码, Vec<usize>>,
+}
+
+impl 编码分组 {
+    pub fn 清空(&mut self) {
+        self.分组.clear();
+    }
+
+    pub fn 添加(&mut self, 编码: 编码, 序号: usize) {
+        let 组 = self.分组.entry(编码).or_default();
+        match 组.last() {
+            Some(x) if *x > 序号 => {
+                if let Err(位置) = 组.binary_search(&序号) {
+                    组.insert(位置, 序号);
+                }
+            }
+            _ => 组.push(序号),
         }
     }
 
-    #[inline(always)]
-    pub fn 查找数量(&self, 编码: u64) -> u8 {
-        if 编码 < self.线性表长度 as u64 {
-            self.线性表[编码 as usize]
-        } else {
-            *self.哈希表.get(&编码).unwrap_or(&0)
+    pub fn 移除(&mut self, 编码: 编码, 序号: usize) {
+        if let Some(组) = self.分组.get_mut(&编码) {
+            if let Ok(位置) = 组.binary_search(&序号) {
+                组.remove(位置);
+            }
+            if 组.is_empty() {
+                self.分组.remove(&编码);
+            }
         }
     }
+
+    pub fn 成员(&self, 编码: 编码) -> &[usize] {
+        self.分组.get(&编码).map_or(&[], |x| x)
+    }
+
+    pub fn 数量(&self, 编码: 编码) -> usize {
+        self.成员(编码).len()
+    }
+
+    pub fn 估计内存(&self) -> usize {
+        哈希表内存(&self.分组) + self.分组.values().map(|x| x.capacity() * size_of::<usize>()).sum::<usize>()
+    }
+
+    /// 组内序号小于给定序号的字词数量
+    pub fn 位次(&self, 编码: 编码, 序号: usize) -> usize {
+        let 组 = self.成员(编码);
+        组.binary_search(&序号).unwrap_or_else(|x| x)
+    }
+}
+
+/// 哈希表占用内存的估计值，按每个槽位存放一个键值对和一个字节的控制信息计算
+pub fn 哈希表内存<K, V>(表: &FxHashMap<K, V>) -> usize {
+    表.capacity() * (size_of::<(K, V)>() + 1)
+}
+
+/// 候选位置超过 u8 的范围时按最大值计算
+#[inline(always)]
+pub fn 截断位置(位置: usize) -> u8 {
+    位置.min(u8::MAX as usize) as u8
 }
 
 #[derive(Debug)]
@@ -57,7 +123,7 @@ pub struct 编码配置 {
     pub 进制: u64,
     pub 乘数列表: Vec<u64>,
     pub 最大码长: usize,
-    pub 自动上屏查找表: 自动上屏,
+    pub 自动上屏查找表: Arc<自动上屏>,
     pub 选择键: Vec<键>,
     pub 首选键: 键,
     pub 简码配置列表: Option<[Vec<简码配置>; 最大词长]>,
@@ -65,24 +131,27 @@ pub struct 编码配置 {
 
 impl 编码配置 {
     pub fn new(数据: &数据) -> Result<Self, 错误> {
-        let 编码器配置 = &数据.配置.encoder;
-        let 最大码长 = 编码器配置.max_length;
+        let mut 编码配置 = Self::不含简码(数据)?;
+        if let Some(configs) = &数据.配置.encoder.short_code {
+            编码配置.简码配置列表 = Some(数据.预处理简码配置(configs.clone())?);
+        }
+        Ok(编码配置)
+    }
+
+    /// 不预处理简码配置，供自己不出简码的编码器使用
+    pub fn 不含简码(数据: &数据) -> Result<Self, 错误> {
+        let 最大码长 = 数据.配置.encoder.max_length;
         if 最大码长 >= 8 {
             return Err("目前暂不支持最大码长大于等于 8 的方案计算！".into());
         }
-        let 自动上屏查找表 = 数据.预处理自动上屏()?;
-        let mut 简码配置列表 = None;
-        if let Some(configs) = &编码器配置.short_code {
-            简码配置列表 = Some(数据.预处理简码配置(configs.clone())?);
-        }
         Ok(Self {
-            自动上屏查找表,
+            自动上屏查找表: 数据.自动上屏查找表.clone(),
             最大码长,
             进制: 数据.进制,
             乘数列表: (0..=最大码长).map(|x| 数据.进制.pow(x as u32)).collect(),
             选择键: 数据.选择键.clone(),
             首选键: 数据.选择键[0],
-            简码配置列表,
+            简码配置列表: None,
         })
     }
 
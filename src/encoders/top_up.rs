@@ -0,0 +1,390 @@
+//! 顶功编码器
+//!
+//! 在顶功方案中，按键分为首码键和其余的键两类，每个编码都应以首码键开头。用户输入下一个编码的首码时，前一个编码会自动上屏，因此大多数编码不需要额外输入空格或选择键。但是，如果一个编码之后再输入一个首码键，恰好构成了码表中另一个更长的编码（或其前缀），输入法就无法判断用户是想顶屏还是继续输入，这时前一个编码仍需用空格确认。
+//!
+//! 原始编码、候选位置和简码都与默认编码器相同，直接由默认编码器增量地计算；本编码器只在此基础上按照这一模型计算每个编码的实际编码：
+//!
+//! 1. 首选编码若达到自动上屏条件，则直接上屏；
+//! 2. 否则，若在其后输入任何一个首码键都不会与码表中的其他编码产生歧义，则视为由下一个编码顶功上屏；
+//! 3. 否则，需要加上首选键；
+//! 4. 非首选的编码总是需要加上相应的选择键；
+//! 5. 不以首码键开头的编码无法顶掉前一个编码，输入它之前需要先用首选键确认前一个编码，这个首选键计入它自己的实际编码。
+
+use super::default::默认编码器;
+use super::{哈希表内存, 编码器, 编码配置};
+use crate::data::{元素, 元素映射, 数据, 编码, 编码信息, 键};
+use crate::错误;
+use rustc_hash::FxHashMap;
+use std::mem::size_of;
+
+pub struct 顶功编码器 {
+    默认编码器: 默认编码器,
+    编码结果: Vec<编码信息>,
+    编码配置: 编码配置,
+    首码键: Vec<键>,
+    /// 按键的编号索引，是否为首码键
+    是首码键: Vec<bool>,
+    未配置自动上屏: bool,
+    /// 码表中的每个原始全码和原始简码的各级前缀（包括它本身）被多少个编码占用
+    前缀计数: FxHashMap<编码, u32>,
+    /// 每个字词目前计入前缀计数的原始全码和原始简码，没有简码时后者为零
+    已登记编码: Vec<[编码; 2]>,
+    待刷新: Vec<usize>,
+    刷新标记: Vec<u64>,
+    轮次: u64,
+}
+
+impl 顶功编码器 {
+    pub fn 新建(数据: &数据) -> Result<Self, 错误> {
+        let 编码器配置 = &数据.配置.encoder;
+        let 顶功配置 = 编码器配置.top_up.as_ref().ok_or("顶功配置不存在")?;
+        // 实际编码在全码之外还可能加上选择键和确认前一个编码的首选键，不能超出目标函数支持的码长
+        if 编码器配置.max_length > 6 {
+            return Err("目前暂不支持最大码长大于 6 的顶功方案计算！".into());
+        }
+        let mut 首码键 = vec![];
+        let mut 是首码键 = vec![false; 数据.进制 as usize];
+        for 键 in &顶功配置.leading_keys {
+            let 键 = 数据
+                .键转数字
+                .get(键)
+                .ok_or(format!("顶功的首码键 {键} 不在字母表中"))?;
+            首码键.push(*键);
+            是首码键[*键 as usize] = true;
+        }
+        if 首码键.is_empty() {
+            return Err("顶功的首码键不能为空！".into());
+        }
+        let mut 默认编码器 = 默认编码器::新建(数据)?;
+        默认编码器.开启刷新记录();
+        // 简码由默认编码器处理，这里只需要码长和选择键等信息
+        let 编码配置 = 编码配置::不含简码(数据)?;
+        let 词数 = 数据.词列表.len();
+        let 未配置自动上屏 =
+            编码器配置.auto_select_length.is_none() && 编码器配置.auto_select_pattern.is_none();
+        Ok(Self {
+            默认编码器,
+            编码结果: 数据.词列表.iter().map(编码信息::new).collect(),
+            编码配置,
+            首码键,
+            是首码键,
+            未配置自动上屏,
+            前缀计数: FxHashMap::default(),
+            已登记编码: vec![],
+            待刷新: vec![],
+            刷新标记: vec![0; 词数],
+            轮次: 0,
+        })
+    }
+
+    fn 码长(&self, 编码: 编码) -> usize {
+        self.编码配置
+            .乘数列表
+            .iter()
+            .position(|x| 编码 < *x)
+            .unwrap_or(self.编码配置.乘数列表.len())
+    }
+
+    /// 判断一个首选编码能否不加首选键直接上屏
+    fn 能否直接上屏(&self, 原始编码: 编码) -> bool {
+        let 码长 = self.码长(原始编码);
+        // 未配置自动上屏规则时，顶功方案只有达到最大码长的编码才自动上屏
+        let 自动上屏 = if self.未配置自动上屏 {
+            码长 >= self.编码配置.最大码长
+        } else {
+            *self
+                .编码配置
+                .自动上屏查找表
+                .get(原始编码 as usize)
+                .unwrap_or(&true)
+        };
+        if 自动上屏 {
+            return true;
+        }
+        // 在其后输入任意一个首码键，都不能构成码表中另一个编码的前缀
+        let 乘数 = self.编码配置.乘数列表[码长];
+        self.首码键
+            .iter()
+            .all(|键| !self.前缀计数.contains_key(&(原始编码 + 键 * 乘数)))
+    }
+
+    fn 生成编码(&self, 原始编码: 编码, 候选位置: u8) -> 编码 {
+        let 编码配置 = &self.编码配置;
+        let 编码 = if 候选位置 == 0 && self.能否直接上屏(原始编码) {
+            原始编码
+        } else {
+            let 选择键 = *编码配置
+                .选择键
+                .get(候选位置 as usize)
+                .unwrap_or(&编码配置.首选键);
+            原始编码 + 选择键 * 编码配置.乘数列表[self.码长(原始编码)]
+        };
+        if self.是首码键[(原始编码 % 编码配置.进制) as usize] {
+            编码
+        } else {
+            编码 * 编码配置.进制 + 编码配置.首选键
+        }
+    }
+
+    /// 字词计入码表的原始全码和原始简码
+    fn 原始编码列表(&self, 序号: usize) -> [编码; 2] {
+        let 编码信息 = &self.默认编码器.编码结果()[序号];
+        let 简码 = if self.默认编码器.有简码() {
+            编码信息.简码.原始编码
+        } else {
+            0
+        };
+        [编码信息.全码.原始编码, 简码]
+    }
+
+    /// 把一组编码的各级前缀计入或移出前缀计数，有无发生变化的前缀记入列表
+    fn 登记前缀(&mut self, 编码列表: [编码; 2], 计入: bool, 变化的前缀: &mut Vec<编码>) {
+        for 原始编码 in 编码列表 {
+            if 原始编码 == 0 {
+                continue;
+            }
+            for 乘数 in &self.编码配置.乘数列表[1..] {
+                let 前缀 = 原始编码 % 乘数;
+                let 计数 = self.前缀计数.entry(前缀).or_default();
+                if 计入 {
+                    *计数 += 1;
+                } else {
+                    *计数 -= 1;
+                }
+                match *计数 {
+                    0 => {
+                        self.前缀计数.remove(&前缀);
+                        变化的前缀.push(前缀);
+                    }
+                    1 if 计入 => 变化的前缀.push(前缀),
+                    _ => {}
+                }
+                if 原始编码 < *乘数 {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// 从默认编码器取得字词的原始编码和候选位置，重新生成实际编码
+    fn 刷新(&mut self, 序号: usize) {
+        let 默认结果 = &self.默认编码器.编码结果()[序号];
+        let (全码, 全码位置) = (默认结果.全码.原始编码, 默认结果.全码.原始编码候选位置);
+        let (简码, 简码位置) = (默认结果.简码.原始编码, 默认结果.简码.原始编码候选位置);
+        let 简码选重 = 默认结果.简码.选重标记;
+        // 与默认编码器相同，全码统一视为首选进行编码
+        let 实际全码 = self.生成编码(全码, 0);
+        let 实际简码 = self.默认编码器.有简码().then(|| self.生成编码(简码, 简码位置));
+        let 编码信息 = &mut self.编码结果[序号];
+        编码信息.全码.原始编码 = 全码;
+        编码信息.全码.原始编码候选位置 = 全码位置;
+        编码信息.全码.更新(实际全码, 全码位置 > 0, 全码位置);
+        if let Some(实际简码) = 实际简码 {
+            编码信息.简码.原始编码 = 简码;
+            编码信息.简码.原始编码候选位置 = 简码位置;
+            编码信息.简码.更新(实际简码, 简码选重, 简码位置);
+        }
+    }
+
+    fn 完整编码(&mut self, 映射: &元素映射) {
+        self.默认编码器.编码(映射, &None);
+        self.默认编码器.交换刷新记录(&mut self.待刷新);
+        self.前缀计数.clear();
+        let 词数 = self.编码结果.len();
+        let 已登记编码: Vec<_> = (0..词数).map(|x| self.原始编码列表(x)).collect();
+        let mut 变化的前缀 = vec![];
+        for 编码列表 in &已登记编码 {
+            self.登记前缀(*编码列表, true, &mut 变化的前缀);
+            变化的前缀.clear();
+        }
+        self.已登记编码 = 已登记编码;
+        for 序号 in 0..词数 {
+            self.刷新(序号);
+        }
+    }
+
+    /// 只更新默认编码器重新计算过的字词的前缀登记，再刷新这些字词，以及能否顶功可能因前缀的有无而改变的字词
+    fn 增量编码(&mut self, 映射: &元素映射, 移动的元素: &Option<Vec<元素>>) {
+        self.轮次 += 1;
+        self.默认编码器.编码(映射, 移动的元素);
+        self.默认编码器.交换刷新记录(&mut self.待刷新);
+        let mut 待刷新 = std::mem::take(&mut self.待刷新);
+        待刷新.retain(|x| {
+            let 首次 = self.刷新标记[*x] != self.轮次;
+            self.刷新标记[*x] = self.轮次;
+            首次
+        });
+        let mut 变化的前缀 = vec![];
+        for 序号 in &待刷新 {
+            let 编码列表 = self.原始编码列表(*序号);
+            let 旧编码列表 = self.已登记编码[*序号];
+            if 编码列表 != 旧编码列表 {
+                self.登记前缀(旧编码列表, false, &mut 变化的前缀);
+                self.登记前缀(编码列表, true, &mut 变化的前缀);
+                self.已登记编码[*序号] = 编码列表;
+            }
+        }
+        // 一个前缀的有无改变时，去掉它最后的首码键得到的编码能否被顶功上屏也可能随之改变
+        for 前缀 in 变化的前缀 {
+            let 码长 = self.码长(前缀);
+            if 码长 < 2 {
+                continue;
+            }
+            let 乘数 = self.编码配置.乘数列表[码长 - 1];
+            if !self.是首码键[(前缀 / 乘数) as usize] {
+                continue;
+            }
+            for 序号 in self.默认编码器.原始编码为(前缀 % 乘数) {
+                if self.刷新标记[序号] != self.轮次 {
+                    self.刷新标记[序号] = self.轮次;
+                    待刷新.push(序号);
+                }
+            }
+        }
+        for 序号 in &待刷新 {
+            self.刷新(*序号);
+        }
+        self.待刷新 = 待刷新;
+    }
+}
+
+impl 编码器 for 顶功编码器 {
+    /// 未提供移动的元素时从头编码；否则增量编码
+    fn 编码(
+        &mut self,
+        映射: &元素映射,
+        移动的元素: &Option<Vec<元素>>,
+    ) -> &mut Vec<编码信息> {
+        if 移动的元素.is_none() || self.已登记编码.is_empty() {
+            self.完整编码(映射);
+        } else {
+            self.增量编码(映射, 移动的元素);
+        }
+        &mut self.编码结果
+    }
+
+    fn 估计内存(&self) -> usize {
+        self.默认编码器.估计内存()
+            + 哈希表内存(&self.前缀计数)
+            + self.编码结果.capacity() * size_of::<编码信息>()
+            + self.已登记编码.capacity() * size_of::<[编码; 2]>()
+            + self.待刷新.capacity() * size_of::<usize>()
+            + self.刷新标记.capacity() * size_of::<u64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Mapped, TopUpConfig, 配置};
+    use crate::data::原始可编码对象;
+    use crate::encoders::default::tests::对比;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::collections::HashMap;
+
+    fn 构造数据(字母表: &str, 元素列表: &[(&str, char)], 首码键: &str, 词列表: &[(&str, &str)]) -> 数据 {
+        构造配置数据(配置::default(), 字母表, 元素列表, 首码键, 词列表)
+    }
+
+    fn 构造配置数据(
+        mut 配置: 配置,
+        字母表: &str,
+        元素列表: &[(&str, char)],
+        首码键: &str,
+        词列表: &[(&str, &str)],
+    ) -> 数据 {
+        配置.form.alphabet = 字母表.to_string();
+        for (元素, 键) in 元素列表 {
+            配置
+                .form
+                .mapping
+                .insert(元素.to_string(), Mapped::Basic(键.to_string()));
+        }
+        配置.encoder.max_length = 配置.encoder.max_length.max(2);
+        配置.encoder.top_up = Some(TopUpConfig {
+            leading_keys: 首码键.chars().collect(),
+        });
+        let 词列表 = 词列表
+            .iter()
+            .map(|(name, sequence)| 原始可编码对象 {
+                name: name.to_string(),
+                sequence: sequence.to_string(),
+                frequency: 1,
+                level: u64::MAX,
+            })
+            .collect();
+        数据::新建(配置, 词列表, HashMap::new(), HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn 顶功歧义需要确认() {
+        let 元素列表 = [("甲", 'a'), ("乙", 'b'), ("丙", 'c'), ("丁", 'd')];
+        let 词列表 = [("一", "甲"), ("二", "甲 乙"), ("三", "丙"), ("四", "丁")];
+        let 数据 = 构造数据("abcd", &元素列表, "abc", &词列表);
+        let mut 编码器 = 顶功编码器::新建(&数据).unwrap();
+        let 编码结果 = 编码器.编码(&数据.初始映射, &None).clone();
+        let 实际编码: HashMap<String, String> = 数据
+            .词列表
+            .iter()
+            .zip(编码结果)
+            .map(|(词, 编码信息)| {
+                let 编码 = 数据::数字转编码(编码信息.全码.实际编码, 数据.进制, &数据.数字转键);
+                (词.名称.clone(), 编码.into_iter().collect())
+            })
+            .collect();
+        // 「a」之后输入首码键「b」会构成「ab」，需要用空格确认；「c」则可以被顶功上屏
+        assert_eq!(实际编码["一"], "a_");
+        assert_eq!(实际编码["二"], "ab");
+        assert_eq!(实际编码["三"], "c");
+        // 「d」不是首码键，输入它之前要先确认前一个编码
+        assert_eq!(实际编码["四"], "_d");
+    }
+
+    #[test]
+    fn 增量编码与完整编码一致() {
+        let 元素列表: Vec<_> = "甲乙丙丁戊己庚辛壬癸子丑"
+            .chars()
+            .zip("abcdefghabcd".chars())
+            .map(|(元素, 键)| (元素.to_string(), 键))
+            .collect();
+        let 元素引用: Vec<_> = 元素列表.iter().map(|(x, y)| (x.as_str(), *y)).collect();
+        let mut 随机数生成器 = StdRng::seed_from_u64(0);
+        let mut 词列表 = vec![];
+        // 字词较少，前缀的有无才会随着元素的移动而改变
+        for _ in 0..40 {
+            let 词长 = 随机数生成器.gen_range(1..=3);
+            let 词: Vec<_> = (0..词长)
+                .map(|_| 元素列表[随机数生成器.gen_range(0..元素列表.len())].0.as_str())
+                .collect();
+            词列表.push((词.concat(), 词.join(" ")));
+        }
+        let 词引用: Vec<_> = 词列表.iter().map(|(x, y)| (x.as_str(), y.as_str())).collect();
+        let mut 配置 = 配置::default();
+        配置.encoder.max_length = 3;
+        配置.encoder.short_code =
+            Some(serde_yaml::from_str("[{length_equal: 3, schemes: [{prefix: 2}]}]").unwrap());
+        let 数据 = 构造配置数据(配置, "abcdefgh", &元素引用, "abcde", &词引用);
+        let 键列表: Vec<_> = "abcdefgh".chars().map(|x| 数据.键转数字[&x]).collect();
+        let mut 编码器 = 顶功编码器::新建(&数据).unwrap();
+        let mut 当前解 = 数据.初始映射.to_vec();
+        let mut 上一步 = 编码器.编码(&当前解, &None).clone();
+        for 步数 in 0..300 {
+            for 编码信息 in 编码器.编码结果.iter_mut() {
+                编码信息.全码.有变化 = false;
+                编码信息.简码.有变化 = false;
+            }
+            let mut 新解 = 当前解.clone();
+            let 元素 = 数据.元素转数字[&元素列表[随机数生成器.gen_range(0..元素列表.len())].0];
+            新解[元素] = 键列表[随机数生成器.gen_range(0..键列表.len())];
+            let 增量 = 编码器.编码(&新解, &Some(vec![元素])).clone();
+            let 完整 = 顶功编码器::新建(&数据).unwrap().编码(&新解, &None).clone();
+            for (序号, 词) in 数据.词列表.iter().enumerate() {
+                let 说明 = format!("第 {} 步，词「{}」", 步数, 词.名称);
+                对比(&增量[序号].全码, &完整[序号].全码, &上一步[序号].全码, &说明);
+                对比(&增量[序号].简码, &完整[序号].简码, &上一步[序号].简码, &说明);
+            }
+            当前解 = 新解;
+            上一步 = 增量;
+        }
+    }
+}
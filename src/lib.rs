@@ -2,36 +2,48 @@
 //!
 //! chai 是使用 libchai 实现的命令行程序，用户提供方案配置文件、拆分表和评测信息，本程序能够生成编码并评测一系列指标，以及基于退火算法优化元素的布局。
 
+pub mod compare;
 pub mod config;
+pub mod corpus;
 pub mod data;
 pub mod encoders;
 pub mod objectives;
 pub mod operators;
 pub mod optimizers;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod render;
 
 use chrono::Local;
 use clap::{Parser, Subcommand};
-use config::{ObjectiveConfig, OptimizationConfig, SolverConfig, 配置};
+use compare::方案差异;
+use config::{ObjectiveConfig, OptimizationConfig, 配置};
 use console_error_panic_hook::set_once;
+use corpus::{分词方式, 分配频率, 是汉字, 模拟结果, 统计频率, 词典, 语料指标};
 use csv::{ReaderBuilder, WriterBuilder};
 use data::{原始可编码对象, 数据};
-use data::{原始当量信息, 原始键位分布信息, 码表项};
-use encoders::default::默认编码器;
-use encoders::编码器;
+use data::{元素映射, 原始当量信息, 原始键位分布信息, 码表项, 编码, 编码信息};
+use encoders::{新建编码器, 编码器};
 use js_sys::Function;
 use objectives::default::默认目标函数;
+use objectives::metric::默认指标;
 use objectives::目标函数;
-use operators::default::默认操作;
+use operators::default::{变异配置, 默认操作};
 use optimizers::{优化方法, 优化问题};
+use render::字形渲染器;
+use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
 use serde_wasm_bindgen::{from_value, to_value, Serializer};
 use serde_with::skip_serializing_none;
-use std::collections::HashMap;
+use std::cmp::Reverse;
 use std::fmt::Display;
 use std::fs::{create_dir_all, read_to_string, write, OpenOptions};
 use std::io::{self, Write};
 use std::iter::FromIterator;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use wasm_bindgen::{prelude::*, JsError};
 
 /// 错误类型
@@ -69,7 +81,7 @@ impl From<错误> for JsError {
 }
 
 /// 图形界面参数的定义
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct 图形界面参数 {
     pub 配置: 配置,
     pub 词列表: Vec<原始可编码对象>,
@@ -77,17 +89,6 @@ pub struct 图形界面参数 {
     pub 原始当量信息: 原始当量信息,
 }
 
-impl Default for 图形界面参数 {
-    fn default() -> Self {
-        Self {
-            配置: 配置::default(),
-            词列表: vec![],
-            原始键位分布信息: HashMap::new(),
-            原始当量信息: HashMap::new(),
-        }
-    }
-}
-
 /// 向用户反馈的消息类型
 #[derive(Serialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -108,16 +109,23 @@ pub enum 消息 {
     Progress {
         steps: usize,
         temperature: f64,
+        accept_rate: f64,
+        reheats: usize,
+        /// 自适应选取变异方式时，目前各种变异方式的概率
+        operator_probabilities: Option<变异配置>,
         metric: String,
     },
     BetterSolution {
         metric: String,
-        config: 配置,
+        config: Box<配置>,
         save: bool,
     },
     Elapsed {
         time: u64,
     },
+    MemoryEstimate {
+        bytes: usize,
+    },
 }
 
 /// 定义了向用户报告消息的接口，用于统一命令行和图形界面的输出方式
@@ -125,6 +133,11 @@ pub enum 消息 {
 /// 命令行界面、图形界面只需要各自实现 post 方法，就可向用户报告各种用户数据
 pub trait 界面 {
     fn 发送(&self, 消息: 消息);
+
+    /// 用户是否要求提前结束优化；优化方法在每一步开始前检查，被中断时照常报告目前的最优解后返回
+    fn 已中断(&self) -> bool {
+        false
+    }
 }
 
 /// 通过图形界面来使用 libchai 的入口，实现了界面特征
@@ -132,6 +145,174 @@ pub trait 界面 {
 pub struct Web {
     回调: Function,
     参数: 图形界面参数,
+    会话: Option<会话>,
+}
+
+/// 码表中一个字词的全码、全码候选位置、简码和简码候选位置
+type 码表签名 = (编码, u8, 编码, u8);
+
+/// 图形界面中保持的编码和评测状态
+///
+/// 数据只在同步参数后构造一次；移动元素时通过编码器的增量接口只重新计算受影响的字词，并用上一次的码表签名找出编码变化的字词
+struct 会话 {
+    数据: 数据,
+    编码器: Box<dyn 编码器 + Send>,
+    目标函数: 默认目标函数,
+    映射: 元素映射,
+    编码快照: Vec<码表签名>,
+    全码索引: FxHashMap<编码, Vec<usize>>,
+}
+
+/// 移动一个元素之后的评测指标、编码发生变化的字词、这些字词的新旧全码上现有的重码组，以及旧全码上因此消除的重码组
+#[derive(Debug, Serialize)]
+pub struct 移动结果 {
+    pub metric: 默认指标,
+    pub changes: Vec<码表项>,
+    pub collisions: Vec<重码组>,
+    /// 移动前的字词列表
+    pub resolved: Vec<重码组>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct 重码组 {
+    pub code: String,
+    pub words: Vec<String>,
+}
+
+impl 会话 {
+    fn 新建(数据: 数据) -> Result<Self, 错误> {
+        let 编码器 = 新建编码器(&数据)?;
+        let 目标函数 = 默认目标函数::新建(&数据)?;
+        let 映射 = 数据.初始映射.to_vec();
+        Ok(Self {
+            数据,
+            编码器,
+            目标函数,
+            映射,
+            编码快照: vec![],
+            全码索引: FxHashMap::default(),
+        })
+    }
+
+    /// 更换目标函数配置；新的目标函数没有任何计数，所以编码器也要重新构造，以便下一次编码时所有字词都计入
+    fn 更换目标函数(&mut self, 目标函数配置: ObjectiveConfig) -> Result<(), 错误> {
+        self.数据.正则化 = match &目标函数配置.regularization {
+            Some(正则化配置) => 数据::预处理正则化(正则化配置, &self.数据.元素转数字)?,
+            None => FxHashMap::default(),
+        };
+        Arc::make_mut(&mut self.数据.配置).optimization = Some(OptimizationConfig {
+            objective: 目标函数配置,
+            constraints: None,
+            metaheuristic: None,
+        });
+        self.编码器 = 新建编码器(&self.数据)?;
+        self.目标函数 = 默认目标函数::新建(&self.数据)?;
+        Ok(())
+    }
+
+    fn 签名(编码信息: &编码信息) -> 码表签名 {
+        (
+            编码信息.全码.原始编码,
+            编码信息.全码.原始编码候选位置,
+            编码信息.简码.原始编码,
+            编码信息.简码.原始编码候选位置,
+        )
+    }
+
+    /// 对当前映射完整编码和评测一次，并重建码表签名和全码索引
+    fn 完整计算(&mut self) -> (Vec<码表项>, 默认指标) {
+        let 编码结果 = self.编码器.编码(&self.映射, &None);
+        let 码表 = self.数据.生成码表(编码结果);
+        let (指标, _) = self.目标函数.计算(编码结果, &self.映射, 0.0);
+        self.编码快照 = 编码结果.iter().map(Self::签名).collect();
+        self.全码索引.clear();
+        for (序号, (全码, ..)) in self.编码快照.iter().enumerate() {
+            self.全码索引.entry(*全码).or_default().push(序号);
+        }
+        (码表, 指标)
+    }
+
+    /// 把一个元素移动到另一个键上，只重新编码和检查包含这个元素的字词
+    fn 移动元素(&mut self, 元素名称: &str, 键: char) -> Result<移动结果, 错误> {
+        let 元素 = *self
+            .数据
+            .元素转数字
+            .get(元素名称)
+            .filter(|x| **x >= self.数据.进制 as usize)
+            .ok_or(format!("元素 {} 不存在", 元素名称))?;
+        let 键 = *self
+            .数据
+            .键转数字
+            .get(&键)
+            .filter(|x| !self.数据.选择键.contains(x))
+            .ok_or(format!("按键 {} 不在字母表中", 键))?;
+        self.映射[元素] = 键;
+        let 编码结果 = self.编码器.编码(&self.映射, &Some(vec![元素]));
+        let (指标, _) = self.目标函数.计算(编码结果, &self.映射, 0.0);
+        // 只有包含这个元素的字词的全码会改变，其他字词的全码只可能因为与它们的新旧全码相同而改变候选位置；简码的连锁变化不逐一检查
+        let mut 变化的词 = vec![];
+        let mut 变化的全码 = vec![];
+        let mut 移动前的重码组 = FxHashMap::default();
+        for 序号 in self.数据.包含元素的词[元素].iter() {
+            let 签名 = Self::签名(&编码结果[*序号]);
+            let 旧签名 = self.编码快照[*序号];
+            if 签名.0 == 旧签名.0 {
+                continue;
+            }
+            if let Some(列表) = self.全码索引.get_mut(&旧签名.0) {
+                if 列表.len() > 1 {
+                    移动前的重码组.entry(旧签名.0).or_insert_with(|| 列表.clone());
+                }
+                列表.retain(|x| x != 序号);
+            }
+            self.全码索引.entry(签名.0).or_default().push(*序号);
+            变化的全码.push(旧签名.0);
+            变化的全码.push(签名.0);
+        }
+        变化的全码.sort_unstable();
+        变化的全码.dedup();
+        for 全码 in &变化的全码 {
+            for 序号 in self.全码索引.get(全码).into_iter().flatten() {
+                let 签名 = Self::签名(&编码结果[*序号]);
+                if 签名 != self.编码快照[*序号] {
+                    self.编码快照[*序号] = 签名;
+                    变化的词.push(*序号);
+                }
+            }
+        }
+        变化的词.sort_unstable();
+        let mut changes = vec![];
+        for 序号 in 变化的词 {
+            changes.push(self.数据.生成码表项(序号, &编码结果[序号]));
+        }
+        let 重码组 = |全码: 编码, 列表: &[usize]| 重码组 {
+            code: 数据::数字转编码(全码, self.数据.进制, &self.数据.数字转键)
+                .into_iter()
+                .collect(),
+            words: 列表
+                .iter()
+                .map(|x| self.数据.词列表[*x].名称.clone())
+                .collect(),
+        };
+        let collisions = 变化的全码
+            .iter()
+            .filter_map(|全码| {
+                let 列表 = self.全码索引.get(全码).filter(|x| x.len() > 1)?;
+                Some(重码组(*全码, 列表))
+            })
+            .collect();
+        let resolved = 变化的全码
+            .iter()
+            .filter(|全码| self.全码索引.get(全码).is_none_or(|x| x.len() <= 1))
+            .filter_map(|全码| Some(重码组(*全码, 移动前的重码组.get(全码)?)))
+            .collect();
+        Ok(移动结果 {
+            metric: 指标,
+            changes,
+            collisions,
+            resolved,
+        })
+    }
 }
 
 /// 用于在图形界面验证输入的配置是否正确
@@ -148,34 +329,59 @@ impl Web {
     pub fn new(回调: Function) -> Web {
         set_once();
         let 参数 = 图形界面参数::default();
-        Self { 回调, 参数 }
+        Self {
+            回调,
+            参数,
+            会话: None,
+        }
     }
 
+    /// 同步前端参数，之前的会话失效，下一次编码时重新构造
     pub fn sync(&mut self, 前端参数: JsValue) -> Result<(), JsError> {
         self.参数 = from_value(前端参数)?;
+        self.会话 = None;
         Ok(())
     }
 
-    pub fn encode_evaluate(&self, 前端目标函数配置: JsValue) -> Result<JsValue, JsError> {
+    /// 用给定的目标函数配置完整编码和评测当前的布局，返回码表和评测指标
+    pub fn encode_evaluate(&mut self, 前端目标函数配置: JsValue) -> Result<JsValue, JsError> {
         let 目标函数配置: ObjectiveConfig = from_value(前端目标函数配置)?;
-        let 图形界面参数 {
-            mut 配置,
-            原始键位分布信息,
-            原始当量信息,
-            词列表,
-        } = self.参数.clone();
-        配置.optimization = Some(OptimizationConfig {
-            objective: 目标函数配置,
-            constraints: None,
-            metaheuristic: None,
-        });
-        let 数据 = 数据::新建(配置, 词列表, 原始键位分布信息, 原始当量信息)?;
-        let mut 编码器 = 默认编码器::新建(&数据)?;
-        let mut 编码结果 = 编码器.编码(&数据.初始映射, &None).clone();
-        let 码表 = 数据.生成码表(&编码结果);
-        let mut 目标函数 = 默认目标函数::新建(&数据)?;
-        let (指标, _, _, _) = 目标函数.计算(&mut 编码结果, &数据.初始映射, 0.0);
-        Ok(to_value(&(码表, 指标))?)
+        let 会话 = match &mut self.会话 {
+            Some(会话) => {
+                会话.更换目标函数(目标函数配置)?;
+                会话
+            }
+            None => {
+                let 图形界面参数 {
+                    mut 配置,
+                    原始键位分布信息,
+                    原始当量信息,
+                    词列表,
+                } = self.参数.clone();
+                配置.optimization = Some(OptimizationConfig {
+                    objective: 目标函数配置,
+                    constraints: None,
+                    metaheuristic: None,
+                });
+                let 数据 = 数据::新建(配置, 词列表, 原始键位分布信息, 原始当量信息)?;
+                self.会话.insert(会话::新建(数据)?)
+            }
+        };
+        Ok(to_value(&会话.完整计算())?)
+    }
+
+    /// 把一个元素移动到另一个键上，增量地重新编码和评测，返回评测指标、编码变化的字词和相关的重码组，并把移动后的映射写回参数
+    ///
+    /// 需要先调用 encode_evaluate 建立会话
+    pub fn move_element(&mut self, 元素: String, 键: char) -> Result<JsValue, JsError> {
+        let 会话 = self
+            .会话
+            .as_mut()
+            .ok_or(错误::from("请先调用 encode_evaluate 编码当前方案"))?;
+        let 移动结果 = 会话.移动元素(&元素, 键)?;
+        // 写回前端参数中的映射，之后的优化从移动后的方案开始
+        self.参数.配置.form.mapping = 会话.数据.更新配置(&会话.映射).form.mapping;
+        Ok(to_value(&移动结果)?)
     }
 
     pub fn optimize(&self) -> Result<(), JsError> {
@@ -187,12 +393,16 @@ impl Web {
         } = self.参数.clone();
         let 优化方法配置 = 配置.clone().optimization.unwrap().metaheuristic.unwrap();
         let 数据 = 数据::新建(配置, 词列表, 原始键位分布信息, 原始当量信息)?;
-        let 编码器 = 默认编码器::新建(&数据)?;
+        let 编码器 = 新建编码器(&数据)?;
         let 目标函数 = 默认目标函数::新建(&数据)?;
         let 操作 = 默认操作::新建(&数据)?;
-        let mut 问题 = 优化问题::新建(数据, 编码器, 目标函数, 操作);
-        let SolverConfig::SimulatedAnnealing(退火) = 优化方法配置;
-        退火.优化(&mut 问题, self);
+        let mut 问题 = 优化问题::新建(数据, 编码器, 目标函数, 操作).开启校验(Box::new(|数据| {
+            Ok((新建编码器(数据)?, 默认目标函数::新建(数据)?))
+        }));
+        let 优化结果 = 优化方法配置.优化(&mut 问题, self);
+        if let Some(错误) = 优化结果.校验错误 {
+            return Err(错误.into());
+        }
         Ok(())
     }
 }
@@ -227,6 +437,12 @@ pub struct 命令行参数 {
     /// 线程数，默认为 1
     #[arg(short, long)]
     pub threads: Option<usize>,
+    /// 优化时每隔 N 步从头计算一次，校验增量计算的结果，覆盖方案文件中的 verify_interval
+    #[arg(long, value_name = "N")]
+    pub verify: Option<NonZeroUsize>,
+    /// 优化的时间限制，单位为秒，覆盖方案文件中的 time_limit
+    #[arg(long, value_name = "SECONDS")]
+    pub time_limit: Option<f64>,
 }
 
 /// 命令行中所有可用的子命令
@@ -236,12 +452,55 @@ pub enum 命令 {
     Encode,
     /// 基于拆分表和方案文件中的配置优化元素布局
     Optimize,
+    /// 根据方案文件中的字形数据将所有字根渲染为 SVG 图形
+    Render,
+    /// 将语料按码表切分，模拟实际输入过程并统计各类评测指标
+    Corpus {
+        /// 语料文件，UTF-8 编码的纯文本
+        corpus: PathBuf,
+        /// 语料的切分方式
+        #[arg(short, long, value_enum, default_value_t = 分词方式::ForwardMaximum)]
+        segmentation: 分词方式,
+    },
+    /// 模拟在语料上打字的过程，根据当量估算每千字的耗时
+    Simulate {
+        /// 语料文件，UTF-8 编码的纯文本
+        corpus: PathBuf,
+        /// 语料的切分方式
+        #[arg(short, long, value_enum, default_value_t = 分词方式::ForwardMaximum)]
+        segmentation: 分词方式,
+        /// 每单位当量对应的秒数
+        #[arg(long, default_value_t = 0.1)]
+        seconds_per_equivalence: f64,
+    },
+    /// 与另一个方案文件在同一份拆分表上比较元素布局、编码和评测指标
+    Compare {
+        /// 用于比较的另一个方案文件
+        other: PathBuf,
+    },
+    /// 从语料中统计字词频率，与拆分表合并后生成新的频率序列表
+    Count {
+        /// 语料文件，UTF-8 编码的纯文本，可以提供多个
+        #[arg(required = true)]
+        corpus: Vec<PathBuf>,
+        /// 词表文件，每行一个词，词表中的词和拆分表中的字词一起参与切分
+        #[arg(short, long, value_name = "FILE")]
+        words: Option<PathBuf>,
+        /// 只保留完全由汉字组成的字词
+        #[arg(long)]
+        cjk_only: bool,
+        /// 频率低于此值的字词不写入结果
+        #[arg(long, default_value_t = 1)]
+        min_frequency: u64,
+    },
 }
 
 /// 通过命令行来使用 libchai 的入口，实现了界面特征
 pub struct 命令行 {
     pub 参数: 命令行参数,
     pub 输出目录: PathBuf,
+    /// 收到中断信号时置位，由所有子命令行共享
+    pub 中断标志: Arc<AtomicBool>,
 }
 
 impl 命令行 {
@@ -254,6 +513,7 @@ impl 命令行 {
         Self {
             参数: args,
             输出目录: output_dir,
+            中断标志: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -267,6 +527,8 @@ impl 命令行 {
             key_distribution: None,
             pair_equivalence: None,
             threads: None,
+            verify: None,
+            time_limit: None,
         };
         let cli = 命令行::新建(参数, None);
         cli.准备数据()
@@ -286,18 +548,29 @@ impl 命令行 {
         reader.deserialize().map(|x| x.unwrap()).collect()
     }
 
+    pub fn 读取配置(&self) -> 配置 {
+        let config_path = self.参数.config.clone().unwrap_or(PathBuf::from("config.yaml"));
+        Self::读取方案文件(&config_path)
+    }
+
+    pub fn 读取方案文件(config_path: &Path) -> 配置 {
+        let config_content = read_to_string(config_path)
+            .unwrap_or_else(|_| panic!("文件 {} 不存在", config_path.display()));
+        serde_yaml::from_str(&config_content).unwrap()
+    }
+
     pub fn 准备数据(&self) -> 数据 {
+        self.根据配置准备数据(self.读取配置())
+    }
+
+    /// 使用给定的方案和命令行参数中的拆分表、评测信息来准备数据
+    pub fn 根据配置准备数据(&self, config: 配置) -> 数据 {
         let 命令行参数 {
-            config,
             encodables: elements,
             key_distribution,
             pair_equivalence,
             ..
         } = self.参数.clone();
-        let config_path = config.unwrap_or(PathBuf::from("config.yaml"));
-        let config_content = read_to_string(&config_path)
-            .unwrap_or_else(|_| panic!("文件 {} 不存在", config_path.display()));
-        let config: 配置 = serde_yaml::from_str(&config_content).unwrap();
         let elements_path = elements.unwrap_or(PathBuf::from("elements.txt"));
         let encodables: Vec<原始可编码对象> = Self::read(elements_path);
 
@@ -339,9 +612,127 @@ impl 命令行 {
         write(&path, metric_str).unwrap();
     }
 
+    /// 将映射中所有具有字形数据的字根渲染为 SVG 图形，保存在输出目录的「字根」子目录中
+    pub fn 输出字根图(&self, 配置: &配置) -> Result<(), 错误> {
+        let 字形数据 = 配置.data.as_ref().ok_or("方案文件中没有字形数据")?;
+        let 渲染器 = 字形渲染器::新建(字形数据);
+        let 字根目录 = self.输出目录.join("字根");
+        create_dir_all(&字根目录)?;
+        let mut 字根列表: Vec<_> = 配置.form.mapping.keys().collect();
+        字根列表.sort();
+        let mut 数量 = 0;
+        for 字根 in 字根列表 {
+            // 读音、笔画等非字形元素没有字形数据，直接跳过
+            let Ok(svg) = 渲染器.生成图形(字根) else {
+                continue;
+            };
+            write(字根目录.join(format!("{}.svg", 字根)), svg)?;
+            数量 += 1;
+        }
+        println!(
+            "已渲染 {} 个字根，结果保存在 {} 中",
+            数量,
+            字根目录.display()
+        );
+        Ok(())
+    }
+
+    /// 读取拆分表和语料，统计频率后输出为新的频率序列表
+    ///
+    /// 词表中的词如果在拆分表中没有对应的拆分序列，只参与切分而不写入结果；拆分表中同名的多行平分这个名称的频率
+    pub fn 输出频率表(
+        &self,
+        语料路径列表: &[PathBuf],
+        词表路径: &Option<PathBuf>,
+        只保留汉字: bool,
+        最低频率: u64,
+    ) -> Result<(), 错误> {
+        let 拆分表路径 = self
+            .参数
+            .encodables
+            .clone()
+            .unwrap_or(PathBuf::from("elements.txt"));
+        let 拆分表: Vec<原始可编码对象> = Self::read(拆分表路径);
+        let mut 名称列表: Vec<String> = 拆分表.iter().map(|x| x.name.clone()).collect();
+        if let Some(词表路径) = 词表路径 {
+            for 行 in read_to_string(词表路径)?.lines() {
+                if let Some(词) = 行.split('\t').next().filter(|x| !x.is_empty()) {
+                    名称列表.push(词.to_string());
+                }
+            }
+        }
+        let 词典 = 词典::新建(名称列表.iter().map(String::as_str));
+        let mut 语料列表 = vec![];
+        for 路径 in 语料路径列表 {
+            语料列表.push(read_to_string(路径)?);
+        }
+        let 频率 = 统计频率(&词典, 语料列表.iter().map(String::as_str));
+        let 缺少拆分的词 = (拆分表.len()..名称列表.len())
+            .filter(|x| 频率.get(*x).is_some_and(|f| *f >= 最低频率))
+            .count();
+        let 拆分表 = 拆分表
+            .into_iter()
+            .filter(|x| !只保留汉字 || x.name.chars().all(是汉字))
+            .collect();
+        let mut 结果 = 分配频率(拆分表, &词典, &频率, 最低频率);
+        结果.sort_by_key(|x| Reverse(x.frequency));
+        let path = self.输出目录.join("elements.txt");
+        let mut writer = WriterBuilder::new()
+            .delimiter(b'\t')
+            .has_headers(false)
+            .flexible(true)
+            .from_path(&path)
+            .unwrap();
+        for 原始可编码对象 {
+            name,
+            sequence,
+            frequency,
+            level,
+        } in &结果
+        {
+            let frequency = frequency.to_string();
+            let mut record = vec![name.as_str(), sequence.as_str(), frequency.as_str()];
+            let level_str = level.to_string();
+            if *level != u64::MAX {
+                record.push(&level_str);
+            }
+            writer.write_record(record).unwrap();
+        }
+        writer.flush()?;
+        println!(
+            "已统计 {} 个字词的频率，结果保存在 {} 中",
+            结果.len(),
+            path.display()
+        );
+        if 缺少拆分的词 > 0 {
+            println!("词表中有 {} 个达到最低频率的词在拆分表中没有拆分序列，未写入结果", 缺少拆分的词);
+        }
+        Ok(())
+    }
+
+    pub fn 输出语料指标(&self, 指标: 语料指标) {
+        let path = self.输出目录.join("语料指标.yaml");
+        print!("{}", 指标);
+        write(&path, serde_yaml::to_string(&指标).unwrap()).unwrap();
+    }
+
+    pub fn 输出模拟结果(&self, 结果: 模拟结果) {
+        let path = self.输出目录.join("模拟结果.yaml");
+        print!("{}", 结果);
+        write(&path, serde_yaml::to_string(&结果).unwrap()).unwrap();
+    }
+
+    pub fn 输出方案差异(&self, 差异: 方案差异) {
+        let path = self.输出目录.join("方案对比.yaml");
+        print!("{}", 差异);
+        write(&path, serde_yaml::to_string(&差异).unwrap()).unwrap();
+    }
+
     pub fn 生成子命令行(&self, index: usize) -> 命令行 {
         let child_dir = self.输出目录.join(format!("{}", index));
-        命令行::新建(self.参数.clone(), Some(child_dir))
+        let mut 子命令行 = 命令行::新建(self.参数.clone(), Some(child_dir));
+        子命令行.中断标志 = self.中断标志.clone();
+        子命令行
     }
 }
 
@@ -383,15 +774,38 @@ impl 界面 for 命令行 {
                 t_max, t_min
             ),
             消息::Elapsed { time } => writeln!(&mut writer, "计算一次评测用时：{} μs", time),
+            消息::MemoryEstimate { bytes } => writeln!(
+                &mut writer,
+                "编码器估计占用内存：{:.1} MB",
+                bytes as f64 / (1 << 20) as f64
+            ),
             消息::Progress {
                 steps,
                 temperature,
+                accept_rate,
+                reheats,
+                operator_probabilities,
                 metric,
-            } => writeln!(
-                &mut writer,
-                "已执行 {} 步，当前温度为 {:.2e}，当前评测指标如下：\n{}",
-                steps, temperature, metric
-            ),
+            } => {
+                let 变异概率 = operator_probabilities.map_or(String::new(), |x| {
+                    format!(
+                        "变异概率为移动 {:.1}%、交换 {:.1}%、整键交换 {:.1}%，",
+                        x.random_move * 100.0,
+                        x.random_swap * 100.0,
+                        x.random_full_key_swap * 100.0
+                    )
+                });
+                writeln!(
+                    &mut writer,
+                    "已执行 {} 步，当前温度为 {:.2e}，近期接受率为 {:.2}%，已回火 {} 次，{}当前评测指标如下：\n{}",
+                    steps,
+                    temperature,
+                    accept_rate * 100.0,
+                    reheats,
+                    变异概率,
+                    metric
+                )
+            }
             消息::BetterSolution {
                 metric,
                 config,
@@ -426,4 +840,60 @@ impl 界面 for 命令行 {
         };
         result.unwrap()
     }
+
+    fn 已中断(&self) -> bool {
+        self.中断标志.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Mapped;
+    use std::collections::HashMap;
+
+    #[test]
+    fn 移动元素报告新增和消除的重码() {
+        let mut 配置 = 配置::default();
+        配置.form.alphabet = "abc".to_string();
+        for (元素, 键) in [("甲", "a"), ("乙", "b"), ("丙", "c")] {
+            配置
+                .form
+                .mapping
+                .insert(元素.to_string(), Mapped::Basic(键.to_string()));
+        }
+        配置.encoder.max_length = 1;
+        配置.optimization = Some(
+            serde_yaml::from_str("objective: {characters_full: {duplication: 1.0}}").unwrap(),
+        );
+        let 词列表 = [("一", "甲"), ("二", "乙"), ("三", "丙")]
+            .map(|(name, sequence)| 原始可编码对象 {
+                name: name.to_string(),
+                sequence: sequence.to_string(),
+                frequency: 1,
+                level: u64::MAX,
+            })
+            .to_vec();
+        let 数据 = 数据::新建(配置, 词列表, HashMap::new(), HashMap::new()).unwrap();
+        let mut 会话 = 会话::新建(数据).unwrap();
+        会话.完整计算();
+        let 词组 = |组: &[重码组]| -> Vec<Vec<String>> { 组.iter().map(|x| x.words.clone()).collect() };
+        let 结果 = 会话.移动元素("乙", 'a').unwrap();
+        assert_eq!(词组(&结果.collisions), [["一", "二"]]);
+        assert!(结果.resolved.is_empty());
+        let 结果 = 会话.移动元素("丙", 'a').unwrap();
+        assert_eq!(词组(&结果.collisions), [["一", "二", "三"]]);
+        // 乙移回 b 之后，a 上仍有一、三重码；丙移回 c 之后，a 上的重码消除
+        let 结果 = 会话.移动元素("乙", 'b').unwrap();
+        assert_eq!(词组(&结果.collisions), [["一", "三"]]);
+        assert!(结果.resolved.is_empty());
+        // 乙移走后三的候选位置前移，也要报告
+        let 变化: Vec<_> = 结果.changes.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(变化, ["二", "三"]);
+        let 结果 = 会话.移动元素("丙", 'c').unwrap();
+        assert!(结果.collisions.is_empty());
+        assert_eq!(词组(&结果.resolved), [["一", "三"]]);
+        let 变化: Vec<_> = 结果.changes.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(变化, ["三"]);
+    }
 }
@@ -1,39 +1,64 @@
-use chai::config::SolverConfig;
-use chai::encoders::default::默认编码器;
-use chai::encoders::编码器;
+use chai::compare::比较方案;
+use chai::corpus::语料评测器;
+use chai::encoders::{新建编码器, 编码器};
 use chai::objectives::{default::默认目标函数, 目标函数};
 use chai::operators::default::默认操作;
 use chai::optimizers::{优化方法, 优化问题};
 use chai::{命令, 命令行, 命令行参数, 错误};
 use clap::Parser;
+use std::fs::read_to_string;
+use std::process::exit;
+use std::sync::atomic::Ordering;
 use std::thread::spawn;
 
 fn main() -> Result<(), 错误> {
     let 参数 = 命令行参数::parse();
     let 命令行 = 命令行::新建(参数, None);
+    match &命令行.参数.command {
+        命令::Render => return 命令行.输出字根图(&命令行.读取配置()),
+        命令::Count {
+            corpus,
+            words,
+            cjk_only,
+            min_frequency,
+        } => return 命令行.输出频率表(corpus, words, *cjk_only, *min_frequency),
+        _ => {}
+    }
     let 数据 = 命令行.准备数据();
     let _config = 数据.配置.clone();
     match 命令行.参数.command {
         命令::Encode => {
-            let mut 编码器 = 默认编码器::新建(&数据)?;
+            let mut 编码器 = 新建编码器(&数据)?;
             let mut 目标函数 = 默认目标函数::新建(&数据)?;
             let mut 编码结果 = 编码器.编码(&数据.初始映射, &None).clone();
             let 码表 = 数据.生成码表(&编码结果);
-            let (指标, _, _, _) = 目标函数.计算(&mut 编码结果, &数据.初始映射, 0.0);
+            let (指标, _) = 目标函数.计算(&mut 编码结果, &数据.初始映射, 0.0);
             命令行.输出编码结果(码表);
             命令行.输出评测指标(指标);
         }
         命令::Optimize => {
             let 线程数 = 命令行.参数.threads.unwrap_or(1);
-            let SolverConfig::SimulatedAnnealing(退火) =
-                _config.optimization.unwrap().metaheuristic.unwrap();
+            let mut 优化方法配置 = _config.optimization.clone().unwrap().metaheuristic.unwrap();
+            优化方法配置.覆盖(命令行.参数.verify, 命令行.参数.time_limit);
+            // 第一次中断时让各线程在下一步结束并保存最优方案，再次中断时立即退出
+            let 中断标志 = 命令行.中断标志.clone();
+            ctrlc::set_handler(move || {
+                if 中断标志.swap(true, Ordering::Relaxed) {
+                    exit(130);
+                }
+                eprintln!("收到中断信号，正在保存各线程的最优方案，再次中断将立即退出");
+            })
+            .map_err(|x| 错误::from(format!("无法设置中断处理：{}", x)))?;
             let mut 线程池 = vec![];
             for 线程序号 in 0..线程数 {
-                let 编码器 = 默认编码器::新建(&数据)?;
+                let 编码器 = 新建编码器(&数据)?;
                 let 目标函数 = 默认目标函数::新建(&数据)?;
                 let 操作 = 默认操作::新建(&数据)?;
-                let mut 问题 = 优化问题::新建(数据.clone(), 编码器, 目标函数, 操作);
-                let 优化方法 = 退火.clone();
+                let mut 问题 = 优化问题::新建(数据.clone(), 编码器, 目标函数, 操作)
+                    .开启校验(Box::new(|数据| {
+                        Ok((新建编码器(数据)?, 默认目标函数::新建(数据)?))
+                    }));
+                let 优化方法 = 优化方法配置.clone();
                 let 子命令行 = 命令行.生成子命令行(线程序号);
                 let 线程 = spawn(move || 优化方法.优化(&mut 问题, &子命令行));
                 线程池.push(线程);
@@ -43,10 +68,40 @@ fn main() -> Result<(), 错误> {
                 优化结果列表.push(线程.join().unwrap());
             }
             优化结果列表.sort_by(|a, b| a.分数.partial_cmp(&b.分数).unwrap());
-            for 优化结果 in 优化结果列表 {
+            for 优化结果 in &优化结果列表 {
                 print!("{}", 优化结果.指标);
             }
+            // 各线程的最优方案都已保存，再报告校验失败
+            if let Some(错误) = 优化结果列表.into_iter().find_map(|x| x.校验错误) {
+                return Err(错误);
+            }
+        }
+        命令::Corpus {
+            ref corpus,
+            segmentation,
+        } => {
+            let mut 编码器 = 新建编码器(&数据)?;
+            let 编码结果 = 编码器.编码(&数据.初始映射, &None).clone();
+            let 文本 = read_to_string(corpus)?;
+            let 评测器 = 语料评测器::新建(&数据, &编码结果);
+            命令行.输出语料指标(评测器.评测(&文本, segmentation));
+        }
+        命令::Simulate {
+            ref corpus,
+            segmentation,
+            seconds_per_equivalence,
+        } => {
+            let mut 编码器 = 新建编码器(&数据)?;
+            let 编码结果 = 编码器.编码(&数据.初始映射, &None).clone();
+            let 文本 = read_to_string(corpus)?;
+            let 评测器 = 语料评测器::新建(&数据, &编码结果);
+            命令行.输出模拟结果(评测器.模拟(&文本, segmentation, seconds_per_equivalence));
+        }
+        命令::Compare { ref other } => {
+            let 新数据 = 命令行.根据配置准备数据(命令行::读取方案文件(other));
+            命令行.输出方案差异(比较方案(&数据, &新数据)?);
         }
+        命令::Render | 命令::Count { .. } => unreachable!(),
     }
     Ok(())
 }
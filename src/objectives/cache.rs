@@ -1,7 +1,4 @@
-use circular_buffer::CircularBuffer;
-use rustc_hash::FxHashMap;
-use rustc_hash::FxHashSet;
-
+use super::conflict::{冲突引导, 冲突记录};
 use super::default::默认目标函数参数;
 use super::metric::FingeringMetric;
 use super::metric::FingeringMetricUniform;
@@ -30,20 +27,21 @@ pub struct 缓存 {
     total_pair_equivalence: f64,
     total_extended_pair_equivalence: f64,
     total_duplication: i64,
+    total_selection_cost: f64,
+    selection_costs: Option<Vec<f64>>,
     total_fingering: [i64; 8],
     total_levels: Vec<i64>,
     tiers_duplication: Vec<i64>,
     tiers_levels: Vec<Vec<i64>>,
     tiers_fingering: Vec<[i64; 8]>,
+    tiers_distribution: Vec<Vec<i64>>,
+    tiers_pairs: Vec<i64>,
+    tiers_pair_equivalence: Vec<f64>,
     max_index: u64,
     segment: u64,
     length_breakpoints: Vec<u64>,
     radix: u64,
-    pub 概率: FxHashMap<usize, f64>,
-    pub 冲突: FxHashMap<usize, FxHashMap<usize, CircularBuffer<4, f64>>>,
-    上一次增加的概率: FxHashMap<u64, Vec<(usize, f64)>>,
-    增加冲突的字根: FxHashSet<(usize, usize)>,
-    首选元素序列: FxHashMap<u64, &'static Vec<usize>>,
+    冲突记录: Option<冲突记录>,
 }
 
 impl 缓存 {
@@ -54,7 +52,8 @@ impl 缓存 {
         频率: u64,
         编码信息: &mut 部分编码信息,
         参数: &默认目标函数参数,
-        元素序列: &'static Vec<usize>,
+        词序号: usize,
+        引导: &mut Option<冲突引导>,
     ) {
         if !编码信息.有变化 {
             return;
@@ -66,9 +65,9 @@ impl 缓存 {
                 频率,
                 编码信息.上一个实际编码,
                 编码信息.上一个选重标记,
+                编码信息.上一个候选位置,
                 参数,
                 -1,
-                元素序列,
             );
         }
         self.增减(
@@ -76,13 +75,23 @@ impl 缓存 {
             频率,
             编码信息.实际编码,
             编码信息.选重标记,
+            编码信息.候选位置,
             参数,
             1,
-            元素序列,
         );
+        if let (Some(记录), Some(引导)) = (&mut self.冲突记录, 引导) {
+            let 词列表 = &参数.词列表;
+            let 频率 = 频率 as i64;
+            if 编码信息.上一个实际编码 != 0 {
+                let (编码, 选重) = (编码信息.上一个实际编码, 编码信息.上一个选重标记);
+                记录.增减(引导, 词列表, 编码, 选重, 词序号, -频率);
+            }
+            let (编码, 选重) = (编码信息.实际编码, 编码信息.选重标记);
+            记录.增减(引导, 词列表, 编码, 选重, 词序号, 频率);
+        }
     }
 
-    pub fn 汇总(&mut self, 参数: &默认目标函数参数, 进度: f64) -> (分组指标, f64) {
+    pub fn 汇总(&mut self, 参数: &默认目标函数参数) -> (分组指标, f64) {
         let partial_weights = &self.partial_weights;
         let 键位分布信息 = &参数.键位分布信息;
         // 初始化返回值和标量化的损失函数
@@ -95,13 +104,8 @@ impl 缓存 {
             fingering: None,
             duplication: None,
             levels: None,
+            selection_cost: None,
         };
-        let 频率和: f64 = self.概率.values().into_iter().sum();
-        let 归一化概率 = self.概率.iter().map(|x| (x.0, x.1 / 频率和)).collect::<FxHashMap<_, _>>();
-        let 最大概率 = 归一化概率.values().fold(f64::NEG_INFINITY, |x, y| f64::max(x, *y));
-        let 指数概率 = 归一化概率.into_iter().map(|x| (*x.0, ((x.1 - 最大概率) / (1.0 - 进度)).exp())).collect::<FxHashMap<_, _>>();
-        let 指数概率和: f64 = 指数概率.values().sum();
-        self.概率 = 指数概率.into_iter().map(|x| (x.0, x.1 / 指数概率和)).collect::<FxHashMap<_, _>>();
         let mut 损失函数 = 0.0;
         // 一、全局指标
         // 1. 按键分布
@@ -124,16 +128,16 @@ impl 缓存 {
             分组指标.key_distribution_loss = Some(距离);
             损失函数 += 距离 * key_distribution_weight;
         }
-        // 2. 组合当量
+        // 2. 组合当量，没有任何组合时记为 0
         if let Some(equivalence_weight) = partial_weights.pair_equivalence {
-            let equivalence = self.total_pair_equivalence / self.total_pairs as f64;
+            let equivalence = self.total_pair_equivalence / self.total_pairs.max(1) as f64;
             分组指标.pair_equivalence = Some(equivalence);
             损失函数 += equivalence * equivalence_weight;
         }
         // 3. 词间当量
         if let Some(equivalence_weight) = partial_weights.extended_pair_equivalence {
             let equivalence =
-                self.total_extended_pair_equivalence / self.total_extended_pairs as f64;
+                self.total_extended_pair_equivalence / self.total_extended_pairs.max(1) as f64;
             分组指标.extended_pair_equivalence = Some(equivalence);
             损失函数 += equivalence * equivalence_weight;
         }
@@ -154,7 +158,13 @@ impl 缓存 {
             分组指标.duplication = Some(duplication);
             损失函数 += duplication * duplication_weight;
         }
-        // 6. 简码
+        // 6. 选重代价
+        if let Some(selection_weight) = partial_weights.selection {
+            let selection_cost = self.total_selection_cost / self.total_frequency as f64;
+            分组指标.selection_cost = Some(selection_cost);
+            损失函数 += selection_cost * selection_weight;
+        }
+        // 7. 简码
         if let Some(levels_weight) = &partial_weights.levels {
             let mut levels: Vec<键长指标> = Vec::new();
             for (ilevel, level) in levels_weight.iter().enumerate() {
@@ -176,6 +186,9 @@ impl 缓存 {
                     duplication: None,
                     levels: None,
                     fingering: None,
+                    key_distribution: None,
+                    key_distribution_loss: None,
+                    pair_equivalence: None,
                 })
                 .collect();
             for (itier, tier_weights) in tiers_weight.iter().enumerate() {
@@ -215,21 +228,36 @@ impl 缓存 {
                     }
                     tiers[itier].fingering = Some(fingering);
                 }
+                // 4. 按键分布
+                if let Some(key_distribution_weight) = tier_weights.key_distribution {
+                    let 总频率: i64 = self.tiers_distribution[itier].iter().sum();
+                    let 分布 = self.tiers_distribution[itier]
+                        .iter()
+                        .map(|x| *x as f64 / 总频率 as f64)
+                        .collect();
+                    let 距离 = 缓存::计算键位分布距离(&分布, 键位分布信息);
+                    let mut 分布映射 = HashMap::new();
+                    for (i, x) in 分布.iter().enumerate() {
+                        if let Some(键) = 参数.数字转键.get(&(i as u64)) {
+                            分布映射.insert(*键, *x);
+                        }
+                    }
+                    tiers[itier].key_distribution = Some(分布映射);
+                    tiers[itier].key_distribution_loss = Some(距离);
+                    损失函数 += 距离 * key_distribution_weight;
+                }
+                // 5. 组合当量，这一层没有任何组合时记为 0
+                if let Some(equivalence_weight) = tier_weights.pair_equivalence {
+                    let equivalence =
+                        self.tiers_pair_equivalence[itier] / self.tiers_pairs[itier].max(1) as f64;
+                    tiers[itier].pair_equivalence = Some(equivalence);
+                    损失函数 += equivalence * equivalence_weight;
+                }
             }
             分组指标.tiers = Some(tiers);
         }
         (分组指标, 损失函数)
     }
-    
-    pub fn 冲突补全(&mut self) {
-        for (元素1, 冲突) in self.冲突.iter_mut() {
-            for (元素2, 冲突) in 冲突.iter_mut() {
-                if !self.增加冲突的字根.contains(&(*元素1, *元素2)) {
-                    冲突.push_back(0.0);
-                }
-            }
-        }
-    }
 }
 
 impl 缓存 {
@@ -238,6 +266,7 @@ impl 缓存 {
         radix: u64,
         total_count: usize,
         max_index: u64,
+        冲突引导: bool,
     ) -> Self {
         let total_frequency = 0;
         let total_pairs = 0;
@@ -249,6 +278,8 @@ impl 缓存 {
         let total_extended_pair_equivalence = 0.0;
         // 2. 有加权指标，也有计数指标
         let total_duplication = 0;
+        let total_selection_cost = 0.0;
+        let selection_costs = partial_weights.selection_costs.clone();
         let total_fingering = [0; 8];
         let nlevel = partial_weights.levels.as_ref().map_or(0, |v| v.len());
         let total_levels = vec![0; nlevel];
@@ -263,6 +294,9 @@ impl 缓存 {
             }
         }
         let tiers_fingering = vec![[0; 8]; ntier];
+        let tiers_distribution = vec![vec![0; radix as usize]; ntier];
+        let tiers_pairs = vec![0; ntier];
+        let tiers_pair_equivalence = vec![0.0; ntier];
         let segment = radix.pow((最大按键组合长度 - 1) as u32);
         let length_breakpoints: Vec<u64> = (0..=8).map(|x| radix.pow(x)).collect();
 
@@ -276,20 +310,21 @@ impl 缓存 {
             total_pair_equivalence,
             total_extended_pair_equivalence,
             total_duplication,
+            total_selection_cost,
+            selection_costs,
             total_fingering,
             total_levels,
             tiers_duplication,
             tiers_levels,
             tiers_fingering,
+            tiers_distribution,
+            tiers_pairs,
+            tiers_pair_equivalence,
             max_index,
             segment,
             length_breakpoints,
             radix,
-            概率: FxHashMap::default(),
-            冲突: FxHashMap::default(),
-            上一次增加的概率: FxHashMap::default(),
-            增加冲突的字根: FxHashSet::default(),
-            首选元素序列: FxHashMap::default(),
+            冲突记录: 冲突引导.then(|| 冲突记录::新建(total_count)),
         }
     }
 
@@ -318,9 +353,9 @@ impl 缓存 {
         frequency: u64,
         code: 编码,
         duplicate: bool,
+        position: u8,
         parameters: &默认目标函数参数,
         sign: i64,
-        元素序列: &'static Vec<usize>,
     ) {
         let frequency = frequency as i64 * sign;
         let radix = self.radix;
@@ -369,40 +404,16 @@ impl 缓存 {
         // 5. 重码
         if duplicate {
             self.total_duplication += frequency;
-            if sign == 1 {
-                let mut 未归一化频率 = Vec::new();
-                if let Some(首选元素序列) = self.首选元素序列.get(&code) {
-                    for (元素1, 元素2) in 元素序列.iter().zip(首选元素序列.iter()) {
-                        if 元素1 != 元素2 {
-                            未归一化频率.push((元素1, frequency as f64 / self.total_frequency as f64));
-                            未归一化频率.push((元素2, frequency as f64 / self.total_frequency as f64));
-                        }
-                    }
-                    for (i, (元素, 频率)) in 未归一化频率.iter().enumerate() {
-                        *self.概率.entry(**元素).or_insert(0.0) += 频率 / 未归一化频率.len() as f64 * 2.0;
-                        let 冲突元素 = 未归一化频率[if i % 2 == 0 {
-                            i + 1
-                        } else {
-                            i - 1
-                        }].0;
-                        self.冲突.entry(**元素).or_insert(FxHashMap::default()).entry(*冲突元素).or_insert(CircularBuffer::new()).push_back(频率 / 未归一化频率.len() as f64 * 2.0 / if 未归一化频率.len() == 1 { 4.0 } else { 1.0 });
-                        self.增加冲突的字根.insert((**元素, *冲突元素));
-                        self.上一次增加的概率.entry(code).or_insert(Vec::new()).push((**元素, 频率 / 未归一化频率.len() as f64 * 2.0));
-                    }
-                }
-            } else {
-                if let Some(上一次增加的概率) = self.上一次增加的概率.get(&code) {
-                    for (元素, 概率) in 上一次增加的概率.iter() {
-                        *self.概率.get_mut(元素).unwrap() -= 概率;
-                    }
-                }
-            }
-        } else {
-            if sign == 1 {
-                self.首选元素序列.insert(code, 元素序列);
-            }
         }
-        // 6. 简码
+        // 6. 选重代价，超出代价列表的候选位置按最后一项计算；未提供代价列表时，第 n 选的代价为 n - 1
+        if duplicate && partial_weights.selection.is_some() {
+            let cost = match &self.selection_costs {
+                Some(costs) => *costs.get(position as usize).or(costs.last()).unwrap_or(&0.0),
+                None => position as f64,
+            };
+            self.total_selection_cost += cost * frequency as f64;
+        }
+        // 7. 简码
         if let Some(levels) = &partial_weights.levels {
             for (ilevel, level) in levels.iter().enumerate() {
                 if level.length == length as usize {
@@ -416,6 +427,7 @@ impl 缓存 {
                 if index >= tier.top.unwrap_or(self.total_count) {
                     continue;
                 }
+                self.tiers_pairs[itier] += (length - 1) as i64 * sign;
                 // 1. 重码
                 if duplicate {
                     self.tiers_duplication[itier] += sign;
@@ -441,6 +453,27 @@ impl 缓存 {
                         code /= self.segment;
                     }
                 }
+                // 4. 按键分布
+                if tier.key_distribution.is_some() {
+                    let mut current = code;
+                    while current > 0 {
+                        let key = current % radix;
+                        if let Some(x) = self.tiers_distribution[itier].get_mut(key as usize) {
+                            *x += sign;
+                        }
+                        current /= radix;
+                    }
+                }
+                // 5. 组合当量
+                if tier.pair_equivalence.is_some() {
+                    let mut code = code;
+                    while code > radix {
+                        let partial_code = (code % self.max_index) as usize;
+                        self.tiers_pair_equivalence[itier] +=
+                            parameters.当量信息[partial_code] * sign as f64;
+                        code /= self.segment;
+                    }
+                }
             }
         }
     }
@@ -0,0 +1,342 @@
+//! 冲突引导：统计当前编码中造成重码的元素，用来引导变异算子
+//!
+//! 一个字词的实际编码与首选字词相同时，两者元素序列中逐位不同的元素就被视为互相冲突，字词的频率计入每一对冲突元素。统计完全是增量的，撤销一个字词时减去的正是当初计入的数值，所以所有权重都是精确的整数，并且与从头统计的结果一致。
+//!
+//! 变异算子按冲突权重优先选取造成重码的元素，并优先把它移动到冲突较少的键上。
+
+use crate::data::{元素, 元素映射, 可编码对象, 键};
+use rand::random;
+use rustc_hash::FxHashMap;
+
+/// 所有计数桶共享的冲突统计
+#[derive(Debug, Clone)]
+pub struct 冲突引导 {
+    元素权重: Vec<i64>,
+    冲突权重: Vec<FxHashMap<元素, i64>>,
+    /// 权重为正的元素，抽取元素时只需遍历它们
+    冲突元素: Vec<元素>,
+    /// 按元素的编号索引，元素在冲突元素列表中的位置
+    冲突元素位置: Vec<Option<usize>>,
+}
+
+/// 抽取元素和键时的温度，随进度从 1 降到一个很小的正数，进度越大越集中于权重最大的选择
+pub fn 抽取温度(进度: f64) -> f64 {
+    (1.0 - 进度).max(1e-3)
+}
+
+/// 一个计数桶中每个编码的首选字词和重码字词，以及每个重码字词目前计入冲突时对应的首选字词
+///
+/// 重码字词总是与所在编码上现在的首选字词冲突：首选字词变化时，这个编码上的重码字词都改为与新的首选字词冲突，所以统计结果与处理字词的先后顺序无关
+#[derive(Debug, Clone)]
+pub struct 冲突记录 {
+    首选词: FxHashMap<u64, usize>,
+    重码词: FxHashMap<u64, Vec<(usize, i64)>>,
+    冲突来源: Vec<Option<usize>>,
+}
+
+impl 冲突引导 {
+    pub fn 新建(元素数: usize) -> Self {
+        Self {
+            元素权重: vec![0; 元素数],
+            冲突权重: vec![FxHashMap::default(); 元素数],
+            冲突元素: vec![],
+            冲突元素位置: vec![None; 元素数],
+        }
+    }
+
+    /// 把两个字词之间的冲突计入或撤销，频率为正时计入，为负时撤销
+    fn 记录(&mut self, 元素序列: &[元素], 首选元素序列: &[元素], 频率: i64) {
+        for (元素一, 元素二) in 元素序列.iter().zip(首选元素序列) {
+            if 元素一 == 元素二 {
+                continue;
+            }
+            for (甲, 乙) in [(*元素一, *元素二), (*元素二, *元素一)] {
+                self.元素权重[甲] += 频率;
+                self.更新冲突元素(甲);
+                let 权重 = self.冲突权重[甲].entry(乙).or_default();
+                *权重 += 频率;
+                if *权重 == 0 {
+                    self.冲突权重[甲].remove(&乙);
+                }
+            }
+        }
+    }
+
+    /// 元素的权重变化后，把它加入或移出冲突元素列表
+    fn 更新冲突元素(&mut self, 元素: 元素) {
+        match (self.元素权重[元素] > 0, self.冲突元素位置[元素]) {
+            (true, None) => {
+                self.冲突元素位置[元素] = Some(self.冲突元素.len());
+                self.冲突元素.push(元素);
+            }
+            (false, Some(位置)) => {
+                self.冲突元素.swap_remove(位置);
+                if let Some(移来的元素) = self.冲突元素.get(位置) {
+                    self.冲突元素位置[*移来的元素] = Some(位置);
+                }
+                self.冲突元素位置[元素] = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// 按冲突权重抽取一个可移动的元素，进度越大越集中于冲突最多的元素；没有可移动的冲突元素时返回空
+    pub fn 抽取元素(&self, 可移动: impl Fn(元素) -> bool, 进度: f64) -> Option<元素> {
+        let 候选 = || {
+            self.冲突元素
+                .iter()
+                .map(|x| (*x, self.元素权重[*x]))
+                .filter(|(元素, _)| 可移动(*元素))
+        };
+        let (总权重, 最大权重) = 候选().fold((0, 0), |(总, 大), (_, x)| (总 + x, 大.max(x)));
+        if 总权重 == 0 {
+            return None;
+        }
+        let 温度 = 抽取温度(进度);
+        let 指数权重 = |x: i64| ((x - 最大权重) as f64 / 总权重 as f64 / 温度).exp();
+        let 指数权重和: f64 = 候选().map(|(_, x)| 指数权重(x)).sum();
+        let mut 剩余 = random::<f64>() * 指数权重和;
+        let mut 结果 = None;
+        for (元素, 权重) in 候选() {
+            结果 = Some(元素);
+            剩余 -= 指数权重(权重);
+            if 剩余 < 0.0 {
+                break;
+            }
+        }
+        结果
+    }
+
+    /// 与元素冲突最多的若干个可移动的元素，按冲突权重从大到小排列
+    pub fn 冲突最多的元素(&self, 元素: 元素, 数量: usize, 可移动: impl Fn(元素) -> bool) -> Vec<元素> {
+        let mut 候选: Vec<_> = self.冲突权重[元素]
+            .iter()
+            .filter(|(x, 权重)| **权重 > 0 && 可移动(**x))
+            .collect();
+        候选.sort_by_key(|(x, 权重)| (std::cmp::Reverse(**权重), **x));
+        候选.into_iter().take(数量).map(|(x, _)| *x).collect()
+    }
+
+    /// 为元素在候选键中抽取一个新的键，进度越大越集中于冲突最少的键；元素没有冲突时返回空
+    ///
+    /// 缓冲区按键编号存放元素与每个键上其他元素的冲突权重，由调用方提供以避免每次分配
+    pub fn 抽取键(
+        &self,
+        元素: 元素,
+        候选键: &[键],
+        映射: &元素映射,
+        进度: f64,
+        缓冲区: &mut Vec<i64>,
+    ) -> Option<键> {
+        let 冲突 = &self.冲突权重[元素];
+        if 冲突.is_empty() {
+            return None;
+        }
+        let 当前键 = 映射[元素];
+        缓冲区.clear();
+        缓冲区.resize(候选键.iter().max().map_or(0, |x| *x as usize + 1), 0);
+        for (其他元素, 权重) in 冲突 {
+            if let Some(x) = 缓冲区.get_mut(映射[*其他元素] as usize) {
+                *x += 权重;
+            }
+        }
+        let 候选 = || 候选键.iter().filter(|x| **x != 当前键);
+        let 最小冲突 = 候选().map(|x| 缓冲区[*x as usize]).min()?;
+        let 温度 = 抽取温度(进度);
+        let 总冲突 = self.元素权重[元素] as f64;
+        let 指数权重 = |x: &键| (-((缓冲区[*x as usize] - 最小冲突) as f64) / 总冲突 / 温度).exp();
+        let 指数权重和: f64 = 候选().map(指数权重).sum();
+        let mut 剩余 = random::<f64>() * 指数权重和;
+        let mut 结果 = None;
+        for 键 in 候选() {
+            结果 = Some(*键);
+            剩余 -= 指数权重(键);
+            if 剩余 < 0.0 {
+                break;
+            }
+        }
+        结果
+    }
+}
+
+impl 冲突记录 {
+    pub fn 新建(字词数: usize) -> Self {
+        Self {
+            首选词: FxHashMap::default(),
+            重码词: FxHashMap::default(),
+            冲突来源: vec![None; 字词数],
+        }
+    }
+
+    /// 在计数桶增减一个字词的编码时同步更新冲突统计，频率为正时计入，为负时撤销
+    pub fn 增减(
+        &mut self,
+        引导: &mut 冲突引导,
+        词列表: &[可编码对象],
+        编码: u64,
+        选重: bool,
+        词序号: usize,
+        频率: i64,
+    ) {
+        if 选重 {
+            let 重码词 = self.重码词.entry(编码).or_default();
+            if 频率 > 0 {
+                重码词.push((词序号, 频率));
+                let 首选词序号 = self.首选词.get(&编码).copied();
+                self.设置冲突来源(引导, 词列表, 词序号, 频率, 首选词序号);
+            } else {
+                重码词.retain(|(x, _)| *x != 词序号);
+                if 重码词.is_empty() {
+                    self.重码词.remove(&编码);
+                }
+                self.设置冲突来源(引导, 词列表, 词序号, -频率, None);
+            }
+            return;
+        }
+        let 首选词序号 = if 频率 > 0 {
+            self.首选词.insert(编码, 词序号);
+            Some(词序号)
+        } else if self.首选词.get(&编码) == Some(&词序号) {
+            self.首选词.remove(&编码);
+            None
+        } else {
+            return;
+        };
+        // 首选字词变化，这个编码上的重码字词都改为与新的首选字词冲突
+        let Some(重码词) = self.重码词.remove(&编码) else {
+            return;
+        };
+        for (重码词序号, 重码词频率) in &重码词 {
+            self.设置冲突来源(引导, 词列表, *重码词序号, *重码词频率, 首选词序号);
+        }
+        self.重码词.insert(编码, 重码词);
+    }
+
+    /// 撤销重码字词原先计入的冲突，再与新的首选字词计入冲突
+    fn 设置冲突来源(
+        &mut self,
+        引导: &mut 冲突引导,
+        词列表: &[可编码对象],
+        词序号: usize,
+        频率: i64,
+        首选词序号: Option<usize>,
+    ) {
+        let 元素序列 = &词列表[词序号].元素序列;
+        if let Some(原首选词序号) = self.冲突来源[词序号].take() {
+            引导.记录(元素序列, &词列表[原首选词序号].元素序列, -频率);
+        }
+        let 首选词序号 = 首选词序号.filter(|x| *x != 词序号);
+        if let Some(首选词序号) = 首选词序号 {
+            引导.记录(元素序列, &词列表[首选词序号].元素序列, 频率);
+        }
+        self.冲突来源[词序号] = 首选词序号;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Mapped, 配置};
+    use crate::data::{原始可编码对象, 数据};
+    use crate::encoders::新建编码器;
+    use crate::objectives::{default::默认目标函数, 目标函数};
+    use crate::operators::{default::默认操作, 变异};
+    use std::collections::HashMap;
+
+    #[test]
+    fn 撤销后恢复原状() {
+        let mut 引导 = 冲突引导::新建(4);
+        let mut 记录 = 冲突记录::新建(2);
+        let 词列表: Vec<_> = vec![vec![0, 1], vec![0, 2]]
+            .into_iter()
+            .map(|元素序列| 可编码对象 {
+                名称: String::new(),
+                词长: 2,
+                元素序列,
+                频率: 1,
+                简码等级: u64::MAX,
+                原始顺序: 0,
+            })
+            .collect();
+        记录.增减(&mut 引导, &词列表, 5, false, 0, 10);
+        记录.增减(&mut 引导, &词列表, 5, true, 1, 3);
+        assert_eq!(引导.元素权重, vec![0, 3, 3, 0]);
+        assert_eq!(引导.抽取键(1, &[1, 2, 3], &vec![0, 1, 2, 3], 0.999, &mut vec![]), Some(3));
+        assert_eq!(引导.冲突最多的元素(1, 2, |_| true), vec![2]);
+        记录.增减(&mut 引导, &词列表, 5, true, 1, -3);
+        assert_eq!(引导.元素权重, vec![0; 4]);
+        assert!(引导.冲突权重.iter().all(|x| x.is_empty()));
+        assert_eq!(引导.抽取元素(|_| true, 0.5), None);
+    }
+
+    #[test]
+    fn 接受和拒绝后冲突统计与重新统计一致() {
+        let mut 配置 = 配置::default();
+        配置.form.alphabet = "abc".to_string();
+        let 元素列表 = ["甲", "乙", "丙", "丁", "戊", "己"];
+        for (序号, 元素) in 元素列表.iter().enumerate() {
+            let 键 = ["a", "b", "c"][序号 % 3];
+            配置
+                .form
+                .mapping
+                .insert(元素.to_string(), Mapped::Basic(键.to_string()));
+        }
+        配置.encoder.max_length = 2;
+        配置.optimization = Some(
+            serde_yaml::from_str(
+                "objective: {characters_full: {duplication: 1.0}, words_full: {duplication: 1.0}}
+metaheuristic: {algorithm: LateAcceptance, history_length: 1, steps: 1, search_method: {random_move: 0.8, random_swap: 0.1, random_full_key_swap: 0.1, conflict_guidance: true}}",
+            )
+            .unwrap(),
+        );
+        let mut 词列表 = vec![];
+        for (序号, 甲) in 元素列表.iter().enumerate() {
+            词列表.push((甲.to_string(), 序号 as u64 + 1));
+            for 乙 in &元素列表[序号..] {
+                词列表.push((format!("{} {}", 甲, 乙), 序号 as u64 + 2));
+            }
+        }
+        let 词列表 = 词列表
+            .into_iter()
+            .enumerate()
+            .map(|(序号, (sequence, frequency))| 原始可编码对象 {
+                name: format!("词{}", 序号),
+                sequence,
+                frequency,
+                level: u64::MAX,
+            })
+            .collect();
+        let 数据 = 数据::新建(配置, 词列表, HashMap::new(), HashMap::new()).unwrap();
+        let mut 编码器 = 新建编码器(&数据).unwrap();
+        let mut 目标函数 = 默认目标函数::新建(&数据).unwrap();
+        let mut 操作 = 默认操作::新建(&数据).unwrap();
+        let mut 当前解 = 数据.初始映射.to_vec();
+        目标函数.计算(编码器.编码(&当前解, &None), &当前解, 0.0);
+        let mut 上次变异 = vec![];
+        for 步数 in 0..200 {
+            let mut 新解 = 当前解.clone();
+            let 变异元素 = 操作.变异(&mut 新解, 目标函数.冲突引导(), 0.5);
+            let mut 移动的元素 = 变异元素.clone();
+            移动的元素.extend(&上次变异);
+            目标函数.计算(编码器.编码(&新解, &Some(移动的元素)), &新解, 0.0);
+            let mut 重新编码器 = 新建编码器(&数据).unwrap();
+            let mut 重新统计 = 默认目标函数::新建(&数据).unwrap();
+            重新统计.计算(重新编码器.编码(&新解, &None), &新解, 0.0);
+            let 增量 = 目标函数.冲突引导().unwrap();
+            let 重新 = 重新统计.冲突引导().unwrap();
+            assert_eq!(增量.元素权重, 重新.元素权重, "第 {} 步", 步数);
+            assert_eq!(增量.冲突权重, 重新.冲突权重, "第 {} 步", 步数);
+            let mut 冲突元素 = 增量.冲突元素.clone();
+            冲突元素.sort();
+            let 权重为正: Vec<_> = (0..新解.len()).filter(|x| 增量.元素权重[*x] > 0).collect();
+            assert_eq!(冲突元素, 权重为正, "第 {} 步", 步数);
+            // 交替接受和拒绝，拒绝时下一次计算需要一并撤销这次变异
+            if 步数 % 2 == 0 {
+                当前解 = 新解;
+                上次变异.clear();
+            } else {
+                上次变异 = 变异元素;
+            }
+        }
+    }
+}
@@ -1,30 +1,38 @@
-use circular_buffer::CircularBuffer;
 use rustc_hash::FxHashMap;
+use std::sync::Arc;
 
 use super::cache::缓存;
-use super::metric::默认指标;
+use super::conflict::冲突引导;
+use super::metric::{容量指标, 默认指标, 软约束指标};
 use super::目标函数;
 use crate::config::PartialWeights;
 use crate::data::{
-    元素映射, 指法向量, 数据, 正则化, 编码信息, 键位分布损失函数
+    元素映射, 可编码对象, 容量约束, 容量计数, 指法向量, 数据, 正则化, 编码信息, 软约束,
+    键位分布损失函数,
 };
-use crate::encoders::default::元素序列;
 use crate::错误;
 
 #[derive(Clone)]
 pub struct 默认目标函数 {
     pub 参数: 默认目标函数参数,
     pub 计数桶列表: Vec<[Option<缓存>; 2]>,
+    pub 冲突引导: Option<冲突引导>,
+    pub 容量计数: 容量计数,
 }
 
 #[derive(Clone)]
 pub struct 默认目标函数参数 {
     pub 键位分布信息: Vec<键位分布损失函数>,
-    pub 当量信息: Vec<f64>,
-    pub 指法计数: Vec<指法向量>,
-    pub 数字转键: FxHashMap<u64, char>,
+    pub 当量信息: Arc<Vec<f64>>,
+    pub 指法计数: Arc<Vec<指法向量>>,
+    pub 数字转键: Arc<FxHashMap<u64, char>>,
     pub 正则化: 正则化,
     pub 正则化强度: f64,
+    pub 容量约束列表: Vec<容量约束>,
+    pub 软约束: 软约束,
+    pub 初始映射: Arc<元素映射>,
+    pub 进制: u64,
+    pub 词列表: Arc<Vec<可编码对象>>,
 }
 
 pub type Frequencies = Vec<f64>;
@@ -49,7 +57,7 @@ impl 默认目标函数 {
         let 键位分布信息 = 数据.键位分布信息.clone();
         let 当量信息 = 数据.当量信息.clone();
         let 正则化 = 数据.正则化.clone();
-        let 指法计数 = 数据.预处理指法标记();
+        let 指法计数 = 数据.指法标记.clone();
         let config = 数据
             .配置
             .optimization
@@ -58,7 +66,17 @@ impl 默认目标函数 {
             .objective
             .clone();
         let 最大编码 = 当量信息.len() as u64;
-        let 构造缓存 = |x: &PartialWeights| 缓存::new(x, 数据.进制, 数据.词列表.len(), 最大编码);
+        let 开启冲突引导 = 数据
+            .配置
+            .optimization
+            .as_ref()
+            .and_then(|x| x.metaheuristic.as_ref())
+            .and_then(|x| x.变异配置())
+            .and_then(|x| x.conflict_guidance)
+            .unwrap_or(false);
+        let 构造缓存 = |x: &PartialWeights| {
+            缓存::new(x, 数据.进制, 数据.词列表.len(), 最大编码, 开启冲突引导)
+        };
         let 一字全码 = config.characters_full.as_ref().map(构造缓存);
         let 一字简码 = config.characters_short.as_ref().map(构造缓存);
         let 多字全码 = config.words_full.as_ref().map(构造缓存);
@@ -74,20 +92,66 @@ impl 默认目标函数 {
                 .regularization
                 .and_then(|x| x.strength)
                 .unwrap_or(1.0),
+            // 所有容量约束都报告各键的使用情况；硬容量约束由算子保证不会超出，只有软约束计入惩罚
+            容量约束列表: 数据.预处理容量约束()?,
+            软约束: 数据.预处理软约束()?,
+            初始映射: 数据.初始映射.clone(),
+            进制: 数据.进制,
+            词列表: 数据.词列表.clone(),
         };
+        let 冲突引导 = 开启冲突引导.then(|| 冲突引导::新建(数据.初始映射.len()));
+        let 容量计数 = 容量计数::新建(&参数.容量约束列表, &数据.初始映射);
         Ok(Self {
-            参数, 计数桶列表
+            参数,
+            计数桶列表,
+            冲突引导,
+            容量计数,
         })
     }
 }
 
+impl 默认目标函数参数 {
+    /// 每个未满足的偏好、每个处于禁用位置的元素按各自的权重计入惩罚，偏离初始映射的元素按统一的权重计入惩罚
+    fn 计算软约束(&self, 映射: &元素映射) -> 软约束指标 {
+        let mut 指标 = 软约束指标 {
+            preferred_keys: 0,
+            forbidden_positions: 0,
+            moved_elements: 0,
+            penalty: 0.0,
+        };
+        for (元素, 键列表, 权重) in &self.软约束.偏好键 {
+            if !键列表.contains(&映射[*元素]) {
+                指标.preferred_keys += 1;
+                指标.penalty += 权重;
+            }
+        }
+        for (元素, 键列表, 权重) in &self.软约束.禁用键 {
+            if 键列表.contains(&映射[*元素]) {
+                指标.forbidden_positions += 1;
+                指标.penalty += 权重;
+            }
+        }
+        if let Some(权重) = self.软约束.保持初始映射 {
+            // 前面的若干个元素是按键本身，不计入
+            指标.moved_elements = 映射
+                .iter()
+                .zip(self.初始映射.iter())
+                .skip(self.进制 as usize)
+                .filter(|(x, y)| x != y)
+                .count();
+            指标.penalty += 权重 * 指标.moved_elements as f64;
+        }
+        指标
+    }
+}
+
 impl 目标函数 for 默认目标函数 {
     type 目标值 = 默认指标;
 
     /// 计算各个部分编码的指标，然后将它们合并成一个指标输出
     fn 计算(
-        &mut self, 编码结果: &mut [编码信息], 映射: &元素映射, 进度: f64
-    ) -> (默认指标, f64, FxHashMap<usize, f64>, FxHashMap<usize, FxHashMap<usize, CircularBuffer<4, f64>>>) {
+        &mut self, 编码结果: &mut [编码信息], 映射: &元素映射, _进度: f64
+    ) -> (默认指标, f64) {
         let 参数 = &self.参数;
         let mut 桶序号列表: Vec<_> = self.计数桶列表.iter().map(|_| 0).collect();
         // 开始计算指标
@@ -97,10 +161,10 @@ impl 目标函数 for 默认目标函数 {
             let 桶 = &mut self.计数桶列表[桶索引];
             let 桶序号 = 桶序号列表[桶索引];
             if let Some(缓存) = &mut 桶[0] {
-                缓存.处理(桶序号, 频率, &mut 编码信息.全码, 参数, &元素序列[i]);
+                缓存.处理(桶序号, 频率, &mut 编码信息.全码, 参数, i, &mut self.冲突引导);
             }
             if let Some(缓存) = &mut 桶[1] {
-                缓存.处理(桶序号, 频率, &mut 编码信息.简码, 参数, &元素序列[i]);
+                缓存.处理(桶序号, 频率, &mut 编码信息.简码, 参数, i, &mut self.冲突引导);
             }
             桶序号列表[桶索引] += 1;
         }
@@ -112,27 +176,27 @@ impl 目标函数 for 默认目标函数 {
             characters_short: None,
             words_short: None,
             memory: None,
+            capacity: None,
+            soft_constraints: None,
         };
         for (桶索引, 桶) in self.计数桶列表.iter_mut().enumerate() {
             let _ = 桶[0].as_mut().map(|x| {
-                let (分组指标, 分组目标函数) = x.汇总(参数, 进度);
+                let (分组指标, 分组目标函数) = x.汇总(参数);
                 目标函数 += 分组目标函数;
                 if 桶索引 == 0 {
                     指标.characters_full = Some(分组指标);
                 } else {
                     指标.words_full = Some(分组指标);
                 }
-                x.冲突补全();
             });
             let _ = &mut 桶[1].as_mut().map(|x| {
-                let (分组指标, 分组目标函数) = x.汇总(参数, 进度);
+                let (分组指标, 分组目标函数) = x.汇总(参数);
                 目标函数 += 分组目标函数;
                 if 桶索引 == 0 {
                     指标.characters_short = Some(分组指标);
                 } else {
                     指标.words_short = Some(分组指标);
                 }
-                x.冲突补全();
             });
         }
 
@@ -157,6 +221,37 @@ impl 目标函数 for 默认目标函数 {
             let 归一化记忆量 = 记忆量 / 映射.len() as f64;
             目标函数 += 归一化记忆量 * 参数.正则化强度;
         }
-        (指标, 目标函数, self.计数桶列表[0][1].as_ref().unwrap().概率.clone(), self.计数桶列表[0][1].as_ref().unwrap().冲突.clone())
+
+        if !参数.软约束.is_empty() {
+            let 软约束指标 = 参数.计算软约束(映射);
+            目标函数 += 软约束指标.penalty;
+            指标.soft_constraints = Some(软约束指标);
+        }
+
+        // 统计每条容量约束的使用情况，软约束按超出上限的元素个数计入惩罚
+        if !参数.容量约束列表.is_empty() {
+            self.容量计数.同步(&参数.容量约束列表, 映射);
+            let mut 容量指标列表 = vec![];
+            for (序号, 约束) in 参数.容量约束列表.iter().enumerate() {
+                let 计数 = |键: &u64| self.容量计数.计数(序号, *键);
+                let excess: usize = 约束
+                    .键列表
+                    .iter()
+                    .map(|x| 计数(x).saturating_sub(约束.上限))
+                    .sum();
+                目标函数 += 约束.惩罚.unwrap_or(0.0) * excess as f64;
+                容量指标列表.push(容量指标 {
+                    max: 约束.上限,
+                    usage: 约束.键列表.iter().map(|x| (参数.数字转键[x], 计数(x))).collect(),
+                    excess,
+                });
+            }
+            指标.capacity = Some(容量指标列表);
+        }
+        (指标, 目标函数)
+    }
+
+    fn 冲突引导(&self) -> Option<&冲突引导> {
+        self.冲突引导.as_ref()
     }
 }
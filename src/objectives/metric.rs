@@ -5,7 +5,30 @@ use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 
 type 指法集 = HashSet<(char, char)>;
-type 键盘布局 = [Vec<char>; 4];
+type 键盘布局 = [&'static [char]; 4];
+
+// 单手的键盘布局，每一行从食指一侧向小指一侧排列
+const 左手布局: 键盘布局 = [
+    &['5', '4', '3', '2', '1'],
+    &['t', 'r', 'e', 'w', 'q'],
+    &['g', 'f', 'd', 's', 'a'],
+    &['b', 'v', 'c', 'x', 'z'],
+];
+const 右手布局: 键盘布局 = [
+    &['6', '7', '8', '9', '0', '-', '='],
+    &['y', 'u', 'i', 'o', 'p', '[', ']'],
+    &['h', 'j', 'k', 'l', ';', '\''],
+    &['n', 'm', ',', '.', '/'],
+];
+const 列对应手指: [手指; 7] = [
+    手指::食指,
+    手指::食指,
+    手指::中指,
+    手指::无名指,
+    手指::小指,
+    手指::小指,
+    手指::小指,
+];
 
 // 指法分析
 //
@@ -19,31 +42,49 @@ pub struct 指法标记 {
     pub 错手: 指法集,
 }
 
-#[derive(PartialEq, PartialOrd, Copy, Clone)]
-enum 手指 {
-    _大拇指,
+#[derive(Debug, PartialEq, PartialOrd, Copy, Clone)]
+pub enum 手指 {
+    大拇指,
     食指,
     中指,
     无名指,
     小指,
 }
 
+/// 按键在键盘上的位置，行号从数字行开始依次为 0 到 3
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct 键位 {
+    pub 行: usize,
+    pub 右手: bool,
+    pub 手指: 手指,
+}
+
+/// 查找一个按键在标准键盘上的位置，不在主键区的按键返回 None
+pub fn 查找键位(键: char) -> Option<键位> {
+    for (右手, 布局) in [(false, &左手布局), (true, &右手布局)] {
+        for (行, 行内按键) in 布局.iter().enumerate() {
+            if let Some(列) = 行内按键.iter().position(|x| *x == 键) {
+                return Some(键位 {
+                    行,
+                    右手,
+                    手指: 列对应手指[列],
+                });
+            }
+        }
+    }
+    None
+}
+
+impl Default for 指法标记 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl 指法标记 {
     pub fn new() -> 指法标记 {
-        let 左手: 键盘布局 = [
-            vec!['5', '4', '3', '2', '1'],
-            vec!['t', 'r', 'e', 'w', 'q'],
-            vec!['g', 'f', 'd', 's', 'a'],
-            vec!['b', 'v', 'c', 'x', 'z'],
-        ];
-        let 右手: 键盘布局 = [
-            vec!['6', '7', '8', '9', '0', '-', '='],
-            vec!['y', 'u', 'i', 'o', 'p', '[', ']'],
-            vec!['h', 'j', 'k', 'l', ';', '\''],
-            vec!['n', 'm', ',', '.', '/'],
-        ];
-        let mut 左手标记 = Self::生成单手指法标记(&左手);
-        let 右手标记 = Self::生成单手指法标记(&右手);
+        let mut 左手标记 = Self::生成单手指法标记(&左手布局);
+        let 右手标记 = Self::生成单手指法标记(&右手布局);
         左手标记.同手.extend(右手标记.同手);
         左手标记.同指大跨排.extend(右手标记.同指大跨排);
         左手标记.同指小跨排.extend(右手标记.同指小跨排);
@@ -54,7 +95,6 @@ impl 指法标记 {
 
     fn 生成单手指法标记(单手布局: &键盘布局) -> 指法标记 {
         use 手指::*;
-        let 列对应手指: [手指; 7] = [食指, 食指, 中指, 无名指, 小指, 小指, 小指];
         let 是长手指 = |x: 手指| x == 中指 || x == 无名指;
         let mut 同手 = 指法集::new();
         let mut 同指大跨排 = 指法集::new();
@@ -102,7 +142,7 @@ impl 指法标记 {
     }
 }
 
-const 指法标记名称: [&str; 8] = [
+pub const 指法标记名称: [&str; 8] = [
     "同手", "大跨", "小跨", "干扰", "错手", "三连", "备用", "备用",
 ];
 
@@ -127,6 +167,9 @@ pub struct 层级指标 {
     pub duplication: Option<u64>,
     pub levels: Option<Vec<LevelMetricUniform>>,
     pub fingering: Option<FingeringMetricUniform>,
+    pub key_distribution: Option<HashMap<char, f64>>,
+    pub key_distribution_loss: Option<f64>,
+    pub pair_equivalence: Option<f64>,
 }
 
 impl Display for 层级指标 {
@@ -157,6 +200,16 @@ impl Display for 层级指标 {
                 }
             }
         }
+        if let Some(key_distribution_loss) = self.key_distribution_loss {
+            f.write_str(&format!(
+                "{}用指分布偏差：{:.2}%；",
+                specifier,
+                key_distribution_loss * 100.0
+            ))?;
+        }
+        if let Some(equivalence) = self.pair_equivalence {
+            f.write_str(&format!("{}组合当量：{:.4}；", specifier, equivalence))?;
+        }
         Ok(())
     }
 }
@@ -171,6 +224,7 @@ pub struct 分组指标 {
     pub extended_pair_equivalence: Option<f64>,
     pub fingering: Option<FingeringMetric>,
     pub levels: Option<Vec<键长指标>>,
+    pub selection_cost: Option<f64>,
 }
 
 const 键盘布局: [[char; 10]; 5] = [
@@ -187,6 +241,9 @@ impl Display for 分组指标 {
         if let Some(duplication) = self.duplication {
             f.write_str(&format!("选重率：{:.4}%；", duplication * 100.0))?;
         }
+        if let Some(selection_cost) = self.selection_cost {
+            f.write_str(&format!("平均选重代价：{:.4}；", selection_cost))?;
+        }
         if let Some(key_distribution_loss) = self.key_distribution_loss {
             f.write_str(&format!(
                 "用指分布偏差：{:.2}%；",
@@ -242,6 +299,44 @@ impl Display for 分组指标 {
     }
 }
 
+/// 一条容量约束在每个键上计入的元素个数，以及所有键超出上限的总数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct 容量指标 {
+    pub max: usize,
+    pub usage: HashMap<char, usize>,
+    pub excess: usize,
+}
+
+impl Display for 容量指标 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!("容量上限 {}：超出 {}；", self.max, self.excess))?;
+        let mut 超限键: Vec<_> = self.usage.iter().filter(|(_, x)| **x > self.max).collect();
+        超限键.sort();
+        for (键, 个数) in 超限键 {
+            f.write_str(&format!("{} {}；", 键, 个数))?;
+        }
+        Ok(())
+    }
+}
+
+/// 软约束的违反情况：未放在偏好键上的元素数、放在禁用位置上的元素数、偏离初始映射的元素数，以及总惩罚
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct 软约束指标 {
+    pub preferred_keys: usize,
+    pub forbidden_positions: usize,
+    pub moved_elements: usize,
+    pub penalty: f64,
+}
+
+impl Display for 软约束指标 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!(
+            "软约束［未在偏好键：{}；在禁用位置：{}；偏离初始映射：{}；惩罚：{:.4}；］",
+            self.preferred_keys, self.forbidden_positions, self.moved_elements, self.penalty
+        ))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct 默认指标 {
     pub characters_full: Option<分组指标>,
@@ -249,6 +344,8 @@ pub struct 默认指标 {
     pub words_full: Option<分组指标>,
     pub words_short: Option<分组指标>,
     pub memory: Option<f64>,
+    pub capacity: Option<Vec<容量指标>>,
+    pub soft_constraints: Option<软约束指标>,
 }
 
 impl Display for 默认指标 {
@@ -256,6 +353,14 @@ impl Display for 默认指标 {
         if let Some(记忆量) = &self.memory {
             f.write_str(&format!("记忆量：{:.2}；\n", 记忆量))?;
         }
+        if let Some(软约束) = &self.soft_constraints {
+            f.write_str(&format!("{}\n", 软约束))?;
+        }
+        if let Some(容量) = &self.capacity {
+            for 约束指标 in 容量 {
+                f.write_str(&format!("{}\n", 约束指标))?;
+            }
+        }
         if let Some(characters) = &self.characters_full {
             f.write_str(&format!("一字全码［{}］\n", characters))?;
         }
@@ -272,6 +377,95 @@ impl Display for 默认指标 {
     }
 }
 
+/// 两组指标中某一项的差异，路径用点号分隔各级字段名
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct 指标差异 {
+    pub path: String,
+    pub old: serde_yaml::Value,
+    pub new: serde_yaml::Value,
+}
+
+impl Display for 指标差异 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let 格式化 = |x: &serde_yaml::Value| {
+            serde_yaml::to_string(x)
+                .map(|x| x.trim_end().to_string())
+                .unwrap_or_default()
+        };
+        write!(f, "{}：{} → {}", self.path, 格式化(&self.old), 格式化(&self.new))
+    }
+}
+
+/// 将两组指标序列化之后逐项比较，返回所有不同的项
+///
+/// 数值之间的相对误差不超过容差时视为相同，用于容忍浮点数累加带来的舍入误差
+pub fn 比较指标<T: Serialize>(旧: &T, 新: &T, 容差: f64) -> Vec<指标差异> {
+    let mut 差异列表 = vec![];
+    let 旧 = serde_yaml::to_value(旧).unwrap_or_default();
+    let 新 = serde_yaml::to_value(新).unwrap_or_default();
+    递归比较(String::new(), &旧, &新, 容差, &mut 差异列表);
+    差异列表
+}
+
+fn 递归比较(
+    路径: String,
+    旧: &serde_yaml::Value,
+    新: &serde_yaml::Value,
+    容差: f64,
+    差异列表: &mut Vec<指标差异>,
+) {
+    use serde_yaml::Value;
+    let 子路径 = |键: &str| {
+        if 路径.is_empty() {
+            键.to_string()
+        } else {
+            format!("{}.{}", 路径, 键)
+        }
+    };
+    match (旧, 新) {
+        (Value::Mapping(旧), Value::Mapping(新)) => {
+            let mut 键列表: Vec<&Value> = 旧.keys().collect();
+            键列表.extend(新.keys().filter(|x| !旧.contains_key(*x)));
+            for 键 in 键列表 {
+                let 名称 = match 键 {
+                    Value::String(x) => x.clone(),
+                    other => serde_yaml::to_string(other).unwrap_or_default().trim_end().to_string(),
+                };
+                let 旧值 = 旧.get(键).unwrap_or(&Value::Null);
+                let 新值 = 新.get(键).unwrap_or(&Value::Null);
+                递归比较(子路径(&名称), 旧值, 新值, 容差, 差异列表);
+            }
+        }
+        (Value::Sequence(旧), Value::Sequence(新)) => {
+            for i in 0..旧.len().max(新.len()) {
+                let 旧值 = 旧.get(i).unwrap_or(&Value::Null);
+                let 新值 = 新.get(i).unwrap_or(&Value::Null);
+                递归比较(子路径(&i.to_string()), 旧值, 新值, 容差, 差异列表);
+            }
+        }
+        (Value::Number(x), Value::Number(y)) => {
+            let (x, y) = (x.as_f64().unwrap_or(0.0), y.as_f64().unwrap_or(0.0));
+            let 相同 = (x.is_nan() && y.is_nan()) || (x - y).abs() <= 容差 * x.abs().max(y.abs()).max(1.0);
+            if !相同 {
+                差异列表.push(指标差异 {
+                    path: 路径,
+                    old: 旧.clone(),
+                    new: 新.clone(),
+                });
+            }
+        }
+        _ => {
+            if 旧 != 新 {
+                差异列表.push(指标差异 {
+                    path: 路径,
+                    old: 旧.clone(),
+                    new: 新.clone(),
+                });
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -3,17 +3,22 @@
 //!
 
 use crate::data::{元素映射, 编码信息};
-use circular_buffer::CircularBuffer;
-use rustc_hash::FxHashMap;
+use conflict::冲突引导;
 use serde::Serialize;
 use std::fmt::Display;
 pub mod cache;
+pub mod conflict;
 pub mod default;
 pub mod metric;
 
 pub trait 目标函数 {
-    type 目标值: Display + Clone + Serialize;
+    type 目标值: Display + Clone + Serialize + Send;
     fn 计算(
         &mut self, 编码结果: &mut [编码信息], 映射: &元素映射, 进度: f64
-    ) -> (Self::目标值, f64, FxHashMap<usize, f64>, FxHashMap<usize, FxHashMap<usize, CircularBuffer<4, f64>>>);
+    ) -> (Self::目标值, f64);
+
+    /// 计算过程中统计的冲突信息，供变异算子引导搜索；未开启冲突引导时为空
+    fn 冲突引导(&self) -> Option<&冲突引导> {
+        None
+    }
 }
@@ -1,92 +1,298 @@
-use super::变异;
-use crate::config::{AtomicConstraint, MappedKey, SolverConfig};
+use super::{变异, 重新放置};
+use crate::config::{AtomicConstraint, ElementWithIndex, Hand, MappedKey, RelationConstraint};
 use crate::data::{键, 数据};
-use crate::data::{元素, 元素映射};
+use crate::data::{元素, 元素映射, 容量约束, 容量计数};
+use crate::objectives::conflict::冲突引导;
+use crate::objectives::metric::{查找键位, 键位};
 use crate::错误;
-use circular_buffer::CircularBuffer;
-use rand::distributions::WeightedIndex;
-use rand::prelude::Distribution;
 use rand::seq::{IteratorRandom, SliceRandom};
 use rand::{random, thread_rng};
 use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 
 pub struct 默认操作 {
     fixed: HashSet<元素>,
     narrowed: HashMap<元素, Vec<键>>,
+    同键组列表: Vec<Vec<元素>>,
+    所在同键组: HashMap<元素, usize>,
+    关系约束列表: Vec<关系约束>,
+    相关约束: HashMap<元素, Vec<usize>>,
+    容量约束列表: Vec<容量约束>,
+    /// 最近一次检查容量约束时的计数，检查时同步到被检查的映射
+    容量计数: RefCell<容量计数>,
+    键位表: FxHashMap<键, 键位>,
     alphabet: Vec<键>,
     radix: usize,    // 码表的基数
     elements: usize, // 键盘映射的元素个数
     变异配置: 变异配置,
+    键权重缓冲: Vec<i64>,
+    自适应: Option<自适应状态>,
+    /// 变异前各个被移动元素所在的键，变异不满足约束时按倒序恢复
+    撤销记录: Vec<(元素, 键)>,
 }
 
+/// 编译后的关系约束，同键约束会被合并为同键组单独处理，不在此列
+#[derive(Debug, Clone)]
+pub enum 关系约束 {
+    异键(Vec<元素>),
+    同行(Vec<元素>),
+    同手(Vec<元素>),
+}
+
+/// 从配置文件中编译得到的全部约束
+pub struct 编译约束 {
+    pub fixed: HashSet<元素>,
+    pub narrowed: HashMap<元素, Vec<键>>,
+    pub 同键组列表: Vec<Vec<元素>>,
+    pub 关系约束列表: Vec<关系约束>,
+    pub 容量约束列表: Vec<容量约束>,
+}
+
+/// 变异结果不满足关系约束或容量约束时重新变异的最大次数
+const 最大尝试次数: usize = 100;
+
 #[skip_serializing_none]
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct 变异配置 {
     pub random_move: f64,
     pub random_swap: f64,
     pub random_full_key_swap: f64,
+    /// 按重码中的冲突情况选取移动的元素和目标键，默认关闭
+    pub conflict_guidance: Option<bool>,
+    /// 按各种变异方式的近期表现自动调整它们的概率，上面三个概率只作为初始值；默认关闭
+    pub adaptive: Option<自适应配置>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct 自适应配置 {
+    /// 计算近期接受率和改进率时平均的次数，默认为 1000
+    pub window: Option<usize>,
+    /// 每种变异方式的最低概率，默认为 0.05
+    pub min_probability: Option<f64>,
 }
 
 pub const DEFAULT_MUTATE: 变异配置 = 变异配置 {
     random_move: 0.9,
     random_swap: 0.09,
     random_full_key_swap: 0.01,
+    conflict_guidance: None,
+    adaptive: None,
 };
 
+const 默认窗口: usize = 1000;
+const 默认最低概率: f64 = 0.05;
+/// 评分中接受率相对于改进率的权重，改进很少时按接受率区分各种变异方式
+const 接受权重: f64 = 0.1;
+/// 调用方长时间不反馈时丢弃未反馈的记录
+const 最大待反馈数: usize = 1024;
+
+/// 自适应地选取变异方式：按近期的改进率和接受率给每种变异方式评分，概率与评分成正比，但不低于最低概率
+#[derive(Debug, Clone)]
+struct 自适应状态 {
+    窗口: f64,
+    最低概率: f64,
+    初始概率: [f64; 3],
+    概率: [f64; 3],
+    次数: [usize; 3],
+    接受率: [f64; 3],
+    改进率: [f64; 3],
+    待反馈: Vec<usize>,
+}
+
 impl 变异 for 默认操作 {
-    fn 变异(&mut self, candidate: &mut 元素映射, 概率: &FxHashMap<usize, f64>, 冲突: &FxHashMap<usize, FxHashMap<usize, CircularBuffer<4, f64>>>, 进度: f64) -> Vec<元素> {
-        let 变异配置 {
+    fn 变异(&mut self, candidate: &mut 元素映射, 引导: Option<&冲突引导>, 进度: f64) -> Vec<元素> {
+        let (算子, 变异元素) = self.有约束的变异(candidate, 引导, 进度);
+        // 多次尝试都不满足约束时没有移动任何元素，调用方会跳过这一步，不会反馈
+        if let Some(自适应) = self.自适应.as_mut() {
+            if !变异元素.is_empty() {
+                自适应.记录(算子);
+            }
+        }
+        变异元素
+    }
+
+    fn 反馈(&mut self, 结果: &[(bool, bool)]) {
+        if let Some(自适应) = self.自适应.as_mut() {
+            自适应.反馈(结果);
+        }
+    }
+
+    fn 变异概率(&self) -> Option<变异配置> {
+        let [random_move, random_swap, random_full_key_swap] = self.自适应.as_ref()?.概率;
+        Some(变异配置 {
             random_move,
             random_swap,
             random_full_key_swap,
-        } = self.变异配置;
-        let sum = random_move + random_swap + random_full_key_swap;
-        let ratio1 = random_move / sum;
-        let ratio2 = (random_move + random_swap) / sum;
-        let number: f64 = random();
-        if number < ratio1 {
-            self.有约束的随机移动(candidate, 概率, 冲突, 进度)
-        } else if number < ratio2 {
-            self.有约束的随机交换(candidate)
-        } else {
-            self.有约束的整键随机交换(candidate, 概率)
+            conflict_guidance: None,
+            adaptive: None,
+        })
+    }
+}
+
+impl 重新放置 for 默认操作 {
+    fn 可选键(&self, 元素: 元素) -> &[键] {
+        if 元素 < self.radix || self.fixed.contains(&元素) {
+            return &[];
         }
+        self.narrowed.get(&元素).unwrap_or(&self.alphabet)
+    }
+
+    fn 同键元素(&self, 元素: 元素) -> &[元素] {
+        self.所在同键组
+            .get(&元素)
+            .map_or(&[], |x| &self.同键组列表[*x])
+    }
+
+    fn 放置(&self, 映射: &mut 元素映射, 元素: 元素, 键: 键) -> Vec<元素> {
+        映射[元素] = 键;
+        let mut 移动的元素 = vec![元素];
+        self.传播同键约束(映射, &mut 移动的元素);
+        移动的元素
+    }
+
+    fn 满足约束(&self, 映射: &元素映射, 移动的元素: &[元素]) -> bool {
+        self.满足关系约束(映射, 移动的元素) && self.满足容量约束(映射, 移动的元素)
     }
 }
 
 // 默认的问题实现，使用配置文件中的约束来定义各种算子
 impl 默认操作 {
+    /// 返回最后一次尝试的变异方式和变异的元素，所有尝试都不满足约束时映射保持不变，变异的元素为空
+    fn 有约束的变异(
+        &mut self,
+        candidate: &mut 元素映射,
+        引导: Option<&冲突引导>,
+        进度: f64,
+    ) -> (usize, Vec<元素>) {
+        let mut 撤销记录 = std::mem::take(&mut self.撤销记录);
+        撤销记录.clear();
+        if self.同键组列表.is_empty()
+            && self.关系约束列表.is_empty()
+            && self.容量约束列表.is_empty()
+        {
+            let 算子 = self.选取算子();
+            let 变异元素 = self.基本变异(算子, candidate, 引导, 进度, &mut 撤销记录);
+            self.撤销记录 = 撤销记录;
+            return (算子, 变异元素);
+        }
+        // 先按原子约束变异，再让同键组的其他元素跟随移动，若仍不满足关系约束或容量约束则撤销并重试
+        let mut 算子 = 0;
+        let mut 结果 = vec![];
+        for _ in 0..最大尝试次数 {
+            算子 = self.选取算子();
+            let mut 变异元素 = self.基本变异(算子, candidate, 引导, 进度, &mut 撤销记录);
+            for 元素 in &变异元素 {
+                if let Some(组号) = self.所在同键组.get(元素) {
+                    撤销记录.extend(self.同键组列表[*组号].iter().map(|x| (*x, candidate[*x])));
+                }
+            }
+            self.传播同键约束(candidate, &mut 变异元素);
+            if self.满足关系约束(candidate, &变异元素) && self.满足容量约束(candidate, &变异元素)
+            {
+                结果 = 变异元素;
+                break;
+            }
+            // 同一个元素可能被记录多次，倒序恢复时最早的记录最后生效
+            for (元素, 键) in 撤销记录.drain(..).rev() {
+                candidate[元素] = 键;
+            }
+        }
+        self.撤销记录 = 撤销记录;
+        (算子, 结果)
+    }
+
+    /// 按概率选取一种变异方式，依次为随机移动、随机交换和整键随机交换
+    fn 选取算子(&self) -> usize {
+        let 概率 = match &self.自适应 {
+            Some(自适应) => 自适应.概率,
+            None => 配置概率(&self.变异配置),
+        };
+        let number: f64 = random();
+        if number < 概率[0] {
+            0
+        } else if number < 概率[0] + 概率[1] {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn 基本变异(
+        &mut self,
+        算子: usize,
+        candidate: &mut 元素映射,
+        引导: Option<&冲突引导>,
+        进度: f64,
+        撤销记录: &mut Vec<(元素, 键)>,
+    ) -> Vec<元素> {
+        match 算子 {
+            0 => self.有约束的随机移动(candidate, 引导, 进度, 撤销记录),
+            1 => self.有约束的随机交换(candidate, 撤销记录),
+            _ => self.有约束的整键随机交换(candidate, 引导, 进度, 撤销记录),
+        }
+    }
+
     pub fn 新建(数据: &数据) -> Result<Self, 错误> {
-        let (fixed, narrowed) = Self::make_constraints(数据)?;
+        let 编译约束 {
+            fixed,
+            narrowed,
+            同键组列表,
+            关系约束列表,
+            容量约束列表,
+        } = Self::make_constraints(数据)?;
         let config = 数据.配置.optimization.clone();
-        let SolverConfig::SimulatedAnnealing(退火方法) = config.unwrap().metaheuristic.unwrap();
-        let 变异配置 = 退火方法.search_method.unwrap_or(DEFAULT_MUTATE);
-        let alphabet: Vec<_> = 数据
-            .配置
-            .form
-            .alphabet
-            .chars()
-            .map(|x| *数据.键转数字.get(&x).unwrap()) // 在生成表示的时候已经确保了这里一定有对应的键
-            .collect();
+        let 变异配置 = config
+            .and_then(|x| x.metaheuristic)
+            .and_then(|x| x.变异配置())
+            .unwrap_or(DEFAULT_MUTATE);
+        let alphabet = Self::生成字母表(数据);
+        let mut 所在同键组 = HashMap::new();
+        for (序号, 同键组) in 同键组列表.iter().enumerate() {
+            for 元素 in 同键组 {
+                所在同键组.insert(*元素, 序号);
+            }
+        }
+        let mut 相关约束: HashMap<元素, Vec<usize>> = HashMap::new();
+        for (序号, 约束) in 关系约束列表.iter().enumerate() {
+            for 元素 in 约束.元素列表() {
+                相关约束.entry(*元素).or_default().push(序号);
+            }
+        }
         Ok(Self {
             fixed,
             narrowed,
+            同键组列表,
+            所在同键组,
+            关系约束列表,
+            相关约束,
+            容量计数: RefCell::new(容量计数::新建(&容量约束列表, &数据.初始映射)),
+            容量约束列表,
+            键位表: Self::生成键位表(数据),
             alphabet,
             radix: 数据.进制 as usize,
             elements: 数据.初始映射.len(),
             变异配置,
+            键权重缓冲: vec![],
+            撤销记录: vec![],
+            自适应: 变异配置
+                .adaptive
+                .map(|x| 自适应状态::新建(&x, 配置概率(&变异配置))),
         })
     }
 
     /// 传入配置表示来构造约束，把用户在配置文件中编写的约束「编译」成便于快速计算的数据结构
-    fn make_constraints(
-        representation: &数据,
-    ) -> Result<(HashSet<元素>, HashMap<元素, Vec<键>>), 错误> {
-        let mut fixed: HashSet<元素> = HashSet::new();
-        let mut narrowed: HashMap<元素, Vec<键>> = HashMap::new();
+    fn make_constraints(representation: &数据) -> Result<编译约束, 错误> {
+        let mut 约束 = 编译约束 {
+            fixed: HashSet::new(),
+            narrowed: HashMap::new(),
+            同键组列表: vec![],
+            关系约束列表: vec![],
+            容量约束列表: vec![],
+        };
         let mut values: Vec<AtomicConstraint> = Vec::new();
         let lookup = |x: String| {
             let element_number = representation.元素转数字.get(&x);
@@ -156,85 +362,292 @@ impl 默认操作 {
                     if transformed.is_empty() {
                         return Err("约束中的键列表不能为空".into());
                     }
-                    narrowed.insert(element, transformed);
+                    约束.收窄(element, transformed, representation)?;
                 } else {
-                    fixed.insert(element);
+                    约束.fixed.insert(element);
                 }
             }
         }
-        Ok((fixed, narrowed))
+        let relations = optimization
+            .constraints
+            .as_ref()
+            .and_then(|x| x.relations.clone())
+            .unwrap_or_default();
+        Self::make_relations(representation, &relations, &mut 约束)?;
+        Self::make_capacities(representation, &mut 约束)?;
+        Ok(约束)
     }
 
-    fn get_movable_element(&self, 概率: &FxHashMap<usize, f64>) -> usize {
-        let mut rng = thread_rng();
-        loop {
-            let key = if 概率.is_empty() {
-                (self.radix..self.elements).choose(&mut rng).unwrap()
+    /// 编译硬容量约束，带有惩罚系数的软约束由目标函数处理
+    fn make_capacities(representation: &数据, 约束: &mut 编译约束) -> Result<(), 错误> {
+        let 初始映射 = &representation.初始映射;
+        let alphabet = Self::生成字母表(representation);
+        for 容量约束 in representation.预处理容量约束()? {
+            if 容量约束.惩罚.is_some() {
+                continue;
+            }
+            for 键 in &容量约束.键列表 {
+                if 容量约束.计数(初始映射, *键) > 容量约束.上限 {
+                    let 键字符 = representation.数字转键[键];
+                    return Err(format!("初始映射中键 {键字符} 上的元素超过了容量上限 {}", 容量约束.上限).into());
+                }
+            }
+            // 只能放在受约束的键上的元素个数不能超过这些键的总容量
+            let 必须计入 = 容量约束
+                .计入元素
+                .iter()
+                .filter(|x| {
+                    约束
+                        .可选键(**x, &alphabet, 初始映射)
+                        .iter()
+                        .all(|k| 容量约束.键列表.contains(k))
+                })
+                .count();
+            if 必须计入 > 容量约束.上限 * 容量约束.键列表.len() {
+                return Err(format!(
+                    "有 {必须计入} 个元素只能放在容量约束的键上，超过了总容量 {}",
+                    容量约束.上限 * 容量约束.键列表.len()
+                )
+                .into());
+            }
+            约束.容量约束列表.push(容量约束);
+        }
+        Ok(())
+    }
+
+    /// 编译关系约束：指定了手的同手约束和同键约束会进一步收窄元素的可选键，其余约束在变异时检查
+    ///
+    /// 在编译时检查约束能否同时满足，以及初始映射是否满足约束，尽早报告矛盾的配置
+    fn make_relations(
+        representation: &数据,
+        relations: &[RelationConstraint],
+        约束: &mut 编译约束,
+    ) -> Result<(), 错误> {
+        let 键位表 = Self::生成键位表(representation);
+        let alphabet = Self::生成字母表(representation);
+        let 初始映射 = &representation.初始映射;
+        let 名称 = |x: 元素| representation.数字转元素[&x].clone();
+        let 列举 = |x: &[元素]| x.iter().map(|x| 名称(*x)).collect::<Vec<_>>().join("、");
+        let lookup = |elements: &[ElementWithIndex]| -> Result<Vec<元素>, 错误> {
+            let mut result = Vec::new();
+            for ElementWithIndex { element, index } in elements {
+                let name = 数据::序列化(element, *index);
+                let number = representation
+                    .元素转数字
+                    .get(&name)
+                    .ok_or(format!("关系约束中的元素 {name} 不存在于键盘映射中"))?;
+                if !result.contains(number) {
+                    result.push(*number);
+                }
+            }
+            if result.len() < 2 {
+                return Err("关系约束至少需要包含两个不同的元素".into());
+            }
+            Ok(result)
+        };
+        let 可选键 = |约束: &编译约束, x: 元素| 约束.可选键(x, &alphabet, 初始映射);
+        let mut 同键约束 = vec![];
+        for relation in relations {
+            match relation {
+                RelationConstraint::SameKey { elements } => 同键约束.push(lookup(elements)?),
+                RelationConstraint::DifferentKey { elements } => {
+                    约束.关系约束列表.push(关系约束::异键(lookup(elements)?))
+                }
+                RelationConstraint::SameRow { elements } => {
+                    约束.关系约束列表.push(关系约束::同行(lookup(elements)?))
+                }
+                RelationConstraint::SameHand { elements, hand } => {
+                    let elements = lookup(elements)?;
+                    // 指定了手的约束等价于收窄每个元素的可选键
+                    if let Some(hand) = hand {
+                        let 右手 = *hand == Hand::Right;
+                        for element in &elements {
+                            let mut keys = 可选键(约束, *element);
+                            keys.retain(|k| 键位表.get(k).is_some_and(|x| x.右手 == 右手));
+                            if keys.is_empty() {
+                                return Err(format!("元素 {} 在指定的手上没有可选的键", 名称(*element)).into());
+                            }
+                            if !约束.fixed.contains(element) {
+                                约束.收窄(*element, keys, representation)?;
+                            }
+                        }
+                    } else {
+                        约束.关系约束列表.push(关系约束::同手(elements));
+                    }
+                }
+            }
+        }
+        // 合并有公共元素的同键约束
+        let mut 同键组列表: Vec<Vec<元素>> = vec![];
+        for mut 组 in 同键约束 {
+            let mut i = 0;
+            while i < 同键组列表.len() {
+                if 同键组列表[i].iter().any(|x| 组.contains(x)) {
+                    for x in 同键组列表.swap_remove(i) {
+                        if !组.contains(&x) {
+                            组.push(x);
+                        }
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+            同键组列表.push(组);
+        }
+        // 同键组中的元素只能在公共的可选键之间移动，若其中有固定元素，则整组固定
+        for 组 in &同键组列表 {
+            let mut 公共键 = 可选键(约束, 组[0]);
+            for element in &组[1..] {
+                let keys = 可选键(约束, *element);
+                公共键.retain(|x| keys.contains(x));
+            }
+            if 公共键.is_empty() {
+                return Err(format!("同键约束中的元素 {} 没有公共的可选键", 列举(组)).into());
+            }
+            if 组.iter().any(|x| 约束.fixed.contains(x)) {
+                约束.fixed.extend(组.iter().copied());
             } else {
-                let 概率 = 概率.iter().collect::<Vec<(&usize, &f64)>>();
-                let index = WeightedIndex::new(概率.iter().map(|(_, v)| *v));
-                match index {
-                    Ok(index) => *概率[index.sample(&mut rng)].0,
-                    Err(_) => (self.radix..self.elements).choose(&mut rng).unwrap()
+                for element in 组 {
+                    约束.收窄(*element, 公共键.clone(), representation)?;
+                }
+            }
+        }
+        // 检查其余约束的可行性
+        let 所在同键组 = |x: 元素| 同键组列表.iter().position(|组| 组.contains(&x));
+        for 关系 in &约束.关系约束列表 {
+            let elements = 关系.元素列表();
+            match 关系 {
+                关系约束::异键(_) => {
+                    let mut 组号 = HashSet::new();
+                    let mut 全部可选键 = HashSet::new();
+                    for element in elements {
+                        if let Some(i) = 所在同键组(*element) {
+                            if !组号.insert(i) {
+                                return Err(format!("元素 {} 既要求同键又要求异键", 列举(&同键组列表[i])).into());
+                            }
+                        }
+                        全部可选键.extend(可选键(约束, *element));
+                    }
+                    if 全部可选键.len() < elements.len() {
+                        return Err(format!("异键约束中的元素 {} 多于可选的键", 列举(elements)).into());
+                    }
+                }
+                关系约束::同行(_) | 关系约束::同手(_) => {
+                    let 分类 = |k: &键| 键位表.get(k).and_then(|x| 关系.键位类别(x));
+                    let mut 公共类别: Option<HashSet<usize>> = None;
+                    for element in elements {
+                        let 类别: HashSet<usize> = 可选键(约束, *element).iter().filter_map(分类).collect();
+                        公共类别 = Some(match 公共类别 {
+                            Some(x) => x.intersection(&类别).copied().collect(),
+                            None => 类别,
+                        });
+                    }
+                    if 公共类别.unwrap_or_default().is_empty() {
+                        return Err(format!("元素 {} 无法放在同一行或同一只手上", 列举(elements)).into());
+                    }
                 }
+            }
+        }
+        // 初始映射必须满足全部约束，否则优化将从不可行解开始
+        for 组 in &同键组列表 {
+            if 组.iter().any(|x| 初始映射[*x] != 初始映射[组[0]]) {
+                return Err(format!("初始映射中同键约束的元素 {} 不在同一键上", 列举(组)).into());
+            }
+        }
+        for 关系 in &约束.关系约束列表 {
+            if !关系.满足(初始映射, &键位表) {
+                return Err(format!("初始映射不满足约束 {:?}：{}", 关系, 列举(关系.元素列表())).into());
+            }
+        }
+        约束.同键组列表 = 同键组列表;
+        Ok(())
+    }
+
+    fn 生成字母表(数据: &数据) -> Vec<键> {
+        数据.配置
+            .form
+            .alphabet
+            .chars()
+            .map(|x| *数据.键转数字.get(&x).unwrap()) // 在生成表示的时候已经确保了这里一定有对应的键
+            .collect()
+    }
+
+    fn 生成键位表(数据: &数据) -> FxHashMap<键, 键位> {
+        数据.数字转键
+            .iter()
+            .filter_map(|(数字, 字符)| 查找键位(*字符).map(|x| (*数字, x)))
+            .collect()
+    }
+
+    /// 当同键组中的某个元素被移动时，把组内其他元素也移动到同一个键上
+    fn 传播同键约束(&self, keymap: &mut 元素映射, 变异元素: &mut Vec<元素>) {
+        for i in 0..变异元素.len() {
+            let Some(组号) = self.所在同键组.get(&变异元素[i]) else {
+                continue;
             };
-            if !self.fixed.contains(&key) {
-                return key;
+            let 目标 = keymap[变异元素[i]];
+            for 元素 in &self.同键组列表[*组号] {
+                if keymap[*元素] != 目标 {
+                    keymap[*元素] = 目标;
+                    变异元素.push(*元素);
+                }
             }
         }
     }
 
-    fn get_swappable_element(&self) -> usize {
+    fn 满足容量约束(&self, keymap: &元素映射, 变异元素: &[元素]) -> bool {
+        if self.容量约束列表.is_empty() {
+            return true;
+        }
+        let mut 容量计数 = self.容量计数.borrow_mut();
+        容量计数.同步(&self.容量约束列表, keymap);
+        self.容量约束列表.iter().enumerate().all(|(序号, 约束)| {
+            变异元素.iter().all(|x| {
+                let 键 = keymap[*x];
+                !约束.受约束(键) || !约束.计入(*x) || 容量计数.计数(序号, 键) <= 约束.上限
+            })
+        })
+    }
+
+    fn 满足关系约束(&self, keymap: &元素映射, 变异元素: &[元素]) -> bool {
+        变异元素
+            .iter()
+            .filter_map(|x| self.相关约束.get(x))
+            .flatten()
+            .all(|x| self.关系约束列表[*x].满足(keymap, &self.键位表))
+    }
+
+    /// 有冲突引导时按冲突权重选取，否则均匀选取
+    fn get_movable_element(&self, 引导: Option<&冲突引导>, 进度: f64) -> usize {
+        let 可移动 = |x: usize| x >= self.radix && !self.fixed.contains(&x);
+        if let Some(key) = 引导.and_then(|x| x.抽取元素(可移动, 进度)) {
+            return key;
+        }
         let mut rng = thread_rng();
         loop {
             let key = (self.radix..self.elements).choose(&mut rng).unwrap();
-            if !self.fixed.contains(&key) {
+            if 可移动(key) {
                 return key;
             }
         }
     }
 
-    fn 生成选择键概率(&self, 元素: usize, 当前键: u64, 冲突: &FxHashMap<usize, FxHashMap<usize, CircularBuffer<4, f64>>>, 元素映射: &元素映射, 进度: f64) -> Option<(Vec<(u64, f64)>, WeightedIndex<f64>)> {
-        match 冲突.get(&元素) {
-            Some(冲突) => {
-                let mut 概率 = FxHashMap::default();
-                for 键 in self.narrowed.get(&元素).unwrap_or(&self.alphabet) {
-                    概率.insert(*键, 0.0);
-                }
-                for (元素, 冲突) in 冲突.iter() {
-                    let 键 = 元素映射[*元素];
-                    if let Some(概率) = 概率.get_mut(&键) {
-                        *概率 -= 冲突[0] + 冲突.get(1).unwrap_or(&0.0);
-                    }
-                }
-                let 最小冲突 = 概率.values().fold(0.0, |a, b| f64::min(a, *b));
-                概率.values_mut().for_each(|x| *x -= 最小冲突);
-                let 概率和: f64 = 概率.values().sum();
-                let 归一化概率 = 概率.iter().map(|x| (x.0, x.1 / 概率和)).collect::<FxHashMap<_, _>>();
-                let 最大概率 = 归一化概率.values().fold(0.0, |x, y| f64::max(x, *y));
-                let mut 指数概率 = 归一化概率.into_iter().map(|x| (x.0, ((x.1 - 最大概率) / (1.0 - 进度)).exp())).collect::<FxHashMap<_, _>>();
-                指数概率.insert(&当前键, 0.0);
-                let 指数概率和: f64 = 指数概率.values().sum();
-                let 概率 = 指数概率.into_iter().map(|x| (*x.0, x.1 / 指数概率和)).collect::<Vec<(_, _)>>();
-                let index = WeightedIndex::new(概率.iter().map(|(_, v)| *v));
-                match index {
-                    Ok(index) => Some((概率, index)),
-                    Err(_) => None
-                }
-            },
-            None => None
-        }
-    }
-
-    fn 选择键(&self, 元素: usize, 概率: &Option<(Vec<(u64, f64)>, WeightedIndex<f64>)>) -> u64 {
+    fn get_swappable_element(&self) -> usize {
         let mut rng = thread_rng();
-        match 概率 {
-            Some((概率, 权重索引)) => 概率[权重索引.sample(&mut rng)].0,
-            None => *self.narrowed.get(&元素).unwrap_or(&self.alphabet).choose(&mut rng).unwrap()
+        loop {
+            let key = (self.radix..self.elements).choose(&mut rng).unwrap();
+            if !self.fixed.contains(&key) {
+                return key;
+            }
         }
     }
 
-    pub fn 有约束的随机交换(&self, keymap: &mut 元素映射) -> Vec<元素> {
+    /// 以下三种变异方式都把移动前的键记入撤销记录
+    pub fn 有约束的随机交换(
+        &self,
+        keymap: &mut 元素映射,
+        撤销记录: &mut Vec<(元素, 键)>,
+    ) -> Vec<元素> {
         let element1 = self.get_swappable_element();
         let key1 = keymap[element1];
         let mut element2 = self.get_swappable_element();
@@ -244,6 +657,7 @@ impl 默认操作 {
         let key2 = keymap[element2];
         let destinations1 = self.narrowed.get(&element1).unwrap_or(&self.alphabet);
         let destinations2 = self.narrowed.get(&element2).unwrap_or(&self.alphabet);
+        撤销记录.extend([(element1, key1), (element2, key2)]);
         //分开判断可行性。这样如果无法交换，至少移动一下。
         if destinations1.contains(&key2) {
             keymap[element1] = key2;
@@ -254,11 +668,17 @@ impl 默认操作 {
         vec![element1, element2]
     }
 
-    pub fn 有约束的整键随机交换(&self, keymap: &mut 元素映射, 概率: &FxHashMap<usize, f64>) -> Vec<元素> {
+    pub fn 有约束的整键随机交换(
+        &self,
+        keymap: &mut 元素映射,
+        引导: Option<&冲突引导>,
+        进度: f64,
+        撤销记录: &mut Vec<(元素, 键)>,
+    ) -> Vec<元素> {
         let mut rng = thread_rng();
         // 寻找一个可移动元素和一个它的可行移动位置，然后把这两个键上的所有元素交换
         // 这样交换不成也至少能移动一次
-        let movable_element = self.get_movable_element(概率);
+        let movable_element = self.get_movable_element(引导, 进度);
         let key1 = keymap[movable_element];
         let mut destinations = self
             .narrowed
@@ -266,12 +686,13 @@ impl 默认操作 {
             .unwrap_or(&self.alphabet)
             .clone();
         destinations.retain(|x| *x != key1);
-        let key2 = destinations.choose(&mut rng).unwrap(); // 在编译约束时已经确保了这里一定有可行的移动位置
+        let key2 = destinations.choose(&mut rng).unwrap(); // 只有一个可选键的元素在编译约束时已改为固定，所以这里一定有可行的移动位置
         let mut moved_elements = vec![];
         for (element, key) in keymap.iter_mut().enumerate() {
             if *key != key1 && *key != *key2 || self.fixed.contains(&element) {
                 continue;
             }
+            撤销记录.push((element, *key));
             let destination = if *key == *key2 { key1 } else { *key2 };
             // 将元素移动到目标
             let destinations2 = self.narrowed.get(&element).unwrap_or(&self.alphabet);
@@ -283,15 +704,250 @@ impl 默认操作 {
         moved_elements
     }
 
-    pub fn 有约束的随机移动(&self, keymap: &mut 元素映射, 概率: &FxHashMap<usize, f64>, 冲突: &FxHashMap<usize, FxHashMap<usize, CircularBuffer<4, f64>>>, 进度: f64) -> Vec<元素> {
-        let movable_element = self.get_movable_element(概率);
+    pub fn 有约束的随机移动(
+        &mut self,
+        keymap: &mut 元素映射,
+        引导: Option<&冲突引导>,
+        进度: f64,
+        撤销记录: &mut Vec<(元素, 键)>,
+    ) -> Vec<元素> {
+        let mut rng = thread_rng();
+        let movable_element = self.get_movable_element(引导, 进度);
         let current = keymap[movable_element];
-        let 概率 = self.生成选择键概率(movable_element, current, 冲突, keymap, 进度);
-        let mut key = self.选择键(movable_element, &概率); // 在编译约束时已经确保了这里一定有可行的移动位置
-        while key == current {
-            key = self.选择键(movable_element, &概率);
-        }
+        let destinations = self.narrowed.get(&movable_element).unwrap_or(&self.alphabet);
+        let guided = match 引导 {
+            Some(引导) => 引导.抽取键(movable_element, destinations, keymap, 进度, &mut self.键权重缓冲),
+            None => None,
+        };
+        // 只有一个可选键的元素在编译约束时已改为固定，所以这里一定有可行的移动位置
+        let key = guided.unwrap_or_else(|| loop {
+            let key = *destinations.choose(&mut rng).unwrap();
+            if key != current {
+                break key;
+            }
+        });
+        撤销记录.push((movable_element, current));
         keymap[movable_element] = key;
         vec![movable_element]
     }
 }
+
+fn 配置概率(配置: &变异配置) -> [f64; 3] {
+    let sum = 配置.random_move + 配置.random_swap + 配置.random_full_key_swap;
+    [
+        配置.random_move / sum,
+        配置.random_swap / sum,
+        配置.random_full_key_swap / sum,
+    ]
+}
+
+impl 自适应状态 {
+    fn 新建(配置: &自适应配置, 初始概率: [f64; 3]) -> Self {
+        Self {
+            窗口: 配置.window.unwrap_or(默认窗口).max(1) as f64,
+            最低概率: 配置.min_probability.unwrap_or(默认最低概率),
+            初始概率,
+            概率: 初始概率,
+            次数: [0; 3],
+            接受率: [0.0; 3],
+            改进率: [0.0; 3],
+            待反馈: vec![],
+        }
+    }
+
+    fn 记录(&mut self, 算子: usize) {
+        if self.待反馈.len() >= 最大待反馈数 {
+            self.待反馈.clear();
+        }
+        self.待反馈.push(算子);
+    }
+
+    /// 结果按顺序对应最近的若干次变异，更早的未反馈的变异被丢弃
+    fn 反馈(&mut self, 结果: &[(bool, bool)]) {
+        let 起点 = self.待反馈.len().saturating_sub(结果.len());
+        for (算子, (接受, 改进)) in self.待反馈[起点..].iter().zip(结果) {
+            let 算子 = *算子;
+            self.次数[算子] += 1;
+            let 步长 = 1.0 / (self.次数[算子] as f64).min(self.窗口);
+            self.接受率[算子] += (*接受 as u8 as f64 - self.接受率[算子]) * 步长;
+            self.改进率[算子] += (*改进 as u8 as f64 - self.改进率[算子]) * 步长;
+        }
+        self.待反馈.clear();
+        self.更新概率();
+    }
+
+    /// 初始概率为零的变异方式始终不用；每种可用的变异方式都得到过反馈之前，保持初始概率
+    fn 更新概率(&mut self) {
+        let 可用: Vec<usize> = (0..3).filter(|x| self.初始概率[*x] > 0.0).collect();
+        if 可用.iter().any(|x| self.次数[*x] == 0) {
+            return;
+        }
+        let (改进率, 接受率) = (self.改进率, self.接受率);
+        let 评分 = |x: usize| 改进率[x] + 接受权重 * 接受率[x];
+        let 总评分: f64 = 可用.iter().map(|x| 评分(*x)).sum();
+        if 总评分 <= 0.0 {
+            return;
+        }
+        let 最低概率 = self.最低概率.clamp(0.0, 1.0 / 可用.len() as f64);
+        let 剩余概率 = 1.0 - 最低概率 * 可用.len() as f64;
+        for x in 可用 {
+            self.概率[x] = 最低概率 + 剩余概率 * 评分(x) / 总评分;
+        }
+    }
+}
+
+impl 编译约束 {
+    /// 把元素的可选键收窄为给定的键；只剩一个键时元素无法移动，改为固定，此时初始映射必须已经在这个键上
+    fn 收窄(&mut self, 元素: 元素, 键列表: Vec<键>, 数据: &数据) -> Result<(), 错误> {
+        if let [键] = 键列表[..] {
+            if 数据.初始映射[元素] != 键 {
+                let 名称 = &数据.数字转元素[&元素];
+                return Err(format!("元素 {名称} 只能放在键 {} 上，但初始映射中不在这个键上", 数据.数字转键[&键]).into());
+            }
+            self.narrowed.remove(&元素);
+            self.fixed.insert(元素);
+        } else {
+            self.narrowed.insert(元素, 键列表);
+        }
+        Ok(())
+    }
+
+    fn 可选键(&self, 元素: 元素, alphabet: &[键], 初始映射: &元素映射) -> Vec<键> {
+        if self.fixed.contains(&元素) {
+            vec![初始映射[元素]]
+        } else {
+            self.narrowed.get(&元素).map_or(alphabet, |x| x).to_vec()
+        }
+    }
+}
+
+impl 关系约束 {
+    pub fn 元素列表(&self) -> &[元素] {
+        match self {
+            关系约束::异键(x) | 关系约束::同行(x) | 关系约束::同手(x) => x,
+        }
+    }
+
+    /// 同行约束按行分类，同手约束按手分类
+    fn 键位类别(&self, 键位: &键位) -> Option<usize> {
+        match self {
+            关系约束::异键(_) => None,
+            关系约束::同行(_) => Some(键位.行),
+            关系约束::同手(_) => Some(键位.右手 as usize),
+        }
+    }
+
+    pub fn 满足(&self, keymap: &元素映射, 键位表: &FxHashMap<键, 键位>) -> bool {
+        match self {
+            关系约束::异键(elements) => {
+                let mut keys = HashSet::new();
+                elements.iter().all(|x| keys.insert(keymap[*x]))
+            }
+            关系约束::同行(elements) | 关系约束::同手(elements) => {
+                let mut 类别 = elements
+                    .iter()
+                    .map(|x| 键位表.get(&keymap[*x]).and_then(|x| self.键位类别(x)));
+                let 第一个 = 类别.next().flatten();
+                第一个.is_some() && 类别.all(|x| x == 第一个)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Mapped, 配置};
+    use crate::data::原始可编码对象;
+
+    #[test]
+    fn 自适应概率偏向有改进的变异方式() {
+        let 配置 = 自适应配置 {
+            window: Some(10),
+            min_probability: Some(0.1),
+        };
+        let mut 状态 = 自适应状态::新建(&配置, [0.5, 0.5, 0.0]);
+        // 每种可用的变异方式都得到反馈之前保持初始概率
+        状态.记录(0);
+        状态.反馈(&[(true, true)]);
+        assert_eq!(状态.概率, [0.5, 0.5, 0.0]);
+        for _ in 0..20 {
+            状态.记录(0);
+            状态.记录(1);
+            状态.反馈(&[(true, true), (false, false)]);
+        }
+        assert!((状态.概率[0] - 0.9).abs() < 1e-9);
+        assert!((状态.概率[1] - 0.1).abs() < 1e-9);
+        assert_eq!(状态.概率[2], 0.0);
+        // 没有反馈的变异被丢弃，只有最后一次对应结果
+        状态.记录(1);
+        状态.记录(0);
+        状态.反馈(&[(false, false)]);
+        assert_eq!(状态.次数, [22, 20, 0]);
+    }
+
+    #[test]
+    fn 公共键只有一个时整组固定() {
+        let mut 配置 = 配置::default();
+        配置.form.alphabet = "abc".to_string();
+        for (元素, 键) in [("甲", "a"), ("乙", "a"), ("丙", "b"), ("丁", "c"), ("戊", "a")] {
+            配置
+                .form
+                .mapping
+                .insert(元素.to_string(), Mapped::Basic(键.to_string()));
+        }
+        配置.encoder.max_length = 2;
+        配置.optimization = Some(
+            serde_yaml::from_str(
+                "objective: {characters_full: {duplication: 1.0}}
+constraints:
+  elements: [{element: 甲, keys: [a, b]}, {element: 乙, keys: [a, c]}, {element: 丁, keys: [c]}]
+  relations: [{type: same_key, elements: [{element: 甲, index: 0}, {element: 乙, index: 0}]}]",
+            )
+            .unwrap(),
+        );
+        let 词列表 = ["甲", "乙", "丙", "丁", "戊"]
+            .map(|x| 原始可编码对象 {
+                name: x.to_string(),
+                sequence: x.to_string(),
+                frequency: 1,
+                level: u64::MAX,
+            })
+            .to_vec();
+        let 数据 = 数据::新建(配置, 词列表, HashMap::new(), HashMap::new()).unwrap();
+        let mut 操作 = 默认操作::新建(&数据).unwrap();
+        let 元素 = |x: &str| 数据.元素转数字[x];
+        // 甲、乙的公共键只有 a，丁只能放在 c 上，都改为固定
+        for x in ["甲", "乙", "丁"] {
+            assert!(操作.fixed.contains(&元素(x)));
+            assert!(!操作.narrowed.contains_key(&元素(x)));
+        }
+        // 只剩丙、戊可以移动，各种变异都不会卡住或者移动固定的元素
+        for 算子 in [0, 1, 2].iter().cycle().take(300) {
+            let mut 映射 = 数据.初始映射.to_vec();
+            操作.基本变异(*算子, &mut 映射, None, 0.0, &mut vec![]);
+            for x in ["甲", "乙", "丁"] {
+                assert_eq!(映射[元素(x)], 数据.初始映射[元素(x)]);
+            }
+        }
+    }
+
+    #[test]
+    fn 唯一可选键与初始映射不符时报错() {
+        let mut 配置 = 配置::default();
+        配置.form.alphabet = "ab".to_string();
+        配置
+            .form
+            .mapping
+            .insert("甲".to_string(), Mapped::Basic("a".to_string()));
+        配置.optimization = Some(
+            serde_yaml::from_str(
+                "objective: {characters_full: {duplication: 1.0}}
+constraints: {elements: [{element: 甲, keys: [b]}]}",
+            )
+            .unwrap(),
+        );
+        let 数据 = 数据::新建(配置, vec![], HashMap::new(), HashMap::new()).unwrap();
+        assert!(默认操作::新建(&数据).is_err());
+    }
+}
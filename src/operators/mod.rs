@@ -1,19 +1,46 @@
 //! 算子接口，以及默认操作的实现（包含变异算子）
 //!
 
-use circular_buffer::CircularBuffer;
-use rustc_hash::FxHashMap;
-
-use crate::data::{元素, 元素映射};
+use crate::data::{元素, 元素映射, 键};
+use crate::objectives::conflict::冲突引导;
+use default::变异配置;
 
 pub mod default;
 
 pub trait 变异 {
     /// 基于现有的一个解通过随机扰动创建一个新的解，返回变异的元素
-    fn 变异(&mut self, 映射: &mut 元素映射, 概率: &FxHashMap<usize, f64>, 冲突: &FxHashMap<usize, FxHashMap<usize, CircularBuffer<4, f64>>>, 进度: f64) -> Vec<元素>;
+    ///
+    /// 找不到满足约束的变异时返回空，映射保持不变；调用方应当跳过这一步，既不计算也不反馈
+    ///
+    /// 目标函数开启了冲突引导时会传入冲突信息，算子可以据此优先移动造成重码的元素，也可以忽略它
+    fn 变异(&mut self, 映射: &mut 元素映射, 引导: Option<&冲突引导>, 进度: f64) -> Vec<元素>;
+
+    /// 报告最近若干次变异得到的解是否被接受、是否优于当前解，按变异的先后顺序排列
+    ///
+    /// 算子可以据此调整各种变异方式的概率，也可以忽略它
+    fn 反馈(&mut self, _结果: &[(bool, bool)]) {}
+
+    /// 目前各种变异方式的概率，用于报告进度；不调整概率的算子返回空
+    fn 变异概率(&self) -> Option<变异配置> {
+        None
+    }
 }
 
 pub trait 杂交 {
     /// 基于现有的一个解通过随机扰动创建一个新的解
     fn 杂交(&mut self, 映射一: &元素映射, 映射二: &元素映射) -> 元素映射;
 }
+
+pub trait 重新放置 {
+    /// 元素可以放置的键，固定的元素返回空
+    fn 可选键(&self, 元素: 元素) -> &[键];
+
+    /// 与元素同键、总是随它一起移动的元素（包括它自己）；不受同键约束的元素返回空
+    fn 同键元素(&self, 元素: 元素) -> &[元素];
+
+    /// 把元素放到给定的键上，受约束牵连的元素一并移动，返回所有移动的元素
+    fn 放置(&self, 映射: &mut 元素映射, 元素: 元素, 键: 键) -> Vec<元素>;
+
+    /// 移动了这些元素之后，映射是否仍然满足全部约束
+    fn 满足约束(&self, 映射: &元素映射, 移动的元素: &[元素]) -> bool;
+}
@@ -16,7 +16,7 @@ pub struct 遗传算法 {
 }
 
 impl<F: 变异 + 杂交> 优化方法<F> for 遗传算法 {
-    fn 优化<E: 编码器, O: 目标函数>(
+    fn 优化<E: 编码器 + Send, O: 目标函数 + Send>(
         &self,
         _问题: &mut 优化问题<E, O, F>,
         _界面: &dyn 界面,
@@ -0,0 +1,107 @@
+//! 大洪水法
+//!
+//! 新解的分数只要不高于当前的水位，或者不差于当前解，就被接受。水位从初始解的分数加上一个余量开始，随进度线性降到目前找到的最优分数，所以搜索后期只接受不变差的解。余量不提供时，以初始解附近随机变异的平均分数变化作为余量。
+
+use super::{
+    local_search::{局部搜索, 局部搜索参数, 平均分数变化, 接受准则},
+    优化方法, 优化结果, 优化问题,
+};
+use crate::{
+    encoders::编码器,
+    objectives::目标函数,
+    operators::{default::变异配置, 变异},
+    界面,
+};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::num::NonZeroUsize;
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct 大洪水方法 {
+    /// 初始水位比初始解的分数高出多少
+    pub margin: Option<f64>,
+    pub steps: usize,
+    pub report_after: Option<f64>,
+    pub search_method: Option<变异配置>,
+    pub update_interval: Option<usize>,
+    pub verify_interval: Option<NonZeroUsize>,
+    /// 优化的时间限制，单位为秒；设置后按测得的速度换算出总步数，steps 不再起作用
+    pub time_limit: Option<f64>,
+}
+
+struct 大洪水 {
+    余量: f64,
+    初始水位: Option<f64>,
+    最优分数: f64,
+}
+
+impl 大洪水 {
+    fn 水位(&self, 进度: f64) -> Option<f64> {
+        self.初始水位
+            .map(|x| x - (x - self.最优分数) * 进度)
+    }
+}
+
+impl 接受准则 for 大洪水 {
+    fn 接受(&mut self, 当前分数: f64, 新分数: f64, 进度: f64) -> bool {
+        // 第一步时的当前分数就是初始解的分数
+        if self.初始水位.is_none() {
+            self.初始水位 = Some(当前分数 + self.余量);
+            self.最优分数 = 当前分数;
+        }
+        let 接受 = 新分数 <= 当前分数 || self.水位(进度).is_some_and(|x| 新分数 <= x);
+        if 接受 {
+            self.最优分数 = self.最优分数.min(新分数);
+        }
+        接受
+    }
+
+    fn 门槛(&self, 当前分数: f64, 进度: f64) -> f64 {
+        self.水位(进度).map_or(0.0, |x| (x - 当前分数).max(0.0))
+    }
+}
+
+impl<F: 变异> 优化方法<F> for 大洪水方法 {
+    fn 优化<E: 编码器 + Send, O: 目标函数 + Send>(
+        &self,
+        问题: &mut 优化问题<E, O, F>,
+        界面: &dyn 界面,
+    ) -> 优化结果<O> {
+        let mut 准则 = 大洪水 {
+            余量: self.margin.unwrap_or_else(|| 平均分数变化(问题)),
+            初始水位: None,
+            最优分数: f64::INFINITY,
+        };
+        let 参数 = 局部搜索参数 {
+            steps: self.steps,
+            time_limit: self.time_limit,
+            report_after: self.report_after,
+            update_interval: self.update_interval,
+            verify_interval: self.verify_interval,
+        };
+        局部搜索(问题, &参数, &mut 准则, 界面)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn 水位降到最优分数() {
+        let mut 准则 = 大洪水 {
+            余量: 1.0,
+            初始水位: None,
+            最优分数: f64::INFINITY,
+        };
+        assert!(准则.接受(10.0, 10.8, 0.0));
+        assert!(准则.接受(10.8, 9.0, 0.5));
+        // 水位为 11 - (11 - 9) × 0.5 = 10
+        assert!(!准则.接受(9.0, 10.1, 0.5));
+        assert!(准则.接受(9.0, 9.9, 0.5));
+        assert!(!准则.接受(9.9, 9.95, 1.0));
+        assert!((准则.门槛(9.5, 0.75) - 0.0).abs() < 1e-12);
+        assert!((准则.门槛(9.0, 0.75) - 0.5).abs() < 1e-12);
+    }
+}
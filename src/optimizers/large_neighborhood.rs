@@ -0,0 +1,402 @@
+//! 大邻域搜索
+//!
+//! 每次迭代选出一小组元素，在它们各自可以放置的键上穷举所有放法，取其中最好的一种；只有比当前解更好时才替换当前解。子集有两种选法：按冲突引导选出一个冲突较多的元素以及与它冲突最多的几个元素，或者选出两个键上的元素，只在这两个键之间重新分配。
+//!
+//! 穷举按混合进制的格雷码顺序进行，相邻两种放法只差一个元素的位置，因此每种放法都只需要一次增量计算。放法总数超过上限时，随机去掉一部分候选键。同键的元素总是一起移动，所以一个子集中每组同键元素至多选一个。
+
+use super::{
+    local_search::{搜索设置, 搜索过程},
+    优化方法, 优化结果, 优化问题,
+};
+use crate::{
+    data::{元素, 元素映射, 键},
+    encoders::编码器,
+    objectives::{conflict::抽取温度, 目标函数},
+    operators::重新放置,
+    界面,
+};
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::{random, thread_rng};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::iter::once;
+use std::num::NonZeroUsize;
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct 大邻域搜索方法 {
+    /// 迭代次数，每次迭代重新放置一组元素
+    pub steps: usize,
+    /// 按冲突选取时每组的元素个数，默认为 3
+    pub subset_size: Option<usize>,
+    /// 每次迭代最多计算的放法数目，默认为 2000
+    pub max_evaluations: Option<usize>,
+    /// 按两个键选取子集的比例，其余按冲突选取，默认为 0.5
+    pub two_keys_ratio: Option<f64>,
+    /// 按冲突选取子集时是否使用冲突引导，默认开启；关闭时随机选取元素
+    pub conflict_guidance: Option<bool>,
+    pub report_after: Option<f64>,
+    pub update_interval: Option<usize>,
+    pub verify_interval: Option<NonZeroUsize>,
+    /// 优化的时间限制，单位为秒；设置后按测得的速度换算出迭代次数，steps 不再起作用
+    pub time_limit: Option<f64>,
+}
+
+/// 子集中的一个元素，以及它的候选键；第一个候选键是元素当前所在的键
+type 待放置元素 = (元素, Vec<键>);
+
+impl<F: 重新放置> 优化方法<F> for 大邻域搜索方法 {
+    fn 优化<E: 编码器 + Send, O: 目标函数 + Send>(
+        &self,
+        问题: &mut 优化问题<E, O, F>,
+        界面: &dyn 界面,
+    ) -> 优化结果<O> {
+        let 设置 = 搜索设置 {
+            steps: self.steps,
+            time_limit: self.time_limit,
+            report_after: self.report_after,
+            update_interval: self.update_interval.unwrap_or(10),
+            verify_interval: self.verify_interval,
+        };
+        let mut 过程 = 搜索过程::新建(问题, 设置, 界面);
+        let 可移动元素: Vec<元素> = (问题.数据.进制 as usize..过程.当前解.len())
+            .filter(|x| !问题.操作.可选键(*x).is_empty())
+            .collect();
+
+        for step in 0.. {
+            if 可移动元素.is_empty() {
+                break;
+            }
+            let Some(progress) = 过程.进度(step, 界面) else {
+                break;
+            };
+            // 没有退火温度，报告冲突引导抽取元素时的温度
+            let 温度 = if 问题.目标函数.冲突引导().is_some() {
+                抽取温度(progress)
+            } else {
+                0.0
+            };
+            过程.报告(step, 温度, 0, None, 界面);
+            let 子集 = if random::<f64>() < self.two_keys_ratio.unwrap_or(0.5) {
+                self.两键子集(问题, &过程.当前解, &可移动元素)
+            } else {
+                self.冲突子集(问题, &过程.当前解, &可移动元素, progress)
+            };
+            // 穷举子集的所有放法，编码器中始终是上一次计算的映射，未计算的移动累积到下一次计算
+            let mut 映射 = 过程.当前解.clone();
+            let mut 涉及元素 = vec![];
+            let mut 待计算 = vec![];
+            let mut 最优放法 = None;
+            let mut 最优分数 = 过程.当前结果.1;
+            let mut 位置 = vec![0; 子集.len()];
+            let mut 方向 = vec![true; 子集.len()];
+            let 长度: Vec<_> = 子集.iter().map(|(_, x)| x.len()).collect();
+            while let Some(序号) = 下一个放法(&mut 位置, &mut 方向, &长度) {
+                if 过程.已超时() || 界面.已中断() {
+                    break;
+                }
+                let (元素, 候选键) = &子集[序号];
+                let 移动的元素 = 问题.操作.放置(&mut 映射, *元素, 候选键[位置[序号]]);
+                for x in 移动的元素 {
+                    if !涉及元素.contains(&x) {
+                        涉及元素.push(x);
+                    }
+                    待计算.push(x);
+                }
+                if !问题.操作.满足约束(&映射, &涉及元素) {
+                    continue;
+                }
+                let rank = 问题.计算(&映射, &Some(std::mem::take(&mut 待计算)), progress);
+                过程.计算次数 += 1;
+                if rank.1 < 最优分数 {
+                    最优分数 = rank.1;
+                    最优放法 = Some(映射.clone());
+                }
+            }
+            // 让编码器回到当前解，这样冲突引导和下一次迭代的增量计算都以当前解为准
+            let 改进 = 最优放法.is_some();
+            let 当前解 = 最优放法.unwrap_or_else(|| 过程.当前解.clone());
+            let 结果 = 问题.计算(&当前解, &Some(涉及元素), progress);
+            过程.计算次数 += 1;
+            过程.校验(问题, step, &当前解, &结果, progress);
+            if 改进 {
+                过程.接受(&当前解, 结果);
+            } else {
+                过程.当前结果 = 结果;
+            }
+            过程.更新最优(问题, progress, 界面);
+        }
+        过程.结束(问题, 界面)
+    }
+}
+
+impl 大邻域搜索方法 {
+    /// 选出一个冲突较多的元素以及与它冲突最多的几个元素，不足时随机补齐；未开启冲突引导时全部随机选取
+    fn 冲突子集<E: 编码器, O: 目标函数, F: 重新放置>(
+        &self,
+        问题: &优化问题<E, O, F>,
+        映射: &元素映射,
+        可移动元素: &[元素],
+        进度: f64,
+    ) -> Vec<待放置元素> {
+        let mut rng = thread_rng();
+        let 数量 = self.subset_size.unwrap_or(3).clamp(1, 可移动元素.len());
+        let 可移动 = |x: 元素| 可移动元素.binary_search(&x).is_ok();
+        let mut 元素列表 = vec![];
+        if let Some(引导) = 问题.目标函数.冲突引导() {
+            if let Some(元素) = 引导.抽取元素(可移动, 进度) {
+                let 冲突元素 = 引导.冲突最多的元素(元素, 数量 - 1, 可移动);
+                加入子集(&问题.操作, &mut 元素列表, once(元素).chain(冲突元素), 数量);
+            }
+        }
+        let mut 补充 = 可移动元素.to_vec();
+        补充.shuffle(&mut rng);
+        加入子集(&问题.操作, &mut 元素列表, 补充, 数量);
+        let 子集 = 元素列表
+            .into_iter()
+            .map(|x| {
+                let mut 候选键 = vec![映射[x]];
+                候选键.extend(问题.操作.可选键(x).iter().filter(|k| **k != 映射[x]));
+                (x, 候选键)
+            })
+            .collect();
+        self.限制放法数目(子集)
+    }
+
+    /// 随机选出一个元素所在的键和它可以移动到的另一个键，取这两个键上的元素在两键之间重新分配；放法太多时随机固定一部分元素
+    fn 两键子集<E: 编码器, O: 目标函数, F: 重新放置>(
+        &self,
+        问题: &优化问题<E, O, F>,
+        映射: &元素映射,
+        可移动元素: &[元素],
+    ) -> Vec<待放置元素> {
+        let mut rng = thread_rng();
+        let 元素 = *可移动元素.choose(&mut rng).unwrap();
+        let 键一 = 映射[元素];
+        let Some(键二) = 问题
+            .操作
+            .可选键(元素)
+            .iter()
+            .filter(|x| **x != 键一)
+            .choose(&mut rng)
+            .copied()
+        else {
+            return vec![];
+        };
+        let mut 其他元素: Vec<_> = 可移动元素
+            .iter()
+            .filter(|x| **x != 元素 && (映射[**x] == 键一 || 映射[**x] == 键二))
+            .copied()
+            .collect();
+        其他元素.shuffle(&mut rng);
+        let mut 元素列表 = vec![];
+        加入子集(&问题.操作, &mut 元素列表, once(元素).chain(其他元素), usize::MAX);
+        let 子集 = 元素列表
+            .into_iter()
+            .map(|x| {
+                let 当前键 = 映射[x];
+                let 另一个键 = if 当前键 == 键一 { 键二 } else { 键一 };
+                let mut 候选键 = vec![当前键];
+                if 问题.操作.可选键(x).contains(&另一个键) {
+                    候选键.push(另一个键);
+                }
+                (x, 候选键)
+            })
+            .collect();
+        self.限制放法数目(子集)
+    }
+
+    /// 放法总数超过上限时，反复从候选键最多的元素中随机去掉一个键，但保留元素当前所在的键；候选键相同多时先去掉靠后的元素的键，最后去掉只剩当前键的元素
+    fn 限制放法数目(&self, mut 子集: Vec<待放置元素>) -> Vec<待放置元素> {
+        let mut rng = thread_rng();
+        let 上限 = self.max_evaluations.unwrap_or(2000).max(1) as f64;
+        while 子集.iter().map(|(_, x)| x.len() as f64).product::<f64>() > 上限 {
+            let (_, 候选键) = 子集.iter_mut().max_by_key(|(_, x)| x.len()).unwrap();
+            if 候选键.len() <= 1 {
+                break;
+            }
+            let 序号 = (1..候选键.len()).choose(&mut rng).unwrap();
+            候选键.swap_remove(序号);
+        }
+        子集.retain(|(_, x)| x.len() > 1);
+        子集
+    }
+}
+
+/// 按顺序把候选元素加入子集，直到子集有给定的数目；跳过已经在子集中的元素，以及与子集中的元素同键的元素
+fn 加入子集<F: 重新放置>(
+    操作: &F,
+    子集: &mut Vec<元素>,
+    候选: impl IntoIterator<Item = 元素>,
+    数目: usize,
+) {
+    for 元素 in 候选 {
+        if 子集.len() >= 数目 {
+            break;
+        }
+        let 同键 = 操作.同键元素(元素);
+        if 子集.contains(&元素) || 同键.iter().any(|x| 子集.contains(x)) {
+            continue;
+        }
+        子集.push(元素);
+    }
+}
+
+/// 按混合进制的反射格雷码前进到下一种放法，每次只有一位改变一格，返回改变的位；已经遍历完所有放法时返回空
+fn 下一个放法(位置: &mut [usize], 方向: &mut [bool], 长度: &[usize]) -> Option<usize> {
+    for 序号 in 0..位置.len() {
+        if 方向[序号] && 位置[序号] + 1 < 长度[序号] {
+            位置[序号] += 1;
+            return Some(序号);
+        }
+        if !方向[序号] && 位置[序号] > 0 {
+            位置[序号] -= 1;
+            return Some(序号);
+        }
+        方向[序号] = !方向[序号];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Mapped, SolverConfig, 配置};
+    use crate::data::{原始可编码对象, 数据};
+    use crate::encoders::新建编码器;
+    use crate::objectives::default::默认目标函数;
+    use crate::operators::default::默认操作;
+    use crate::消息;
+    use std::collections::{HashMap, HashSet};
+
+    struct 静默;
+
+    impl 界面 for 静默 {
+        fn 发送(&self, _: 消息) {}
+    }
+
+    #[test]
+    fn 格雷码遍历所有放法() {
+        let 长度 = vec![3, 1, 2, 4];
+        let mut 位置 = vec![0; 4];
+        let mut 方向 = vec![true; 4];
+        let mut 已访问 = HashSet::new();
+        已访问.insert(位置.clone());
+        while let Some(序号) = 下一个放法(&mut 位置, &mut 方向, &长度) {
+            assert!(位置[序号] < 长度[序号]);
+            assert!(已访问.insert(位置.clone()));
+        }
+        assert_eq!(已访问.len(), 24);
+    }
+
+    struct 同键组列表(Vec<Vec<元素>>);
+
+    impl 重新放置 for 同键组列表 {
+        fn 可选键(&self, _: 元素) -> &[键] {
+            &[]
+        }
+
+        fn 同键元素(&self, 元素: 元素) -> &[元素] {
+            self.0.iter().find(|x| x.contains(&元素)).map_or(&[], |x| x)
+        }
+
+        fn 放置(&self, 映射: &mut 元素映射, 元素: 元素, 键: 键) -> Vec<元素> {
+            let 同键元素 = self.同键元素(元素);
+            let 移动的元素 = if 同键元素.is_empty() { vec![元素] } else { 同键元素.to_vec() };
+            for x in &移动的元素 {
+                映射[*x] = 键;
+            }
+            移动的元素
+        }
+
+        fn 满足约束(&self, _: &元素映射, _: &[元素]) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn 子集中没有同键元素() {
+        let 操作 = 同键组列表(vec![vec![1, 2], vec![3, 4]]);
+        let mut 子集 = vec![];
+        加入子集(&操作, &mut 子集, [2, 1, 2, 4, 3], 3);
+        assert_eq!(子集, vec![2, 4]);
+        加入子集(&操作, &mut 子集, [5, 6], 3);
+        assert_eq!(子集, vec![2, 4, 5]);
+    }
+
+    #[test]
+    fn 不劣于初始解并找到小问题的最优解() {
+        let mut 配置 = 配置::default();
+        配置.form.alphabet = "abc".to_string();
+        let 元素列表 = ["甲", "乙", "丙", "丁", "戊", "己"];
+        for 元素 in 元素列表 {
+            配置
+                .form
+                .mapping
+                .insert(元素.to_string(), Mapped::Basic("a".to_string()));
+        }
+        配置.encoder.max_length = 2;
+        配置.optimization = Some(
+            serde_yaml::from_str(
+                "objective: {characters_full: {duplication: 1.0}, words_full: {duplication: 1.0}}
+constraints: {relations: [{type: same_key, elements: [{element: 甲, index: 0}, {element: 乙, index: 0}]}]}
+metaheuristic: {algorithm: LargeNeighborhood, steps: 300, verify_interval: 1}",
+            )
+            .unwrap(),
+        );
+        let mut 词列表 = vec![];
+        for (序号, 甲) in 元素列表.iter().enumerate() {
+            词列表.push((甲.to_string(), 甲.to_string(), 序号 as u64 + 1));
+            for 乙 in &元素列表[序号..] {
+                词列表.push((format!("{}{}", 甲, 乙), format!("{} {}", 甲, 乙), 序号 as u64 + 2));
+            }
+        }
+        let 词列表 = 词列表
+            .into_iter()
+            .map(|(name, sequence, frequency)| 原始可编码对象 {
+                name,
+                sequence,
+                frequency,
+                level: u64::MAX,
+            })
+            .collect();
+        let 数据 = 数据::新建(配置, 词列表, HashMap::new(), HashMap::new()).unwrap();
+        let 计算 = |映射: &元素映射| {
+            let mut 编码器 = 新建编码器(&数据).unwrap();
+            let mut 目标函数 = 默认目标函数::新建(&数据).unwrap();
+            目标函数.计算(编码器.编码(映射, &None), 映射, 0.0).1
+        };
+        // 穷举满足同键约束的所有映射
+        let 元素序号: Vec<_> = 元素列表.iter().map(|x| 数据.元素转数字[*x]).collect();
+        let 键列表: Vec<_> = "abc".chars().map(|x| 数据.键转数字[&x]).collect();
+        let mut 最优分数 = f64::INFINITY;
+        for 编号 in 0..键列表.len().pow(元素序号.len() as u32) {
+            let mut 映射 = 数据.初始映射.to_vec();
+            let mut 余数 = 编号;
+            for 元素 in &元素序号 {
+                映射[*元素] = 键列表[余数 % 键列表.len()];
+                余数 /= 键列表.len();
+            }
+            if 映射[元素序号[0]] == 映射[元素序号[1]] {
+                最优分数 = 最优分数.min(计算(&映射));
+            }
+        }
+        let 初始分数 = 计算(&数据.初始映射);
+        let Some(SolverConfig::LargeNeighborhood(方法)) =
+            数据.配置.optimization.as_ref().unwrap().metaheuristic.clone()
+        else {
+            unreachable!()
+        };
+        let 编码器 = 新建编码器(&数据).unwrap();
+        let 目标函数 = 默认目标函数::新建(&数据).unwrap();
+        let 操作 = 默认操作::新建(&数据).unwrap();
+        let mut 问题 = 优化问题::新建(数据, 编码器, 目标函数, 操作).开启校验(Box::new(|数据| {
+            Ok((新建编码器(数据)?, 默认目标函数::新建(数据)?))
+        }));
+        let 结果 = 方法.优化(&mut 问题, &静默);
+        assert!(结果.校验错误.is_none());
+        assert!(结果.分数 <= 初始分数);
+        assert_eq!(结果.映射[元素序号[0]], 结果.映射[元素序号[1]]);
+        assert!((结果.分数 - 最优分数).abs() < 1e-9, "{} {}", 结果.分数, 最优分数);
+    }
+}
@@ -0,0 +1,99 @@
+//! 延迟接受爬山法
+//!
+//! 记录最近 L 步当前解的分数，新解只要不差于 L 步之前的当前解，或者不差于现在的当前解，就被接受。只需要一个历史长度参数，历史越长，搜索越接近随机游走，收敛越慢但越不容易陷入局部最优。
+
+use super::{
+    local_search::{局部搜索, 局部搜索参数, 接受准则},
+    优化方法, 优化结果, 优化问题,
+};
+use crate::{
+    encoders::编码器,
+    objectives::目标函数,
+    operators::{default::变异配置, 变异},
+    界面,
+};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::num::NonZeroUsize;
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct 延迟接受方法 {
+    pub history_length: usize,
+    pub steps: usize,
+    pub report_after: Option<f64>,
+    pub search_method: Option<变异配置>,
+    pub update_interval: Option<usize>,
+    pub verify_interval: Option<NonZeroUsize>,
+    /// 优化的时间限制，单位为秒；设置后按测得的速度换算出总步数，steps 不再起作用
+    pub time_limit: Option<f64>,
+}
+
+struct 延迟接受 {
+    历史: Vec<f64>,
+    步数: usize,
+}
+
+impl 接受准则 for 延迟接受 {
+    fn 接受(&mut self, 当前分数: f64, 新分数: f64, _进度: f64) -> bool {
+        if self.历史.is_empty() {
+            return 新分数 <= 当前分数;
+        }
+        // 历史在第一步时全部填为初始解的分数
+        if self.步数 == 0 {
+            self.历史.fill(当前分数);
+        }
+        let 位置 = self.步数 % self.历史.len();
+        let 接受 = 新分数 <= self.历史[位置] || 新分数 <= 当前分数;
+        self.历史[位置] = if 接受 { 新分数 } else { 当前分数 };
+        self.步数 += 1;
+        接受
+    }
+
+    fn 门槛(&self, 当前分数: f64, _进度: f64) -> f64 {
+        if self.步数 == 0 || self.历史.is_empty() {
+            return 0.0;
+        }
+        let 位置 = self.步数 % self.历史.len();
+        (self.历史[位置] - 当前分数).max(0.0)
+    }
+}
+
+impl<F: 变异> 优化方法<F> for 延迟接受方法 {
+    fn 优化<E: 编码器 + Send, O: 目标函数 + Send>(
+        &self,
+        问题: &mut 优化问题<E, O, F>,
+        界面: &dyn 界面,
+    ) -> 优化结果<O> {
+        let 参数 = 局部搜索参数 {
+            steps: self.steps,
+            time_limit: self.time_limit,
+            report_after: self.report_after,
+            update_interval: self.update_interval,
+            verify_interval: self.verify_interval,
+        };
+        let mut 准则 = 延迟接受 {
+            历史: vec![0.0; self.history_length],
+            步数: 0,
+        };
+        局部搜索(问题, &参数, &mut 准则, 界面)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn 接受不差于历史的解() {
+        let mut 准则 = 延迟接受 {
+            历史: vec![0.0; 2],
+            步数: 0,
+        };
+        assert!(!准则.接受(10.0, 11.0, 0.0));
+        assert!(准则.接受(10.0, 9.0, 0.0));
+        // 两步之前的当前分数是 10，比现在的 9 差，所以可以接受 9.5
+        assert!(准则.接受(9.0, 9.5, 0.0));
+        assert!(!准则.接受(9.5, 9.8, 0.0));
+    }
+}
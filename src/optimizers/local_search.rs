@@ -0,0 +1,283 @@
+//! 单链搜索的公共流程
+//!
+//! 每一步变异当前解、增量计算新解的指标，再由接受准则决定是否接受。延迟接受爬山法、门槛接受法和大洪水法只在接受准则上有所不同，它们共用这里的主循环。退火算法和大邻域搜索有自己的主循环，但与它们共用搜索过程中的进度、报告、校验和保存。
+
+use super::{优化结果, 优化问题};
+use crate::{
+    data::元素映射, encoders::编码器, objectives::目标函数, operators::default::变异配置,
+    operators::变异, 消息, 界面, 错误,
+};
+use std::num::NonZeroUsize;
+use std::time::Duration;
+use web_time::Instant;
+
+/// 局部搜索的接受准则，分数越小越好
+pub trait 接受准则 {
+    /// 在给定进度下是否用新解替换当前解
+    fn 接受(&mut self, 当前分数: f64, 新分数: f64, 进度: f64) -> bool;
+
+    /// 报告进度时显示的门槛，即此时新解最多可以比当前解差多少
+    fn 门槛(&self, 当前分数: f64, 进度: f64) -> f64;
+}
+
+pub struct 局部搜索参数 {
+    pub steps: usize,
+    /// 时间限制，单位为秒；提供时按测得的速度换算出总步数，steps 不再起作用
+    pub time_limit: Option<f64>,
+    pub report_after: Option<f64>,
+    pub update_interval: Option<usize>,
+    pub verify_interval: Option<NonZeroUsize>,
+}
+
+/// 搜索过程的设置
+pub struct 搜索设置 {
+    /// 总步数，进度为已执行的步数占总步数的比例
+    pub steps: usize,
+    /// 时间限制，单位为秒，从搜索开始时计时
+    ///
+    /// 提供时先按用时计算进度预热一段时间，再按预热期间的速度把剩余时间换算为总步数，steps 不再起作用；到达时间限制时无论是否走完都会结束
+    pub time_limit: Option<f64>,
+    pub report_after: Option<f64>,
+    pub update_interval: usize,
+    pub verify_interval: Option<NonZeroUsize>,
+}
+
+/// 单链搜索中与接受准则无关的部分：计算进度、定期报告状态、校验增量计算的结果、记录最优解，并在结束时保存最优解
+pub struct 搜索过程<O: 目标函数> {
+    pub 当前解: 元素映射,
+    pub 当前结果: (O::目标值, f64),
+    pub 最优解: 元素映射,
+    pub 最优结果: (O::目标值, f64),
+    /// 计算过的候选解数目，用于估计每次计算的用时
+    pub 计算次数: usize,
+    设置: 搜索设置,
+    开始时刻: Instant,
+    截止时刻: Option<Instant>,
+    /// 总步数，按时间限制搜索时在预热结束后才确定
+    总步数: Option<usize>,
+    上次报告: usize,
+    接受次数: usize,
+    校验错误: Option<错误>,
+}
+
+impl<O: 目标函数> 搜索过程<O> {
+    /// 从问题的初始映射出发，先从头计算一次
+    pub fn 新建<E: 编码器, F>(
+        问题: &mut 优化问题<E, O, F>,
+        设置: 搜索设置,
+        界面: &dyn 界面,
+    ) -> Self {
+        let 开始时刻 = Instant::now();
+        let 截止时刻 = 设置
+            .time_limit
+            .map(|x| 开始时刻 + Duration::from_secs_f64(x.max(0.0)));
+        let 总步数 = 截止时刻.is_none().then_some(设置.steps);
+        let 当前解 = 问题.数据.初始映射.to_vec();
+        let 当前结果 = 问题.计算(&当前解, &None, 0.0);
+        界面.发送(消息::MemoryEstimate {
+            bytes: 问题.编码器.估计内存(),
+        });
+        Self {
+            最优解: 当前解.clone(),
+            最优结果: 当前结果.clone(),
+            当前解,
+            当前结果,
+            计算次数: 0,
+            设置,
+            开始时刻,
+            截止时刻,
+            总步数,
+            上次报告: 0,
+            接受次数: 0,
+            校验错误: None,
+        }
+    }
+
+    /// 第 step 步的进度；已经用完步数或时间、校验失败或者被中断时返回空
+    pub fn 进度(&mut self, step: usize, 界面: &dyn 界面) -> Option<f64> {
+        // 按时间限制搜索时，用时达到这个比例后预热结束
+        const 预热比例: f64 = 0.05;
+        if self.校验错误.is_some() || 界面.已中断() || self.已超时() {
+            return None;
+        }
+        if let (None, Some(截止时刻)) = (self.总步数, self.截止时刻) {
+            let 总时间 = 截止时刻.duration_since(self.开始时刻).as_secs_f64();
+            let 时间进度 = self.开始时刻.elapsed().as_secs_f64() / 总时间;
+            if step == 0 || 时间进度 < 预热比例 {
+                return Some(时间进度);
+            }
+            // 换算出的总步数使进度在预热结束时保持连续
+            self.总步数 = Some((step as f64 / 时间进度) as usize);
+        }
+        let 总步数 = self.总步数?;
+        (step < 总步数).then(|| step as f64 / 总步数 as f64)
+    }
+
+    /// 是否已经到达时间限制
+    pub fn 已超时(&self) -> bool {
+        self.截止时刻.is_some_and(|x| Instant::now() >= x)
+    }
+
+    /// 每过一定的步数，报告当前状态和计算速度
+    pub fn 报告(
+        &mut self,
+        step: usize,
+        温度: f64,
+        回火次数: usize,
+        变异概率: Option<变异配置>,
+        界面: &dyn 界面,
+    ) {
+        let 间隔 = self.设置.update_interval;
+        let 最后一步 = self.总步数.is_some_and(|x| step + 1 == x);
+        if !step.is_multiple_of(间隔) && !最后一步 {
+            return;
+        }
+        界面.发送(消息::Progress {
+            steps: step,
+            temperature: 温度,
+            accept_rate: self.接受次数 as f64 / (step - self.上次报告).max(1) as f64,
+            reheats: 回火次数,
+            operator_probabilities: 变异概率,
+            metric: format!("{}", self.当前结果.0),
+        });
+        self.上次报告 = step;
+        self.接受次数 = 0;
+        if step == 间隔 && self.计算次数 > 0 {
+            let 用时 = self.开始时刻.elapsed().as_micros() as u64 / self.计算次数 as u64;
+            界面.发送(消息::Elapsed { time: 用时 });
+        }
+    }
+
+    /// 每过一定的步数，校验增量计算的结果；失败时记下错误，搜索在下一步结束
+    pub fn 校验<E: 编码器, F>(
+        &mut self,
+        问题: &优化问题<E, O, F>,
+        step: usize,
+        映射: &元素映射,
+        结果: &(O::目标值, f64),
+        进度: f64,
+    ) {
+        let 间隔 = self.设置.verify_interval;
+        if let Err(错误) = 问题.定期校验(间隔, step, 映射, 结果, 进度) {
+            self.校验错误 = Some(错误);
+        }
+    }
+
+    /// 搜索无法继续时记下错误，搜索在下一步结束
+    pub fn 中止(&mut self, 错误: 错误) {
+        self.校验错误 = Some(错误);
+    }
+
+    /// 用新解替换当前解
+    pub fn 接受(&mut self, 映射: &元素映射, 结果: (O::目标值, f64)) {
+        self.当前解.clone_from(映射);
+        self.当前结果 = 结果;
+        self.接受次数 += 1;
+    }
+
+    /// 如果当前解优于目前的最优解，更新最优解并报告，返回是否更新
+    pub fn 更新最优<E: 编码器, F>(
+        &mut self,
+        问题: &优化问题<E, O, F>,
+        进度: f64,
+        界面: &dyn 界面,
+    ) -> bool {
+        if self.当前结果.1 >= self.最优结果.1 {
+            return false;
+        }
+        self.最优结果 = self.当前结果.clone();
+        self.最优解.clone_from(&self.当前解);
+        界面.发送(消息::BetterSolution {
+            metric: format!("{}", self.最优结果.0),
+            config: Box::new(问题.数据.更新配置(&self.最优解)),
+            save: 进度 > self.设置.report_after.unwrap_or(0.9),
+        });
+        true
+    }
+
+    /// 保存并返回最优解
+    pub fn 结束<E: 编码器, F>(self, 问题: &优化问题<E, O, F>, 界面: &dyn 界面) -> 优化结果<O> {
+        界面.发送(消息::BetterSolution {
+            metric: format!("{}", self.最优结果.0),
+            config: Box::new(问题.数据.更新配置(&self.最优解)),
+            save: true,
+        });
+        优化结果 {
+            映射: self.最优解,
+            指标: self.最优结果.0,
+            分数: self.最优结果.1,
+            校验错误: self.校验错误,
+        }
+    }
+}
+
+pub fn 局部搜索<E: 编码器, O: 目标函数, F: 变异>(
+    问题: &mut 优化问题<E, O, F>,
+    参数: &局部搜索参数,
+    准则: &mut impl 接受准则,
+    界面: &dyn 界面,
+) -> 优化结果<O> {
+    let 设置 = 搜索设置 {
+        steps: 参数.steps,
+        time_limit: 参数.time_limit,
+        report_after: 参数.report_after,
+        update_interval: 参数.update_interval.unwrap_or(1000),
+        verify_interval: 参数.verify_interval,
+    };
+    let mut 过程 = 搜索过程::新建(问题, 设置, 界面);
+    let mut last_diff = vec![];
+
+    for step in 0.. {
+        let Some(progress) = 过程.进度(step, 界面) else {
+            break;
+        };
+        let 门槛 = 准则.门槛(过程.当前结果.1, progress);
+        过程.报告(step, 门槛, 0, 问题.操作.变异概率(), 界面);
+        // 生成一个新解，上一个新解被拒绝时编码器中仍是它的结果，需要一并传入它变异的元素
+        let mut next_candidate = 过程.当前解.clone();
+        let diff = 问题.变异(&mut next_candidate, progress);
+        if diff.is_empty() {
+            continue;
+        }
+        let mut total_diff = diff.clone();
+        total_diff.extend(&last_diff);
+        let next_rank = 问题.计算(&next_candidate, &Some(total_diff), progress);
+        过程.计算次数 += 1;
+        过程.校验(问题, step, &next_candidate, &next_rank, progress);
+        let accepted = 准则.接受(过程.当前结果.1, next_rank.1, progress);
+        问题.反馈(&[(accepted, next_rank.1 < 过程.当前结果.1)]);
+        if accepted {
+            过程.接受(&next_candidate, next_rank);
+            last_diff.clear();
+        } else {
+            last_diff = diff;
+        }
+        过程.更新最优(问题, progress, 界面);
+    }
+    过程.结束(问题, 界面)
+}
+
+/// 从初始解出发随机变异若干次，统计分数变化的平均绝对值
+pub fn 平均分数变化<E: 编码器, O: 目标函数, F: 变异>(问题: &mut 优化问题<E, O, F>) -> f64 {
+    const 采样次数: usize = 1000;
+    let 初始映射 = 问题.数据.初始映射.to_vec();
+    let (_, 初始分数) = 问题.计算(&初始映射, &None, 0.0);
+    let mut 总变化 = 0.0;
+    let mut 有效次数 = 0;
+    let mut 上次变异 = vec![];
+    for _ in 0..采样次数 {
+        let mut 映射 = 初始映射.clone();
+        let 变异元素 = 问题.变异(&mut 映射, 0.0);
+        if 变异元素.is_empty() {
+            continue;
+        }
+        // 编码器中是上一个样本的结果，需要一并传入它变异的元素
+        let mut 移动的元素 = 变异元素.clone();
+        移动的元素.extend(&上次变异);
+        let (_, 分数) = 问题.计算(&映射, &Some(移动的元素), 0.0);
+        总变化 += (分数 - 初始分数).abs();
+        有效次数 += 1;
+        上次变异 = 变异元素;
+    }
+    总变化 / 有效次数.max(1) as f64
+}
@@ -1,17 +1,24 @@
 //! 优化方法接口，以及若干优化方法的实现
 //!
 
-use circular_buffer::CircularBuffer;
-use rustc_hash::FxHashMap;
-
 use crate::{
+    config::SolverConfig,
     data::{元素, 元素映射, 数据},
     encoders::编码器,
-    objectives::目标函数,
-    界面,
+    objectives::{metric::比较指标, 目标函数},
+    operators::{变异, 重新放置},
+    界面, 错误,
 };
+use std::num::NonZeroUsize;
 pub mod genetic;
+pub mod great_deluge;
+pub mod large_neighborhood;
+pub mod late_acceptance;
+pub mod local_search;
+pub mod parallel;
+pub mod schedule;
 pub mod simulated_annealing;
+pub mod threshold_accepting;
 
 #[derive(Debug)]
 pub struct 计时器 {
@@ -38,13 +45,19 @@ pub struct 优化结果<O: 目标函数> {
     pub 映射: 元素映射,
     pub 指标: O::目标值,
     pub 分数: f64,
+    /// 校验增量计算的结果失败或者并行评估出错时提前结束优化，这里记录失败的原因
+    pub 校验错误: Option<错误>,
 }
 
+/// 从头构建编码器和目标函数的方法，用于校验增量计算的结果，以及为并行评估创建工作线程
+pub type 重建方法<E, O> = Box<dyn Fn(&数据) -> Result<(E, O), 错误> + Send>;
+
 pub struct 优化问题<E: 编码器, O: 目标函数, F> {
     pub 数据: 数据,
     pub 目标函数: O,
     pub 编码器: E,
     pub 操作: F,
+    pub 重建方法: Option<重建方法<E, O>>,
 }
 
 impl<E: 编码器, O: 目标函数, F> 优化问题<E, O, F> {
@@ -54,21 +67,110 @@ impl<E: 编码器, O: 目标函数, F> 优化问题<E, O, F> {
             目标函数,
             编码器,
             操作,
+            重建方法: None,
+        }
+    }
+
+    /// 提供重建编码器和目标函数的方法，使优化方法可以校验增量计算的结果
+    pub fn 开启校验(mut self, 重建方法: 重建方法<E, O>) -> Self {
+        self.重建方法 = Some(重建方法);
+        self
+    }
+
+    /// 用全新的编码器和目标函数对映射从头计算一遍，与增量计算得到的指标和分数逐项比较
+    ///
+    /// 未提供重建方法时直接返回成功
+    pub fn 校验(
+        &self,
+        映射: &元素映射,
+        增量指标: &O::目标值,
+        增量分数: f64,
+        进度: f64,
+    ) -> Result<(), 错误> {
+        const 容差: f64 = 1e-9;
+        let Some(重建方法) = &self.重建方法 else {
+            return Ok(());
+        };
+        let (mut 编码器, mut 目标函数) = 重建方法(&self.数据)?;
+        let 编码结果 = 编码器.编码(映射, &None);
+        let (指标, 分数) = 目标函数.计算(编码结果, 映射, 进度);
+        let mut 差异列表: Vec<String> = 比较指标(增量指标, &指标, 容差)
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        if (增量分数 - 分数).abs() > 容差 * 分数.abs().max(1.0) {
+            差异列表.push(format!("分数：{} → {}", 增量分数, 分数));
+        }
+        if 差异列表.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "增量计算的结果与从头计算的结果不一致（左为增量计算，右为从头计算）：\n{}",
+            差异列表.join("\n")
+        )
+        .into())
+    }
+
+    /// 每隔一定的步数校验一次，失败时在错误信息中注明步数
+    pub fn 定期校验(
+        &self,
+        间隔: Option<NonZeroUsize>,
+        步数: usize,
+        映射: &元素映射,
+        结果: &(O::目标值, f64),
+        进度: f64,
+    ) -> Result<(), 错误> {
+        let Some(间隔) = 间隔 else {
+            return Ok(());
+        };
+        if !步数.is_multiple_of(间隔.get()) {
+            return Ok(());
         }
+        self.校验(映射, &结果.0, 结果.1, 进度)
+            .map_err(|x| format!("第 {} 步校验失败：{}", 步数, x.message).into())
     }
 
     pub fn 计算(
         &mut self, 映射: &元素映射, 变化: &Option<Vec<元素>>, 进度: f64
-    ) -> (O::目标值, f64, FxHashMap<usize, f64>, FxHashMap<usize, FxHashMap<usize, CircularBuffer<4, f64>>>) {
+    ) -> (O::目标值, f64) {
         let 编码结果 = self.编码器.编码(映射, 变化);
         self.目标函数.计算(编码结果, 映射, 进度)
     }
 }
 
+impl<E: 编码器, O: 目标函数, F: 变异> 优化问题<E, O, F> {
+    /// 变异映射，目标函数开启了冲突引导时把最近一次计算得到的冲突信息传给算子
+    pub fn 变异(&mut self, 映射: &mut 元素映射, 进度: f64) -> Vec<元素> {
+        self.操作.变异(映射, self.目标函数.冲突引导(), 进度)
+    }
+
+    /// 把最近若干次变异的结果告诉算子，每个结果依次为是否接受、是否优于当前解
+    pub fn 反馈(&mut self, 结果: &[(bool, bool)]) {
+        self.操作.反馈(结果);
+    }
+}
+
 pub trait 优化方法<F> {
-    fn 优化<E: 编码器, O: 目标函数>(
+    fn 优化<E: 编码器 + Send, O: 目标函数 + Send>(
         &self,
         问题: &mut 优化问题<E, O, F>,
         界面: &dyn 界面,
     ) -> 优化结果<O>;
 }
+
+/// 按配置中选择的优化方法求解
+impl<F: 变异 + 重新放置> 优化方法<F> for SolverConfig {
+    fn 优化<E: 编码器 + Send, O: 目标函数 + Send>(
+        &self,
+        问题: &mut 优化问题<E, O, F>,
+        界面: &dyn 界面,
+    ) -> 优化结果<O> {
+        match self {
+            SolverConfig::SimulatedAnnealing(方法) => 方法.优化(问题, 界面),
+            SolverConfig::LateAcceptance(方法) => 方法.优化(问题, 界面),
+            SolverConfig::ThresholdAccepting(方法) => 方法.优化(问题, 界面),
+            SolverConfig::GreatDeluge(方法) => 方法.优化(问题, 界面),
+            SolverConfig::LargeNeighborhood(方法) => 方法.优化(问题, 界面),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! 并行评估多个候选解
+//!
+//! 主线程和若干个工作线程各自持有一套编码器和目标函数。每一步由主线程提出若干个候选解，第一个在主线程上计算，其余的分别发给各个工作线程计算。每个线程都记住自己上一次计算的映射，与新的候选解比较得到移动的元素，因此始终走增量计算的路径。
+
+use super::优化问题;
+use crate::{
+    data::{元素, 元素映射},
+    encoders::编码器,
+    objectives::目标函数,
+    错误,
+};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{Scope, ScopedJoinHandle};
+
+type 任务 = (元素映射, f64);
+
+struct 工作线程<'范围, O: 目标函数> {
+    任务发送: Sender<任务>,
+    结果接收: Receiver<(O::目标值, f64)>,
+    句柄: ScopedJoinHandle<'范围, ()>,
+}
+
+pub struct 并行评估器<'范围, O: 目标函数> {
+    工作线程列表: Vec<工作线程<'范围, O>>,
+    主线程映射: Option<元素映射>,
+}
+
+/// 两个映射之间键位不同的元素
+pub fn 映射差异(旧映射: &元素映射, 新映射: &元素映射) -> Vec<元素> {
+    (0..新映射.len())
+        .filter(|x| 旧映射.get(*x) != Some(&新映射[*x]))
+        .collect()
+}
+
+impl<'范围, O: 目标函数 + Send + '范围> 并行评估器<'范围, O>
+where
+    O::目标值: Send,
+{
+    /// 用问题的重建方法为每个工作线程创建一套编码器和目标函数，工作线程在范围结束前一直运行
+    pub fn 新建<E: 编码器 + Send + '范围, F>(
+        范围: &'范围 Scope<'范围, '_>,
+        问题: &优化问题<E, O, F>,
+        工作线程数: usize,
+    ) -> Result<Self, 错误> {
+        let 重建方法 = 问题.重建方法.as_ref().ok_or("并行评估需要提供重建方法")?;
+        let mut 工作线程列表 = vec![];
+        for _ in 0..工作线程数 {
+            let (mut 编码器, mut 目标函数) = 重建方法(&问题.数据)?;
+            let (任务发送, 任务接收) = channel::<任务>();
+            let (结果发送, 结果接收) = channel();
+            let 句柄 = 范围.spawn(move || {
+                let mut 上次映射: Option<元素映射> = None;
+                for (映射, 进度) in 任务接收 {
+                    let 移动的元素 = 上次映射.as_ref().map(|x| 映射差异(x, &映射));
+                    let 编码结果 = 编码器.编码(&映射, &移动的元素);
+                    let 结果 = 目标函数.计算(编码结果, &映射, 进度);
+                    上次映射 = Some(映射);
+                    if 结果发送.send(结果).is_err() {
+                        break;
+                    }
+                }
+            });
+            工作线程列表.push(工作线程 {
+                任务发送,
+                结果接收,
+                句柄,
+            });
+        }
+        Ok(Self {
+            工作线程列表,
+            主线程映射: None,
+        })
+    }
+}
+
+impl<O: 目标函数> 并行评估器<'_, O> {
+    /// 每一步可以同时评估的候选解数目，包括主线程
+    pub fn 候选数(&self) -> usize {
+        self.工作线程列表.len() + 1
+    }
+
+    /// 同时计算各个候选解，按顺序返回它们的指标和分数；候选解的数目不能超过候选数
+    ///
+    /// 有工作线程意外退出时返回它退出的原因
+    pub fn 评估<E: 编码器, F>(
+        &mut self,
+        问题: &mut 优化问题<E, O, F>,
+        候选解列表: &[元素映射],
+        进度: f64,
+    ) -> Result<Vec<(O::目标值, f64)>, 错误> {
+        let Some((主线程候选解, 其他候选解)) = 候选解列表.split_first() else {
+            return Ok(vec![]);
+        };
+        for (工作线程, 候选解) in self.工作线程列表.iter().zip(其他候选解) {
+            if 工作线程.任务发送.send((候选解.clone(), 进度)).is_err() {
+                return Err(self.关闭());
+            }
+        }
+        let 移动的元素 = self.主线程映射.as_ref().map(|x| 映射差异(x, 主线程候选解));
+        let mut 结果列表 = vec![问题.计算(主线程候选解, &移动的元素, 进度)];
+        self.主线程映射 = Some(主线程候选解.clone());
+        for 工作线程 in self.工作线程列表.iter().take(其他候选解.len()) {
+            match 工作线程.结果接收.recv() {
+                Ok(结果) => 结果列表.push(结果),
+                Err(_) => return Err(self.关闭()),
+            }
+        }
+        Ok(结果列表)
+    }
+
+    /// 有工作线程意外退出时，关闭所有工作线程并等它们结束，返回意外退出的原因
+    ///
+    /// 等待过的线程在范围结束时不会再次引发恐慌，所以可以把错误交给调用者处理
+    fn 关闭(&mut self) -> 错误 {
+        let mut 原因列表 = vec![];
+        for 工作线程 { 任务发送, 句柄, .. } in self.工作线程列表.drain(..) {
+            drop(任务发送);
+            if let Err(恐慌) = 句柄.join() {
+                let 原因 = 恐慌
+                    .downcast_ref::<&str>()
+                    .map(|x| x.to_string())
+                    .or_else(|| 恐慌.downcast_ref::<String>().cloned());
+                原因列表.push(原因.unwrap_or("未知原因".to_string()));
+            }
+        }
+        format!("并行评估的工作线程意外退出：{}", 原因列表.join("；")).into()
+    }
+}
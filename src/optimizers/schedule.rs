@@ -0,0 +1,162 @@
+//! 降温方式与回火
+//!
+//! 几何降温和 Lundy–Mees 降温的温度只取决于本轮降温的进度；自适应降温则根据近期的接受率逐步调整温度，使接受率跟随一个从高到低的目标。回火时重新开始一轮降温，在剩余的进度内从最高温降到最低温。
+
+use super::simulated_annealing::降温时间表;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum 降温方式 {
+    /// 每一步的温度都是上一步的温度乘以一个固定倍数
+    Geometric,
+    /// 温度的倒数随步数线性增长，高温阶段降温较快，低温阶段停留较久
+    LundyMees,
+    /// 目标接受率从 initial_acceptance 等比地降到 final_acceptance，温度按近 window 步的平均接受率调整
+    Adaptive {
+        initial_acceptance: Option<f64>,
+        final_acceptance: Option<f64>,
+        window: Option<usize>,
+    },
+}
+
+/// 连续若干步没有找到更优的解时回火
+#[skip_serializing_none]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct 回火配置 {
+    pub patience: usize,
+    /// 回火时是否从目前的最优解重新出发，默认为否
+    pub restart: Option<bool>,
+}
+
+const 默认初始接受率: f64 = 0.5;
+const 默认最终接受率: f64 = 0.01;
+const 默认窗口: usize = 100;
+
+/// 降温过程的状态，温度由整个优化的进度决定，进度可以按步数计算，也可以按用时计算
+pub struct 降温状态 {
+    方式: 降温方式,
+    时间表: 降温时间表,
+    温度: f64,
+    起点: f64,
+    平均接受率: f64,
+    pub 回火次数: usize,
+}
+
+impl 降温状态 {
+    pub fn 新建(方式: 降温方式, 时间表: 降温时间表) -> Self {
+        let mut 状态 = Self {
+            方式,
+            时间表,
+            温度: 时间表.t_max,
+            起点: 0.0,
+            平均接受率: 0.0,
+            回火次数: 0,
+        };
+        状态.平均接受率 = 状态.目标接受率(0.0);
+        状态
+    }
+
+    /// 本轮降温的进度，从 0 到 1
+    fn 本轮进度(&self, 进度: f64) -> f64 {
+        ((进度 - self.起点) / (1.0 - self.起点).max(f64::EPSILON)).clamp(0.0, 1.0)
+    }
+
+    fn 目标接受率(&self, 进度: f64) -> f64 {
+        let 降温方式::Adaptive {
+            initial_acceptance,
+            final_acceptance,
+            ..
+        } = self.方式
+        else {
+            return 0.0;
+        };
+        let 初始 = initial_acceptance.unwrap_or(默认初始接受率);
+        let 最终 = final_acceptance.unwrap_or(默认最终接受率);
+        初始 * (最终 / 初始).powf(self.本轮进度(进度))
+    }
+
+    /// 给定进度下的温度
+    pub fn 温度(&self, 进度: f64) -> f64 {
+        let 降温时间表 { t_max, t_min, .. } = self.时间表;
+        let 进度 = self.本轮进度(进度);
+        match self.方式 {
+            降温方式::Geometric => t_max * (t_min / t_max).powf(进度),
+            降温方式::LundyMees => 1.0 / (1.0 / t_max + (1.0 / t_min - 1.0 / t_max) * 进度),
+            降温方式::Adaptive { .. } => self.温度,
+        }
+    }
+
+    /// 记录一步是否接受了新解，自适应降温据此调整温度
+    pub fn 记录(&mut self, 进度: f64, 接受: bool) {
+        let 降温方式::Adaptive { window, .. } = self.方式 else {
+            return;
+        };
+        let 窗口 = window.unwrap_or(默认窗口).max(1) as f64;
+        self.平均接受率 += (接受 as u8 as f64 - self.平均接受率) / 窗口;
+        // 接受率高于目标时降温，低于目标时升温，平均接受率为零时按一个很小的值计算
+        let 比例 = self.目标接受率(进度) / self.平均接受率.max(1e-6);
+        self.温度 =
+            (self.温度 * 比例.powf(1.0 / 窗口)).clamp(self.时间表.t_min, self.时间表.t_max);
+    }
+
+    /// 从给定的进度开始重新降温
+    pub fn 回火(&mut self, 进度: f64) {
+        self.起点 = 进度;
+        self.温度 = self.时间表.t_max;
+        self.平均接受率 = self.目标接受率(进度);
+        self.回火次数 += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn 回火后重新降温() {
+        let 时间表 = 降温时间表 {
+            t_max: 1.0,
+            t_min: 0.01,
+            steps: 100,
+        };
+        for 方式 in [降温方式::Geometric, 降温方式::LundyMees] {
+            let mut 状态 = 降温状态::新建(方式, 时间表);
+            assert!((状态.温度(0.0) - 1.0).abs() < 1e-12);
+            assert!((状态.温度(1.0) - 0.01).abs() < 1e-12);
+            状态.回火(0.5);
+            assert!((状态.温度(0.5) - 1.0).abs() < 1e-12);
+            assert!((状态.温度(1.0) - 0.01).abs() < 1e-12);
+        }
+        assert!(
+            降温状态::新建(降温方式::LundyMees, 时间表).温度(0.5)
+                < 降温状态::新建(降温方式::Geometric, 时间表).温度(0.5)
+        );
+    }
+
+    #[test]
+    fn 自适应降温跟随接受率() {
+        let 时间表 = 降温时间表 {
+            t_max: 1.0,
+            t_min: 0.001,
+            steps: 1000,
+        };
+        let 方式 = 降温方式::Adaptive {
+            initial_acceptance: Some(0.5),
+            final_acceptance: Some(0.5),
+            window: Some(10),
+        };
+        let mut 状态 = 降温状态::新建(方式, 时间表);
+        for 步数 in 0..100 {
+            状态.记录(步数 as f64 / 1000.0, true);
+        }
+        let 温度 = 状态.温度(0.1);
+        assert!(温度 < 1.0);
+        for 步数 in 100..200 {
+            状态.记录(步数 as f64 / 1000.0, false);
+        }
+        assert!(状态.温度(0.2) > 温度);
+    }
+}
@@ -1,17 +1,24 @@
 //! 退火算法
 
-use super::{优化方法, 优化结果, 优化问题};
+use super::{
+    local_search::{搜索设置, 搜索过程},
+    parallel::{并行评估器, 映射差异},
+    schedule::{回火配置, 降温方式, 降温状态},
+    优化方法, 优化结果, 优化问题,
+};
 use crate::{
     data::元素映射,
     encoders::编码器,
     objectives::目标函数,
     operators::{default::变异配置, 变异},
-    消息, 界面,
+    消息, 界面, 错误,
 };
 use rand::random;
-use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
+use std::num::NonZeroUsize;
+use std::thread::scope;
+use std::time::Duration;
 use web_time::Instant;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -26,96 +33,223 @@ pub struct 降温时间表 {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct 退火方法 {
     pub parameters: Option<降温时间表>,
+    /// 不提供 parameters 时，通过试验找到最高温和最低温后退火的步数，默认为 1000
+    pub steps: Option<usize>,
     pub report_after: Option<f64>,
     pub search_method: Option<变异配置>,
     pub update_interval: Option<usize>,
+    pub verify_interval: Option<NonZeroUsize>,
+    pub parallel_neighborhood: Option<并行邻域>,
+    /// 默认为几何降温
+    pub schedule: Option<降温方式>,
+    pub reheat: Option<回火配置>,
+    /// 优化的时间限制，单位为秒，包括寻找参数的用时，寻找参数最多用去其中的五分之一；设置后按测得的速度换算出总步数，parameters 中的步数不再起作用
+    pub time_limit: Option<f64>,
+}
+
+/// 每一步同时提出并评估多个候选解，从中选出最优的一个再决定是否接受
+///
+/// 需要优化问题提供重建方法，在不支持多线程的平台上退化为每步一个候选解
+#[skip_serializing_none]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct 并行邻域 {
+    /// 每一步的候选解数目，每个候选解占用一个线程
+    pub candidates: usize,
+    /// 默认为 metropolis
+    pub selection: Option<邻域选择>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum 邻域选择 {
+    /// 最优的候选解按当前温度以 Metropolis 准则接受
+    Metropolis,
+    /// 只在最优的候选解有改进时接受，即最速下降
+    BestImprovement,
+}
+
+/// 不提供参数时退火的默认步数
+const 默认步数: usize = 1000;
+
+/// 有时间限制时，寻找参数最多用去的比例
+const 调参比例: f64 = 0.2;
+
 impl<F: 变异> 优化方法<F> for 退火方法 {
-    fn 优化<E: 编码器, O: 目标函数>(
+    fn 优化<E: 编码器 + Send, O: 目标函数 + Send>(
         &self,
         问题: &mut 优化问题<E, O, F>,
         界面: &dyn 界面,
     ) -> 优化结果<O> {
-        let 降温时间表 = self.parameters.unwrap_or_else(|| self.调参(问题, 界面));
-        self.solve_with(问题, 降温时间表, 界面)
+        let 开始时刻 = Instant::now();
+        let 降温时间表 = match self.parameters {
+            Some(x) => x,
+            None => match self.调参(问题, 界面, self.time_limit.map(|x| x * 调参比例)) {
+                Some(x) => x,
+                // 寻找参数时被中断，不再退火，直接保存初始方案
+                None => return self.提前结束(问题, 界面, None),
+            },
+        };
+        // 时间限制从寻找参数之前开始计算，退火只用剩下的时间
+        let time_limit = self.time_limit.map(|x| x - 开始时刻.elapsed().as_secs_f64());
+        self.solve_with(问题, 降温时间表, time_limit, 界面)
     }
 }
 
 impl 退火方法 {
-    /// 退火算法求解的主函数
-    fn solve_with<E: 编码器, O: 目标函数, F: 变异>(
+    /// 不退火，直接保存初始方案；提供了错误时把它记在结果中
+    fn 提前结束<E: 编码器, O: 目标函数, F>(
+        &self,
+        问题: &mut 优化问题<E, O, F>,
+        界面: &dyn 界面,
+        原因: Option<错误>,
+    ) -> 优化结果<O> {
+        let 设置 = 搜索设置 {
+            steps: 0,
+            time_limit: None,
+            report_after: self.report_after,
+            update_interval: self.update_interval.unwrap_or(1000),
+            verify_interval: None,
+        };
+        let mut 过程 = 搜索过程::新建(问题, 设置, 界面);
+        if let Some(原因) = 原因 {
+            过程.中止(原因);
+        }
+        过程.结束(问题, 界面)
+    }
+
+    /// 退火算法求解的主函数，配置了并行邻域时先创建工作线程
+    fn solve_with<E: 编码器 + Send, O: 目标函数 + Send, F: 变异>(
         &self,
         问题: &mut 优化问题<E, O, F>,
         parameters: 降温时间表,
+        time_limit: Option<f64>,
         interface: &dyn 界面,
     ) -> 优化结果<O> {
-        let mut best_candidate = 问题.数据.初始映射.clone();
-        let mut best_rank = 问题.计算(&best_candidate, &None, 0.0);
-        let mut annealing_candidate = best_candidate.clone();
-        let mut annealing_rank = best_rank.clone();
+        let 并行邻域 = match self.parallel_neighborhood {
+            Some(x) if x.candidates > 1 && 问题.重建方法.is_some() => x,
+            _ => return self.solve_chain(问题, parameters, time_limit, interface, None),
+        };
+        if cfg!(target_arch = "wasm32") {
+            return self.solve_chain(问题, parameters, time_limit, interface, None);
+        }
+        let 选择 = 并行邻域.selection.unwrap_or(邻域选择::Metropolis);
+        scope(|范围| match 并行评估器::新建(范围, 问题, 并行邻域.candidates - 1) {
+            Ok(mut 评估器) => {
+                self.solve_chain(问题, parameters, time_limit, interface, Some((&mut 评估器, 选择)))
+            }
+            Err(错误) => {
+                let 错误 = format!("无法创建并行评估的工作线程：{}", 错误.message).into();
+                self.提前结束(问题, interface, Some(错误))
+            }
+        })
+    }
+
+    /// 沿一条马尔可夫链退火，提供了并行评估器时每一步从多个候选解中选优
+    fn solve_chain<E: 编码器, O: 目标函数 + Send, F: 变异>(
+        &self,
+        问题: &mut 优化问题<E, O, F>,
+        parameters: 降温时间表,
+        time_limit: Option<f64>,
+        interface: &dyn 界面,
+        mut 并行: Option<(&mut 并行评估器<'_, O>, 邻域选择)>,
+    ) -> 优化结果<O> {
+        let 设置 = 搜索设置 {
+            steps: parameters.steps,
+            time_limit,
+            report_after: self.report_after,
+            update_interval: self.update_interval.unwrap_or(1000),
+            verify_interval: self.verify_interval,
+        };
+        let mut 过程 = 搜索过程::新建(问题, 设置, interface);
         let mut last_diff = vec![];
-        let 降温时间表 {
-            t_max,
-            t_min,
-            steps,
-        } = parameters;
-        let start = Instant::now();
-        let update_interval = self.update_interval.unwrap_or(1000);
+        let mut schedule = 降温状态::新建(self.schedule.unwrap_or(降温方式::Geometric), parameters);
+        let mut last_improvement = 0;
 
-        for step in 0..steps {
-            // 等比级数降温：每一步的温度都是上一步的温度乘以一个固定倍数
-            let progress = step as f64 / steps as f64;
-            let temperature = t_max * (t_min / t_max).powf(progress);
-            // 每过一定的步数，报告当前状态和计算速度
-            if step % update_interval == 0 || step == steps - 1 {
-                interface.发送(消息::Progress {
-                    steps: step,
-                    temperature,
-                    metric: format!("{}", annealing_rank.0),
-                });
-                if step == update_interval {
-                    let elapsed = start.elapsed().as_micros() as u64 / update_interval as u64;
-                    interface.发送(消息::Elapsed { time: elapsed });
+        for step in 0.. {
+            let Some(progress) = 过程.进度(step, interface) else {
+                break;
+            };
+            let temperature = schedule.温度(progress);
+            过程.报告(step, temperature, schedule.回火次数, 问题.操作.变异概率(), interface);
+            // 生成一个新解，并行时生成多个并取其中最优的
+            let (next_candidate, next_rank, diff, 分数列表, 序号) = if let Some((评估器, _)) =
+                并行.as_mut()
+            {
+                // 没有移动任何元素的候选解不参与评估
+                let mut 候选解列表: Vec<_> = (0..评估器.候选数())
+                    .filter_map(|_| {
+                        let mut 候选解 = 过程.当前解.clone();
+                        let 变异元素 = 问题.变异(&mut 候选解, progress);
+                        (!变异元素.is_empty()).then_some(候选解)
+                    })
+                    .collect();
+                if 候选解列表.is_empty() {
+                    continue;
                 }
-            }
-            // 生成一个新解
-            let mut next_candidate = annealing_candidate.clone();
-            let diff = 问题.操作.变异(&mut next_candidate, &annealing_rank.2, &annealing_rank.3, step as f64 / steps as f64);
-            let mut total_diff = diff.clone();
-            total_diff.extend(&last_diff);
-            let next_rank = 问题.计算(&next_candidate, &Some(total_diff), step as f64 / steps as f64);
-            // 如果满足退火条件，接受新解
-            let improvement = next_rank.1 - annealing_rank.1;
-            if improvement < 0.0 || (random::<f64>() < (-improvement / temperature).exp()) {
-                annealing_candidate.clone_from(&next_candidate);
-                annealing_rank = next_rank;
+                let mut 结果列表 = match 评估器.评估(问题, &候选解列表, progress) {
+                    Ok(x) => x,
+                    Err(错误) => {
+                        过程.中止(错误);
+                        break;
+                    }
+                };
+                let 分数列表: Vec<_> = 结果列表.iter().map(|x| x.1).collect();
+                let 序号 = (0..分数列表.len())
+                    .min_by(|a, b| 分数列表[*a].total_cmp(&分数列表[*b]))
+                    .unwrap();
+                let next_rank = 结果列表.swap_remove(序号);
+                (候选解列表.swap_remove(序号), next_rank, vec![], 分数列表, 序号)
+            } else {
+                let mut next_candidate = 过程.当前解.clone();
+                let diff = 问题.变异(&mut next_candidate, progress);
+                if diff.is_empty() {
+                    continue;
+                }
+                let mut total_diff = diff.clone();
+                total_diff.extend(&last_diff);
+                let next_rank = 问题.计算(&next_candidate, &Some(total_diff), progress);
+                let 分数列表 = vec![next_rank.1];
+                (next_candidate, next_rank, diff, 分数列表, 0)
+            };
+            过程.计算次数 += 分数列表.len();
+            过程.校验(问题, step, &next_candidate, &next_rank, progress);
+            // 如果满足退火条件，接受新解；最速下降时只接受改进
+            let improvement = next_rank.1 - 过程.当前结果.1;
+            let metropolis = !matches!(并行, Some((_, 邻域选择::BestImprovement)));
+            let accepted = improvement < 0.0
+                || (metropolis && random::<f64>() < (-improvement / temperature).exp());
+            schedule.记录(progress, accepted);
+            // 告诉算子每个候选解是否被接受、是否优于当前解
+            let 结果: Vec<_> = 分数列表
+                .iter()
+                .enumerate()
+                .map(|(i, x)| (accepted && i == 序号, *x < 过程.当前结果.1))
+                .collect();
+            问题.反馈(&结果);
+            if accepted {
+                过程.接受(&next_candidate, next_rank);
                 last_diff.clear();
             } else {
                 last_diff = diff;
             }
-            // 如果当前解优于目前的最优解，更新最优解
-            if annealing_rank.1 < best_rank.1 {
-                best_rank = annealing_rank.clone();
-                best_candidate.clone_from(&annealing_candidate);
-                let save = progress > self.report_after.unwrap_or(0.9);
-                interface.发送(消息::BetterSolution {
-                    metric: format!("{}", best_rank.0),
-                    config: 问题.数据.更新配置(&best_candidate),
-                    save,
-                })
+            if 过程.更新最优(问题, progress, interface) {
+                last_improvement = step;
+            }
+            // 长时间没有找到更优的解时回火，可以选择从最优解重新出发
+            if let Some(回火配置 { patience, restart }) = self.reheat {
+                if step - last_improvement >= patience {
+                    schedule.回火(progress);
+                    last_improvement = step;
+                    if restart.unwrap_or(false) {
+                        // 编码器最后计算的是 next_candidate，下一步需要补上它与最优解之间的差异
+                        last_diff = 映射差异(&next_candidate, &过程.最优解);
+                        过程.当前解.clone_from(&过程.最优解);
+                        过程.当前结果 = 过程.最优结果.clone();
+                    }
+                }
             }
         }
-        interface.发送(消息::BetterSolution {
-            metric: format!("{}", best_rank.0),
-            config: 问题.数据.更新配置(&best_candidate),
-            save: true,
-        });
-        优化结果 {
-            映射: best_candidate,
-            指标: best_rank.0.clone(),
-            分数: best_rank.1,
-        }
+        过程.结束(问题, interface)
     }
 
     fn trial_run<E: 编码器, O: 目标函数, F: 变异>(
@@ -126,14 +260,14 @@ impl 退火方法 {
         steps: usize,
     ) -> (元素映射, f64, f64) {
         let mut candidate = from.clone();
-        let (_, mut energy, mut 概率, mut 冲突) = 问题.计算(&candidate, &None, 0.0);
+        let (_, mut energy) = 问题.计算(&candidate, &None, 0.0);
         let mut accepts = 0;
         let mut improves = 0;
 
         for step in 0..steps {
             let mut next_candidate = candidate.clone();
-            let moved_elements = 问题.操作.变异(&mut next_candidate, &概率, &冲突, step as f64 / steps as f64);
-            let (_, next_energy, 下一个概率, 下一个冲突) = 问题.计算(&next_candidate, &Some(moved_elements), step as f64 / steps as f64);
+            let moved_elements = 问题.变异(&mut next_candidate, step as f64 / steps as f64);
+            let (_, next_energy) = 问题.计算(&next_candidate, &Some(moved_elements), step as f64 / steps as f64);
             let energy_delta = next_energy - energy;
             if energy_delta < 0.0 || (-energy_delta / temperature).exp() > random::<f64>() {
                 accepts += 1;
@@ -142,8 +276,6 @@ impl 退火方法 {
                 }
                 candidate = next_candidate;
                 energy = next_energy;
-                概率 = 下一个概率;
-                冲突 = 下一个冲突;
             }
         }
         let accept_rate = accepts as f64 / steps as f64;
@@ -151,12 +283,13 @@ impl 退火方法 {
         (candidate, accept_rate, improve_rate)
     }
 
-    // 不提供参数，通过试验来获得一组参数的办法
+    // 不提供参数，通过试验来获得一组参数的办法；提供了时间限制时，到时就用已经试出的温度；被中断时返回空
     pub fn 调参<E: 编码器, O: 目标函数, F: 变异>(
         &self,
         问题: &mut 优化问题<E, O, F>,
         界面: &dyn 界面,
-    ) -> 降温时间表 {
+        时间限制: Option<f64>,
+    ) -> Option<降温时间表> {
         // 最高温时，接受概率应该至少有这么多
         const HIGH_ACCEPTANCE: f64 = 0.98;
         // 最低温时，改进概率应该至多有这么多
@@ -164,14 +297,16 @@ impl 退火方法 {
         // 搜索温度时用的步进大小
         const MULTIPLIER: f64 = 2.0;
 
+        let 截止时刻 = 时间限制.map(|x| Instant::now() + Duration::from_secs_f64(x.max(0.0)));
+        let 继续 = || !界面.已中断() && 截止时刻.is_none_or(|x| Instant::now() < x);
         let batch = 1000;
-        let mut candidate = 问题.数据.初始映射.clone();
-        let (_, energy, _, _) = 问题.计算(&candidate, &None, 0.0);
+        let mut candidate = 问题.数据.初始映射.to_vec();
+        let (_, energy) = 问题.计算(&candidate, &None, 0.0);
         let mut sum_delta = 0.0;
         for _ in 0..batch {
             let mut next_candidate = candidate.clone();
-            let moved_elements = 问题.操作.变异(&mut next_candidate, &FxHashMap::default(), &FxHashMap::default(), 0.0);
-            let (_, next_energy, _, _) = 问题.计算(&next_candidate, &Some(moved_elements), 0.0);
+            let moved_elements = 问题.操作.变异(&mut next_candidate, None, 0.0);
+            let (_, next_energy) = 问题.计算(&next_candidate, &Some(moved_elements), 0.0);
             sum_delta += (next_energy - energy).abs();
         }
         let initial_guess = sum_delta / batch as f64;
@@ -180,7 +315,7 @@ impl 退火方法 {
         let mut improve_rate;
         (candidate, accept_rate, improve_rate) =
             self.trial_run(问题, candidate, temperature, batch);
-        while accept_rate > HIGH_ACCEPTANCE {
+        while accept_rate > HIGH_ACCEPTANCE && 继续() {
             temperature /= MULTIPLIER;
             (candidate, accept_rate, improve_rate) =
                 self.trial_run(问题, candidate, temperature, batch);
@@ -189,7 +324,7 @@ impl 退火方法 {
                 accept_rate,
             });
         }
-        while accept_rate < HIGH_ACCEPTANCE {
+        while accept_rate < HIGH_ACCEPTANCE && 继续() {
             temperature *= MULTIPLIER;
             (candidate, accept_rate, improve_rate) =
                 self.trial_run(问题, candidate, temperature, batch);
@@ -199,9 +334,9 @@ impl 退火方法 {
             });
         }
         let t_max = temperature;
-        candidate = 问题.数据.初始映射.clone();
+        candidate = 问题.数据.初始映射.to_vec();
         temperature = initial_guess;
-        while improve_rate > LOW_IMPROVEMENT {
+        while improve_rate > LOW_IMPROVEMENT && 继续() {
             temperature /= MULTIPLIER;
             (candidate, _, improve_rate) = self.trial_run(问题, candidate, temperature, batch);
             界面.发送(消息::TrialMin {
@@ -209,12 +344,16 @@ impl 退火方法 {
                 improve_rate,
             });
         }
-        let t_min = temperature;
+        if 界面.已中断() {
+            return None;
+        }
+        // 到时提前结束时最低温可能还没降到最高温以下
+        let t_min = temperature.min(t_max);
         界面.发送(消息::Parameters { t_max, t_min });
-        降温时间表 {
+        Some(降温时间表 {
             t_max,
             t_min,
-            steps: 1000,
-        }
+            steps: self.steps.unwrap_or(默认步数),
+        })
     }
 }
@@ -0,0 +1,78 @@
+//! 门槛接受法
+//!
+//! 新解只要比当前解差得不超过门槛就被接受，门槛随进度线性降到零。与退火相比，接受与否是确定的，也只需要一个初始门槛；不提供时，以初始解附近随机变异的平均分数变化作为初始门槛。
+
+use super::{
+    local_search::{局部搜索, 局部搜索参数, 平均分数变化, 接受准则},
+    优化方法, 优化结果, 优化问题,
+};
+use crate::{
+    encoders::编码器,
+    objectives::目标函数,
+    operators::{default::变异配置, 变异},
+    界面,
+};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::num::NonZeroUsize;
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct 门槛接受方法 {
+    pub threshold: Option<f64>,
+    pub steps: usize,
+    pub report_after: Option<f64>,
+    pub search_method: Option<变异配置>,
+    pub update_interval: Option<usize>,
+    pub verify_interval: Option<NonZeroUsize>,
+    /// 优化的时间限制，单位为秒；设置后按测得的速度换算出总步数，steps 不再起作用
+    pub time_limit: Option<f64>,
+}
+
+struct 门槛接受 {
+    初始门槛: f64,
+}
+
+impl 接受准则 for 门槛接受 {
+    fn 接受(&mut self, 当前分数: f64, 新分数: f64, 进度: f64) -> bool {
+        新分数 - 当前分数 <= self.门槛(当前分数, 进度)
+    }
+
+    fn 门槛(&self, _当前分数: f64, 进度: f64) -> f64 {
+        self.初始门槛 * (1.0 - 进度)
+    }
+}
+
+impl<F: 变异> 优化方法<F> for 门槛接受方法 {
+    fn 优化<E: 编码器 + Send, O: 目标函数 + Send>(
+        &self,
+        问题: &mut 优化问题<E, O, F>,
+        界面: &dyn 界面,
+    ) -> 优化结果<O> {
+        let 参数 = 局部搜索参数 {
+            steps: self.steps,
+            time_limit: self.time_limit,
+            report_after: self.report_after,
+            update_interval: self.update_interval,
+            verify_interval: self.verify_interval,
+        };
+        let mut 准则 = 门槛接受 {
+            初始门槛: self.threshold.unwrap_or_else(|| 平均分数变化(问题)),
+        };
+        局部搜索(问题, &参数, &mut 准则, 界面)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn 门槛线性降到零() {
+        let mut 准则 = 门槛接受 { 初始门槛: 1.0 };
+        assert!(准则.接受(10.0, 10.8, 0.0));
+        assert!(!准则.接受(10.0, 10.8, 0.5));
+        assert!(准则.接受(10.0, 10.0, 1.0));
+        assert!(!准则.接受(10.0, 10.1, 1.0));
+    }
+}
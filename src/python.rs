@@ -0,0 +1,170 @@
+//! Python 绑定：在 Python 中编码、评测和优化方案
+//!
+//! 提供与图形界面的 `Web` 相同的功能，所有参数和返回值都是 Python 中的字典和列表，结构与方案文件、频率序列表等一致。使用 maturin 构建：
+//!
+//! ```bash
+//! maturin develop --release
+//! ```
+
+// pyo3 的宏展开会产生多余的类型转换
+#![allow(clippy::useless_conversion)]
+
+use crate::config::{ObjectiveConfig, OptimizationConfig, 配置};
+use crate::data::数据;
+use crate::encoders::新建编码器;
+use crate::objectives::default::默认目标函数;
+use crate::objectives::目标函数;
+use crate::operators::default::默认操作;
+use crate::optimizers::{优化方法, 优化问题};
+use crate::{图形界面参数, 消息, 界面, 错误};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pythonize::{depythonize, pythonize};
+use std::cell::RefCell;
+
+impl From<错误> for PyErr {
+    fn from(value: 错误) -> Self {
+        PyValueError::new_err(value.message)
+    }
+}
+
+/// 通过 Python 来使用 libchai 的入口
+#[pyclass]
+pub struct Chai {
+    参数: 图形界面参数,
+}
+
+/// 将优化过程中的消息转换为字典，传给 Python 中的回调函数
+///
+/// 回调函数抛出的第一个异常会被保存下来，在优化结束后重新抛出
+struct Python回调<'py> {
+    回调: Bound<'py, PyAny>,
+    异常: RefCell<Option<PyErr>>,
+}
+
+impl 界面 for Python回调<'_> {
+    fn 发送(&self, 消息: 消息) {
+        if self.异常.borrow().is_some() {
+            return;
+        }
+        let 结果 = pythonize(self.回调.py(), &消息)
+            .map_err(PyErr::from)
+            .and_then(|x| self.回调.call1((x,)));
+        if let Err(异常) = 结果 {
+            *self.异常.borrow_mut() = Some(异常);
+        }
+    }
+
+    /// 回调函数抛出过异常，或者收到了 Ctrl-C 等信号时中断优化，异常在优化结束后重新抛出
+    fn 已中断(&self) -> bool {
+        if self.异常.borrow().is_some() {
+            return true;
+        }
+        if let Err(异常) = self.回调.py().check_signals() {
+            *self.异常.borrow_mut() = Some(异常);
+            return true;
+        }
+        false
+    }
+}
+
+impl Chai {
+    /// 准备数据，如果提供了目标函数配置，就用它替换方案中的优化配置
+    fn 准备数据(&self, 目标函数配置: Option<ObjectiveConfig>) -> Result<数据, 错误> {
+        let 图形界面参数 {
+            mut 配置,
+            原始键位分布信息,
+            原始当量信息,
+            词列表,
+        } = self.参数.clone();
+        if let Some(objective) = 目标函数配置 {
+            配置.optimization = Some(OptimizationConfig {
+                objective,
+                constraints: None,
+                metaheuristic: None,
+            });
+        }
+        数据::新建(配置, 词列表, 原始键位分布信息, 原始当量信息)
+    }
+}
+
+#[pymethods]
+impl Chai {
+    /// 传入方案、频率序列表、单键用指分布表和双键速度当量表
+    #[new]
+    fn new(
+        config: &Bound<'_, PyAny>,
+        encodables: &Bound<'_, PyAny>,
+        key_distribution: &Bound<'_, PyAny>,
+        pair_equivalence: &Bound<'_, PyAny>,
+    ) -> PyResult<Self> {
+        let 参数 = 图形界面参数 {
+            配置: depythonize(config)?,
+            词列表: depythonize(encodables)?,
+            原始键位分布信息: depythonize(key_distribution)?,
+            原始当量信息: depythonize(pair_equivalence)?,
+        };
+        Ok(Self { 参数 })
+    }
+
+    /// 返回码表和评测指标，未提供目标函数配置时使用方案中的配置
+    #[pyo3(signature = (objective=None))]
+    fn encode_evaluate(
+        &self,
+        py: Python<'_>,
+        objective: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<(PyObject, PyObject)> {
+        let 目标函数配置 = objective.map(depythonize).transpose()?;
+        let 数据 = self.准备数据(目标函数配置)?;
+        let mut 编码器 = 新建编码器(&数据)?;
+        let mut 编码结果 = 编码器.编码(&数据.初始映射, &None).clone();
+        let 码表 = 数据.生成码表(&编码结果);
+        let mut 目标函数 = 默认目标函数::新建(&数据)?;
+        let (指标, _) = 目标函数.计算(&mut 编码结果, &数据.初始映射, 0.0);
+        Ok((pythonize(py, &码表)?.unbind(), pythonize(py, &指标)?.unbind()))
+    }
+
+    /// 优化方案，优化过程中的消息以字典的形式传给回调函数，返回最优的方案和评测指标
+    fn optimize(&self, py: Python<'_>, callback: &Bound<'_, PyAny>) -> PyResult<(PyObject, PyObject)> {
+        let 数据 = self.准备数据(None)?;
+        let 优化方法配置 = 数据
+            .配置
+            .optimization
+            .as_ref()
+            .and_then(|x| x.metaheuristic.clone())
+            .ok_or(错误::from("方案中没有优化方法配置"))?;
+        let 编码器 = 新建编码器(&数据)?;
+        let 目标函数 = 默认目标函数::新建(&数据)?;
+        let 操作 = 默认操作::新建(&数据)?;
+        let mut 问题 = 优化问题::新建(数据, 编码器, 目标函数, 操作).开启校验(Box::new(|数据| {
+            Ok((新建编码器(数据)?, 默认目标函数::新建(数据)?))
+        }));
+        let 回调 = Python回调 {
+            回调: callback.clone(),
+            异常: RefCell::new(None),
+        };
+        let 优化结果 = 优化方法配置.优化(&mut 问题, &回调);
+        if let Some(异常) = 回调.异常.into_inner() {
+            return Err(异常);
+        }
+        if let Some(错误) = 优化结果.校验错误 {
+            return Err(错误.into());
+        }
+        let 方案 = 问题.数据.更新配置(&优化结果.映射);
+        Ok((pythonize(py, &方案)?.unbind(), pythonize(py, &优化结果.指标)?.unbind()))
+    }
+}
+
+/// 验证方案是否正确，返回补全默认值之后的方案
+#[pyfunction]
+fn validate(py: Python<'_>, config: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+    let 配置: 配置 = depythonize(config)?;
+    Ok(pythonize(py, &配置)?.unbind())
+}
+
+#[pymodule]
+fn chai(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Chai>()?;
+    m.add_function(wrap_pyfunction!(validate, m)?)?;
+    Ok(())
+}
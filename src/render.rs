@@ -0,0 +1,245 @@
+//! 字形渲染：将配置文件中的笔画数据解析为 SVG 图形
+//!
+//! 笔画数据采用汉字自动拆分系统的格式，坐标系为 100 × 100 的方格。每个笔画由起点和一系列相对坐标的绘制命令组成：
+//!
+//! - `h`、`v`：水平、竖直线段，参数为位移
+//! - `c`：三次贝塞尔曲线，参数为两个控制点和终点的位移
+//! - `z`：折线，参数为两个拐点和终点的位移
+//! - `a`：圆，参数为直径，起点位于圆的最上方
+//!
+
+use crate::config::{Data, Draw, Glyph, Stroke};
+use crate::错误;
+
+/// 解析引用之后的笔画，只包含起点和绘制命令
+#[derive(Debug, Clone)]
+pub struct 笔画 {
+    pub feature: String,
+    pub start: (i8, i8),
+    pub curves: Vec<Draw>,
+}
+
+/// 引用链的最大深度，用于防止配置中出现循环引用
+const 最大引用深度: usize = 16;
+
+/// 根据配置中的字库和自定义字形来渲染字根
+pub struct 字形渲染器<'a> {
+    数据: &'a Data,
+}
+
+impl<'a> 字形渲染器<'a> {
+    pub fn 新建(数据: &'a Data) -> Self {
+        Self { 数据 }
+    }
+
+    /// 查找一个部件的字形，自定义字形优先于字库中的字形
+    ///
+    /// 字库中的一个字符可能有多个字形，此时优先选择标签与配置中的 `tags` 相匹配的字形，否则选择第一个
+    pub fn 查找字形(&self, 名称: &str) -> Result<&'a Glyph, 错误> {
+        if let Some(字形) = self
+            .数据
+            .glyph_customization
+            .as_ref()
+            .and_then(|x| x.get(名称))
+        {
+            return Ok(字形);
+        }
+        let 字符 = self
+            .数据
+            .repertoire
+            .as_ref()
+            .and_then(|x| x.get(名称))
+            .ok_or(format!("字库中不存在部件「{名称}」"))?;
+        let 标签列表 = self.数据.tags.as_deref().unwrap_or_default();
+        let 匹配 = 字符.glyphs.iter().find(|字形| {
+            字形
+                .tags()
+                .is_some_and(|x| x.iter().any(|标签| 标签列表.contains(标签)))
+        });
+        匹配
+            .or(字符.glyphs.first())
+            .ok_or(format!("部件「{名称}」没有字形").into())
+    }
+
+    /// 将一个部件的字形解析为笔画列表，对于衍生部件，递归地解析其源部件并替换引用笔画
+    pub fn 解析笔画(&self, 名称: &str) -> Result<Vec<笔画>, 错误> {
+        self.递归解析笔画(名称, 0)
+    }
+
+    fn 递归解析笔画(&self, 名称: &str, 深度: usize) -> Result<Vec<笔画>, 错误> {
+        if 深度 > 最大引用深度 {
+            return Err(format!("部件「{名称}」的引用层级过深，可能存在循环引用").into());
+        }
+        match self.查找字形(名称)? {
+            Glyph::BasicComponent { strokes, .. } => strokes
+                .iter()
+                .map(|x| match x {
+                    Stroke::SVGStroke {
+                        feature,
+                        start,
+                        curveList,
+                    } => Ok(笔画 {
+                        feature: feature.clone(),
+                        start: *start,
+                        curves: curveList.clone(),
+                    }),
+                    Stroke::ReferenceStroke { .. } => {
+                        Err(format!("基本部件「{名称}」中不能包含引用笔画").into())
+                    }
+                })
+                .collect(),
+            Glyph::DerivedComponent {
+                source, strokes, ..
+            } => {
+                let 源笔画列表 = self.递归解析笔画(source, 深度 + 1)?;
+                strokes
+                    .iter()
+                    .map(|x| match x {
+                        Stroke::SVGStroke {
+                            feature,
+                            start,
+                            curveList,
+                        } => Ok(笔画 {
+                            feature: feature.clone(),
+                            start: *start,
+                            curves: curveList.clone(),
+                        }),
+                        Stroke::ReferenceStroke { index, .. } => {
+                            源笔画列表.get(*index).cloned().ok_or(
+                                format!("部件「{名称}」引用了源部件「{source}」中不存在的第 {index} 笔")
+                                    .into(),
+                            )
+                        }
+                    })
+                    .collect()
+            }
+            _ => Err(format!("部件「{名称}」是复合体或拼接部件，没有笔画数据").into()),
+        }
+    }
+
+    /// 将一个部件渲染为 SVG 路径，每个笔画对应一条路径
+    pub fn 生成路径(&self, 名称: &str) -> Result<Vec<String>, 错误> {
+        Ok(self.解析笔画(名称)?.iter().map(笔画::路径).collect())
+    }
+
+    /// 将一个部件渲染为完整的 SVG 文档
+    pub fn 生成图形(&self, 名称: &str) -> Result<String, 错误> {
+        let mut svg = String::from(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 100 100" fill="none" stroke="currentColor" stroke-width="7" stroke-linecap="round" stroke-linejoin="round">"#,
+        );
+        for 路径 in self.生成路径(名称)? {
+            svg.push_str(&format!(r#"<path d="{}"/>"#, 路径));
+        }
+        svg.push_str("</svg>");
+        Ok(svg)
+    }
+}
+
+impl 笔画 {
+    /// 将笔画转换为 SVG 路径字符串，绘制命令均使用相对坐标
+    pub fn 路径(&self) -> String {
+        let (x, y) = self.start;
+        let mut 命令列表 = vec![format!("M{} {}", x, y)];
+        for 命令 in &self.curves {
+            let 命令 = match 命令 {
+                Draw::H { parameterList: [dx] } => format!("h{}", dx),
+                Draw::V { parameterList: [dy] } => format!("v{}", dy),
+                Draw::C { parameterList: p } => {
+                    format!("c{} {} {} {} {} {}", p[0], p[1], p[2], p[3], p[4], p[5])
+                }
+                Draw::Z { parameterList: p } => {
+                    let p = p.map(|x| x as i32);
+                    format!(
+                        "l{} {} l{} {} l{} {}",
+                        p[0],
+                        p[1],
+                        p[2] - p[0],
+                        p[3] - p[1],
+                        p[4] - p[2],
+                        p[5] - p[3]
+                    )
+                }
+                Draw::A { parameterList: [d] } => {
+                    let r = *d as f64 / 2.0;
+                    format!("a{r} {r} 0 1 1 0 {d} a{r} {r} 0 1 1 0 {}", -(*d as i32))
+                }
+            };
+            命令列表.push(命令);
+        }
+        命令列表.join(" ")
+    }
+}
+
+impl Glyph {
+    pub fn tags(&self) -> Option<&Vec<String>> {
+        match self {
+            Glyph::BasicComponent { tags, .. }
+            | Glyph::DerivedComponent { tags, .. }
+            | Glyph::SplicedComponent { tags, .. }
+            | Glyph::Compound { tags, .. } => tags.as_ref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PrimitiveCharacter;
+    use std::collections::HashMap;
+
+    fn 横(y: i8) -> Stroke {
+        Stroke::SVGStroke {
+            feature: "横".to_string(),
+            start: (10, y),
+            curveList: vec![Draw::H {
+                parameterList: [80],
+            }],
+        }
+    }
+
+    #[test]
+    fn 衍生部件引用源部件的笔画() {
+        let 二 = PrimitiveCharacter {
+            unicode: 0x4e8c,
+            tygf: 1,
+            gb2312: 1,
+            name: None,
+            gf0014_id: None,
+            gf3001_id: None,
+            readings: vec![],
+            glyphs: vec![Glyph::BasicComponent {
+                tags: None,
+                strokes: vec![横(20), 横(80)],
+            }],
+            ambiguous: false,
+        };
+        let 衍生 = Glyph::DerivedComponent {
+            tags: None,
+            source: "二".to_string(),
+            strokes: vec![
+                Stroke::ReferenceStroke {
+                    feature: "横".to_string(),
+                    index: 1,
+                },
+                Stroke::SVGStroke {
+                    feature: "竖".to_string(),
+                    start: (50, 10),
+                    curveList: vec![Draw::V {
+                        parameterList: [70],
+                    }],
+                },
+            ],
+        };
+        let 数据 = Data {
+            character_set: None,
+            repertoire: Some(HashMap::from([("二".to_string(), 二)])),
+            glyph_customization: Some(HashMap::from([("\u{e000}".to_string(), 衍生)])),
+            reading_customization: None,
+            tags: None,
+        };
+        let 渲染器 = 字形渲染器::新建(&数据);
+        let 路径 = 渲染器.生成路径("\u{e000}").unwrap();
+        assert_eq!(路径, vec!["M10 80 h80", "M50 10 v70"]);
+        assert!(渲染器.生成路径("三").is_err());
+    }
+}